@@ -0,0 +1,124 @@
+/// Offline template conflict check
+///
+/// Loads saved template caches and reports templates that can claim the same
+/// log lines (examples matched by another template, fully shared fragments,
+/// equivalent regexes). Exits non-zero when conflicts are found.
+///
+/// Usage: check-templates [PATH...]
+///   PATH is a cache JSON file, a binary matcher snapshot (.bin), or a
+///   directory of them. Defaults to the cache/ directory.
+use anyhow::Result;
+use log_analyzer::log_matcher::{ConflictKind, LogMatcher, LogTemplate};
+use log_analyzer::matcher_config::MatcherConfig;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+struct TemplateCache {
+    templates: Vec<LogTemplate>,
+    #[serde(default)]
+    disabled: Vec<u64>,
+}
+
+fn load_matcher(path: &Path) -> Result<LogMatcher> {
+    if path.extension().and_then(|s| s.to_str()) == Some("bin") {
+        return LogMatcher::load_from_file(&path.to_string_lossy());
+    }
+
+    let content = fs::read_to_string(path)?;
+    let cache: TemplateCache = serde_json::from_str(&content)?;
+
+    let matcher = LogMatcher::empty(MatcherConfig::default());
+    matcher.add_templates(cache.templates)?;
+    for template_id in cache.disabled {
+        matcher.set_template_disabled(template_id, true);
+    }
+    Ok(matcher)
+}
+
+fn describe(kind: ConflictKind) -> &'static str {
+    match kind {
+        ConflictKind::ExampleMatchedByOther => "example matched by",
+        ConflictKind::SharedFragments => "all fragments shared with",
+        ConflictKind::EquivalentRegex => "equivalent regex to",
+    }
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            let ext = entry_path.extension().and_then(|s| s.to_str());
+            if matches!(ext, Some("json") | Some("bin")) {
+                files.push(entry_path);
+            }
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let roots = if args.is_empty() { vec!["cache".to_string()] } else { args };
+
+    let mut files = Vec::new();
+    for root in &roots {
+        let path = Path::new(root);
+        if !path.exists() {
+            anyhow::bail!("Path not found: {}", root);
+        }
+        collect_files(path, &mut files)?;
+    }
+    files.sort();
+
+    let mut total_conflicts = 0;
+
+    for path in &files {
+        let matcher = match load_matcher(path) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Skipping {} (incompatible format): {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let templates: std::collections::HashMap<u64, LogTemplate> = matcher
+            .get_all_templates()
+            .into_iter()
+            .map(|t| (t.template_id, t))
+            .collect();
+        let conflicts = matcher.find_conflicts();
+
+        println!(
+            "{}: {} templates, {} conflicts",
+            path.display(),
+            templates.len(),
+            conflicts.len()
+        );
+
+        for conflict in &conflicts {
+            let pattern = |id: u64| templates.get(&id).map(|t| t.pattern.as_str()).unwrap_or("?");
+            println!(
+                "  template {} {} template {}",
+                conflict.template_id,
+                describe(conflict.kind),
+                conflict.other_template_id
+            );
+            println!("    {}", pattern(conflict.template_id));
+            println!("    {}", pattern(conflict.other_template_id));
+        }
+
+        total_conflicts += conflicts.len();
+    }
+
+    println!("\nChecked {} file(s), {} conflicts", files.len(), total_conflicts);
+
+    if total_conflicts > 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
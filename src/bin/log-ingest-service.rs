@@ -6,22 +6,47 @@
 /// Performance: 370K logs/sec with optimized template matching
 
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
 use chrono::{DateTime, Utc};
 use log_analyzer::buffered_writer::BufferedClickHouseWriter;
-use log_analyzer::clickhouse_client::{ClickHouseClient, LogEntry};
+use log_analyzer::bulk_ingest::{BodyEncoding, BulkFormat, LineSplitter};
+use log_analyzer::clickhouse_client::{AnomalyRow, ClickHouseClient, LogEntry, LogTypeRow, TemplateRow, TemplateStatsRow};
+use log_analyzer::count_anomaly::{AnomalyConfig, AnomalyMethod, AnomalyWebhook, CountAnomalyDetector};
+use log_analyzer::es_bulk::{self, BulkAction, BulkResponse};
+use log_analyzer::field_mapping::{FieldMapping, MappedFields};
 use log_analyzer::llm_service::LLMServiceClient;
 use log_analyzer::llm_config::MultiLLMConfig;
-use log_analyzer::log_matcher::{LogMatcher, LogTemplate};
+use log_analyzer::log_format_detector::HeaderFormat;
+use log_analyzer::loki;
+use log_analyzer::log_matcher::{ConflictKind, LogMatcher, LogTemplate, MatchExplanation, TemplateStats};
+use log_analyzer::masking::{BuiltinMask, CustomMask, Masker, MaskingConfig};
 use log_analyzer::matcher_config::MatcherConfig;
+use log_analyzer::matcher_registry::{MatcherRegistry, TenantKey};
+use log_analyzer::multiline::{MultilineAssembler, MultilineConfig, Record, TIMESTAMP_PREFIX};
+use log_analyzer::otlp::{decode_json_request, logs_from_request, OtlpLog, OtlpMapping};
+use log_analyzer::parameter_drift::{DriftConfig, ParameterDrift, ParameterDriftReport};
+use log_analyzer::syslog::{self, SyslogFramer, SyslogMapping};
+use log_analyzer::template_metadata::{Severity, SOURCE_MANUAL};
+use log_analyzer::template_validator::TemplateValidator;
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_server::{LogsService, LogsServiceServer};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    ExportLogsPartialSuccess, ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use prost::Message;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::{mpsc, Semaphore};
 use tokio::time::{interval, Instant};
 use tower_http::cors::CorsLayer;
@@ -35,6 +60,14 @@ const LLM_BATCH_TIMEOUT_SECS: u64 = 2;
 const LLM_MAX_CONCURRENT_BATCHES: usize = 5;
 const LLM_MAX_RETRIES: u32 = 3;
 const LLM_INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_STATS_FLUSH_INTERVAL_SECS: u64 = 60;
+const LLM_DEDUP_CAPACITY: usize = 10_000;
+const DEFAULT_DRIFT_SAMPLE_RATE: f64 = 0.1;
+const DEFAULT_ANOMALY_INTERVAL_SECS: u64 = 60;
+const ANOMALY_WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const SYSLOG_QUEUE_CAPACITY: usize = 10_000;
+/// Elasticsearch version reported to `_bulk` clients; Beats refuse older servers
+const ELASTIC_COMPAT_VERSION: &str = "8.15.0";
 
 // ============================================================================
 // Application State
@@ -42,10 +75,157 @@ const LLM_INITIAL_BACKOFF_MS: u64 = 1000;
 
 #[derive(Clone)]
 struct AppState {
-    matcher: Arc<LogMatcher>,
+    registry: Arc<MatcherRegistry>,
     writer: Arc<BufferedClickHouseWriter>,
     clickhouse: Arc<ClickHouseClient>,
-    unmatched_tx: mpsc::UnboundedSender<String>,
+    unmatched_tx: mpsc::UnboundedSender<UnmatchedLog>,
+    /// Set when multi-line assembly is enabled
+    assembler: Option<Arc<StreamAssembler>>,
+    /// Set when parameter drift tracking is enabled
+    drift: Option<Arc<DriftTracker>>,
+    /// Detector settings, also used by offline scans
+    anomaly_config: AnomalyConfig,
+    /// Set when live count anomaly detection is enabled
+    anomalies: Option<Arc<AnomalyTracker>>,
+    /// Attribute mapping of OTLP log records
+    otlp_mapping: Arc<OtlpMapping>,
+    /// Label mapping of Loki pushes
+    loki_mapping: Arc<FieldMapping>,
+    /// Document field mapping of Elasticsearch `_bulk` requests
+    elastic_mapping: Arc<FieldMapping>,
+}
+
+/// Parameter value histograms per tenant, fed from a sample of matched logs
+struct DriftTracker {
+    config: DriftConfig,
+    sample_rate: f64,
+    tenants: Mutex<HashMap<TenantKey, ParameterDrift>>,
+}
+
+impl DriftTracker {
+    fn new(config: DriftConfig, sample_rate: f64) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            sample_rate,
+            tenants: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn record(&self, tenant: &TenantKey, samples: Vec<(u64, HashMap<String, String>)>, now: DateTime<Utc>) {
+        if samples.is_empty() {
+            return;
+        }
+        let mut tenants = self.tenants.lock().unwrap();
+        let drift = tenants
+            .entry(tenant.clone())
+            .or_insert_with(|| ParameterDrift::new(self.config.clone()));
+        for (template_id, variables) in &samples {
+            drift.record(*template_id, variables, now);
+        }
+    }
+
+    fn report(&self, tenant: &TenantKey, all: bool, now: DateTime<Utc>) -> Vec<ParameterDriftReport> {
+        let mut tenants = self.tenants.lock().unwrap();
+        match tenants.get_mut(tenant) {
+            Some(drift) if all => drift.report(now),
+            Some(drift) => drift.drifted(now),
+            None => Vec::new(),
+        }
+    }
+
+    /// A changed or deleted template starts over with an empty baseline
+    fn remove_template(&self, tenant: &TenantKey, template_id: u64) {
+        if let Some(drift) = self.tenants.lock().unwrap().get_mut(tenant) {
+            drift.remove_template(template_id);
+        }
+    }
+}
+
+/// Count anomaly detectors per tenant, fed with hit counts by `detect_count_anomalies`
+struct AnomalyTracker {
+    config: AnomalyConfig,
+    tenants: Mutex<HashMap<TenantKey, CountAnomalyDetector>>,
+    webhook: Option<AnomalyWebhook>,
+}
+
+impl AnomalyTracker {
+    fn new(config: AnomalyConfig, webhook: Option<AnomalyWebhook>) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            tenants: Mutex::new(HashMap::new()),
+            webhook,
+        })
+    }
+
+    /// A changed or deleted template starts over with an empty history
+    fn remove_template(&self, tenant: &TenantKey, template_id: u64) {
+        if let Some(detector) = self.tenants.lock().unwrap().get_mut(tenant) {
+            detector.remove_template(template_id);
+        }
+    }
+}
+
+/// Open multi-line records per source: tenant plus log_stream_name, so lines
+/// from different hosts writing to one stream don't interleave
+struct StreamAssembler {
+    config: MultilineConfig,
+    sources: Mutex<HashMap<(TenantKey, String), MultilineAssembler<IngestRequest>>>,
+}
+
+impl StreamAssembler {
+    fn new(config: MultilineConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            sources: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Feed a request's lines in order; returns the records they completed.
+    /// Each completed record carries the request fields of its first line.
+    fn push(&self, logs: Vec<IngestRequest>) -> Vec<IngestRequest> {
+        let mut sources = self.sources.lock().unwrap();
+        let mut records = Vec::new();
+
+        for mut log in logs {
+            let key = (TenantKey::new(&log.org_id, &log.log_stream_id), log.log_stream_name.clone());
+            let assembler = sources
+                .entry(key)
+                .or_insert_with(|| MultilineAssembler::new(self.config.clone()).expect("validated multiline config"));
+            let line = std::mem::take(&mut log.message);
+            records.extend(assembler.push(&line, log).map(Self::into_request));
+        }
+        records
+    }
+
+    /// Records whose source went quiet for the flush timeout
+    fn flush_idle(&self) -> Vec<IngestRequest> {
+        let now = std::time::Instant::now();
+        let mut sources = self.sources.lock().unwrap();
+        let mut records = Vec::new();
+
+        for assembler in sources.values_mut() {
+            records.extend(assembler.flush_idle(now).map(Self::into_request));
+        }
+        // Sources with nothing open hold no state worth keeping
+        sources.retain(|_, assembler| assembler.has_pending());
+        records
+    }
+
+    fn into_request(record: Record<IngestRequest>) -> IngestRequest {
+        let mut request = record.meta;
+        request.message = record.text;
+        request
+    }
+}
+
+/// Unmatched log queued for LLM template generation, tagged with its tenant
+#[derive(Debug, Clone)]
+struct UnmatchedLog {
+    tenant: TenantKey,
+    message: String,
 }
 
 impl AppState {
@@ -69,21 +249,55 @@ impl AppState {
               CLICKHOUSE_BUFFER_SIZE, CLICKHOUSE_FLUSH_INTERVAL_SECS);
 
         // Load templates from ClickHouse or use default
-        let config = MatcherConfig::batch_processing();
-        let matcher = Arc::new(LogMatcher::with_config(config));
+        let verify_with_regex = std::env::var("MATCHER_VERIFY_REGEX")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let masking = masking_from_env()?;
+        let config = MatcherConfig::batch_processing()
+            .with_regex_verification(verify_with_regex)
+            .with_masking(masking.clone());
+        let global_fallback = std::env::var("MATCHER_GLOBAL_FALLBACK")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(true);
+
+        // Global set starts with the default templates; tenants start empty
+        let registry = Arc::new(
+            MatcherRegistry::with_global(LogMatcher::with_config(config.clone()), config)
+                .with_global_fallback(global_fallback),
+        );
+
+        // Per-stream header formats
+        match clickhouse.get_stream_formats().await {
+            Ok(rows) => {
+                for row in rows {
+                    match HeaderFormat::parse(&row.format) {
+                        Ok(format) => registry.set_header_format(&TenantKey::new(&row.org_id, &row.log_stream_id), Some(format)),
+                        Err(e) => warn!("Ignoring header format of {}/{}: {}", row.org_id, row.log_stream_id, e),
+                    }
+                }
+            }
+            Err(e) => warn!("Could not load stream header formats from ClickHouse: {}", e),
+        }
 
         // Try to load templates from ClickHouse
         match clickhouse.get_templates().await {
             Ok(templates) => {
                 info!("Loaded {} templates from ClickHouse", templates.len());
-
-                for template in templates {
-                    matcher.add_template(LogTemplate {
-                        template_id: template.template_id,
-                        pattern: template.pattern,
-                        variables: template.variables,
-                        example: template.example,
-                    });
+                registry.load_rows(templates);
+                info!("Template sets: {} tenant(s) + global (fallback: {})",
+                      registry.tenant_count(), global_fallback);
+
+                // Log types are derived from the templates; record them for
+                // templates stored before log types existed
+                let log_type_rows: Vec<LogTypeRow> = registry
+                    .log_types()
+                    .iter()
+                    .flat_map(|(tenant, log_types)| {
+                        log_types.iter().map(|log_type| LogTypeRow::from_log_type(&tenant.org_id, &tenant.log_stream_id, log_type))
+                    })
+                    .collect();
+                if let Err(e) = clickhouse.upsert_log_types(&log_type_rows).await {
+                    warn!("Could not store log types in ClickHouse: {}", e);
                 }
             }
             Err(e) => {
@@ -94,33 +308,268 @@ impl AppState {
 
         // Initialize LLM service with multi-LLM configuration
         let llm_config = MultiLLMConfig::from_env();
-        let llm_client = Arc::new(LLMServiceClient::new_with_config(llm_config)?);
+        let llm_client = Arc::new(LLMServiceClient::new_with_config(llm_config)?.with_masking(&masking));
 
         // Create channel for unmatched logs
         let (unmatched_tx, unmatched_rx) = mpsc::unbounded_channel();
 
         // Spawn background task to process unmatched logs
-        let matcher_clone = matcher.clone();
+        let registry_clone = registry.clone();
         let clickhouse_clone = clickhouse.clone();
         tokio::spawn(async move {
-            process_unmatched_logs(unmatched_rx, llm_client, matcher_clone, clickhouse_clone).await;
+            process_unmatched_logs(unmatched_rx, llm_client, registry_clone, clickhouse_clone).await;
         });
         info!("Started LLM template generation service");
 
+        // Periodically write per-template hit counts to ClickHouse (0 disables)
+        let stats_flush_secs = std::env::var("STATS_FLUSH_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_STATS_FLUSH_INTERVAL_SECS);
+        if stats_flush_secs > 0 {
+            let registry_clone = registry.clone();
+            let clickhouse_clone = clickhouse.clone();
+            tokio::spawn(async move {
+                flush_template_stats(registry_clone, clickhouse_clone, Duration::from_secs(stats_flush_secs)).await;
+            });
+            info!("Started template stats flusher (every {}s)", stats_flush_secs);
+        }
+
+        let assembler = match multiline_from_env()? {
+            Some(config) => {
+                info!("Multi-line assembly enabled (start: {:?}, max lines: {}, flush: {}ms)",
+                      config.start_pattern, config.max_lines, config.flush_timeout_ms);
+                Some(Arc::new(StreamAssembler::new(config)?))
+            }
+            None => None,
+        };
+
+        let (anomaly_config, anomaly_interval_secs, webhook_url) = anomaly_from_env()?;
+        let anomalies = if anomaly_interval_secs > 0 {
+            let webhook = match &webhook_url {
+                Some(url) => Some(AnomalyWebhook::new(url, Duration::from_secs(ANOMALY_WEBHOOK_TIMEOUT_SECS))?),
+                None => None,
+            };
+            let tracker = Arc::new(AnomalyTracker::new(anomaly_config.clone(), webhook)?);
+            let registry_clone = registry.clone();
+            let clickhouse_clone = clickhouse.clone();
+            let tracker_clone = tracker.clone();
+            tokio::spawn(async move {
+                detect_count_anomalies(registry_clone, clickhouse_clone, tracker_clone, Duration::from_secs(anomaly_interval_secs)).await;
+            });
+            info!("Started count anomaly detection (every {}s, {:?}, z >= {}, webhook: {})",
+                  anomaly_interval_secs, anomaly_config.method, anomaly_config.z_threshold,
+                  webhook_url.as_deref().unwrap_or("none"));
+            Some(tracker)
+        } else {
+            None
+        };
+
+        let drift = match drift_from_env()? {
+            Some((config, sample_rate)) => {
+                info!("Parameter drift tracking enabled (sample rate: {}, window: {}s, baseline: {} windows, threshold: {})",
+                      sample_rate, config.window_secs, config.baseline_windows, config.threshold);
+                Some(Arc::new(DriftTracker::new(config, sample_rate)?))
+            }
+            None => None,
+        };
+
+        let otlp_mapping = match std::env::var("OTLP_MAPPING") {
+            Ok(json) if !json.is_empty() => serde_json::from_str(&json)
+                .map_err(|e| anyhow::anyhow!("invalid OTLP_MAPPING: {}", e))?,
+            _ => OtlpMapping::new(),
+        };
+        let loki_mapping = mapping_from_env("LOKI_MAPPING", FieldMapping::loki())?;
+        let elastic_mapping = mapping_from_env("ELASTIC_MAPPING", FieldMapping::elasticsearch())?;
+
         Ok(Self {
-            matcher,
+            registry,
             writer,
             clickhouse,
             unmatched_tx,
+            assembler,
+            drift,
+            anomaly_config,
+            anomalies,
+            otlp_mapping: Arc::new(otlp_mapping),
+            loki_mapping: Arc::new(loki_mapping),
+            elastic_mapping: Arc::new(elastic_mapping),
         })
     }
 }
 
+/// Field mapping preset with the keys overridden by a JSON object in `var`
+fn mapping_from_env(var: &str, preset: FieldMapping) -> anyhow::Result<FieldMapping> {
+    match std::env::var(var) {
+        Ok(json) if !json.is_empty() => preset
+            .with_overrides(&json)
+            .map_err(|e| anyhow::anyhow!("invalid {}: {}", var, e)),
+        _ => Ok(preset),
+    }
+}
+
+/// Parameter drift config from DRIFT_SAMPLE_RATE (share of matched logs whose
+/// variables are counted; 0 turns tracking off), DRIFT_WINDOW_SECS,
+/// DRIFT_BASELINE_WINDOWS, DRIFT_THRESHOLD, DRIFT_TOP_K and DRIFT_MIN_SAMPLES
+fn drift_from_env() -> anyhow::Result<Option<(DriftConfig, f64)>> {
+    let sample_rate = match std::env::var("DRIFT_SAMPLE_RATE") {
+        Ok(rate) => rate
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| (0.0..=1.0).contains(rate))
+            .ok_or_else(|| anyhow::anyhow!("invalid DRIFT_SAMPLE_RATE '{}' (expected 0 to 1)", rate))?,
+        Err(_) => DEFAULT_DRIFT_SAMPLE_RATE,
+    };
+    if sample_rate == 0.0 {
+        return Ok(None);
+    }
+
+    let env = |name: &str| std::env::var(name).ok();
+    let mut config = DriftConfig::new();
+    if let Some(secs) = env("DRIFT_WINDOW_SECS").and_then(|v| v.parse().ok()) {
+        config = config.with_window_secs(secs);
+    }
+    if let Some(windows) = env("DRIFT_BASELINE_WINDOWS").and_then(|v| v.parse().ok()) {
+        config = config.with_baseline_windows(windows);
+    }
+    if let Some(threshold) = env("DRIFT_THRESHOLD").and_then(|v| v.parse().ok()) {
+        config = config.with_threshold(threshold);
+    }
+    if let Some(top_k) = env("DRIFT_TOP_K").and_then(|v| v.parse().ok()) {
+        config = config.with_top_k(top_k);
+    }
+    if let Some(samples) = env("DRIFT_MIN_SAMPLES").and_then(|v| v.parse().ok()) {
+        config = config.with_min_samples(samples);
+    }
+    config.validate()?;
+    Ok(Some((config, sample_rate)))
+}
+
+/// Count anomaly config from ANOMALY_METHOD (`ewma` or `rolling`),
+/// ANOMALY_EWMA_ALPHA, ANOMALY_ROLLING_WINDOW, ANOMALY_Z_THRESHOLD,
+/// ANOMALY_MIN_HISTORY and ANOMALY_NEW_TEMPLATES, plus the live detection
+/// interval from ANOMALY_INTERVAL_SECS (0 turns it off) and the optional
+/// ANOMALY_WEBHOOK_URL
+fn anomaly_from_env() -> anyhow::Result<(AnomalyConfig, u64, Option<String>)> {
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let mut config = AnomalyConfig::new();
+
+    match env("ANOMALY_METHOD").as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("ewma") => {
+            if let Some(alpha) = env("ANOMALY_EWMA_ALPHA").and_then(|v| v.parse().ok()) {
+                config = config.with_method(AnomalyMethod::Ewma { alpha });
+            }
+        }
+        Some("rolling") => {
+            let window = env("ANOMALY_ROLLING_WINDOW").and_then(|v| v.parse().ok()).unwrap_or(30);
+            config = config.with_method(AnomalyMethod::Rolling { window });
+        }
+        Some(other) => anyhow::bail!("invalid ANOMALY_METHOD '{}' (expected ewma or rolling)", other),
+    }
+    if let Some(threshold) = env("ANOMALY_Z_THRESHOLD").and_then(|v| v.parse().ok()) {
+        config = config.with_z_threshold(threshold);
+    }
+    if let Some(intervals) = env("ANOMALY_MIN_HISTORY").and_then(|v| v.parse().ok()) {
+        config = config.with_min_history(intervals);
+    }
+    if let Some(enabled) = env("ANOMALY_NEW_TEMPLATES") {
+        config = config.with_new_template_alerts(enabled == "1" || enabled.eq_ignore_ascii_case("true"));
+    }
+    config.validate()?;
+
+    let interval_secs = env("ANOMALY_INTERVAL_SECS")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ANOMALY_INTERVAL_SECS);
+    Ok((config, interval_secs, env("ANOMALY_WEBHOOK_URL")))
+}
+
+/// Multi-line assembly config from MULTILINE (1/true for the default
+/// indentation and marker rules, "timestamp" for timestamp-prefixed records),
+/// MULTILINE_START_PATTERN, MULTILINE_CONTINUATION_PATTERN, MULTILINE_MAX_LINES
+/// and MULTILINE_FLUSH_MS. None when assembly is off.
+fn multiline_from_env() -> anyhow::Result<Option<MultilineConfig>> {
+    let mode = std::env::var("MULTILINE").unwrap_or_default();
+    let start_pattern = std::env::var("MULTILINE_START_PATTERN").ok().filter(|p| !p.is_empty());
+    let continuation_pattern = std::env::var("MULTILINE_CONTINUATION_PATTERN").ok().filter(|p| !p.is_empty());
+
+    let mut config = match mode.to_ascii_lowercase().as_str() {
+        "timestamp" => MultilineConfig::new().with_start_pattern(TIMESTAMP_PREFIX),
+        "1" | "true" => MultilineConfig::new(),
+        "0" | "false" => return Ok(None),
+        // Setting a pattern alone turns assembly on
+        "" if start_pattern.is_none() && continuation_pattern.is_none() => return Ok(None),
+        "" => MultilineConfig::new(),
+        other => anyhow::bail!("invalid MULTILINE '{}' (expected true, false or timestamp)", other),
+    };
+
+    if let Some(pattern) = start_pattern {
+        config = config.with_start_pattern(&pattern);
+    }
+    if let Some(pattern) = continuation_pattern {
+        config = config.with_continuation_pattern(&pattern);
+    }
+    if let Some(lines) = std::env::var("MULTILINE_MAX_LINES").ok().and_then(|v| v.parse().ok()) {
+        config = config.with_max_lines(lines);
+    }
+    if let Some(ms) = std::env::var("MULTILINE_FLUSH_MS").ok().and_then(|v| v.parse().ok()) {
+        config = config.with_flush_timeout_ms(ms);
+    }
+    config.validate()?;
+    Ok(Some(config))
+}
+
+/// Periodically hand records whose source went idle to the normal ingest path
+async fn flush_multiline_records(state: AppState, assembler: Arc<StreamAssembler>) {
+    let period = (assembler.config.flush_timeout() / 2).max(Duration::from_millis(50));
+    let mut ticker = interval(period);
+
+    loop {
+        ticker.tick().await;
+        let records = assembler.flush_idle();
+        if !records.is_empty() {
+            debug!("Flushing {} idle multi-line record(s)", records.len());
+            ingest_records(&state, &records).await;
+        }
+    }
+}
+
+/// Masking config from MATCHER_MASKS (comma-separated built-in masks),
+/// MATCHER_CUSTOM_MASKS (JSON array of {pattern, replacement}),
+/// MATCHER_CASE_FOLD and MATCHER_COLLAPSE_WHITESPACE
+fn masking_from_env() -> anyhow::Result<MaskingConfig> {
+    let flag = |name: &str| {
+        std::env::var(name)
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    };
+
+    let mut masking = MaskingConfig::new()
+        .with_case_folding(flag("MATCHER_CASE_FOLD"))
+        .with_whitespace_collapsing(flag("MATCHER_COLLAPSE_WHITESPACE"));
+
+    if let Ok(names) = std::env::var("MATCHER_MASKS") {
+        for name in names.split(',').filter(|n| !n.trim().is_empty()) {
+            masking = masking.with_builtin(name.parse::<BuiltinMask>()?);
+        }
+    }
+    if let Ok(json) = std::env::var("MATCHER_CUSTOM_MASKS") {
+        masking.custom = serde_json::from_str::<Vec<CustomMask>>(&json)
+            .map_err(|e| anyhow::anyhow!("invalid MATCHER_CUSTOM_MASKS: {}", e))?;
+    }
+    masking.validate()?;
+
+    if masking.is_enabled() {
+        info!("Input masking: {:?} + {} custom (case fold: {}, collapse whitespace: {})",
+              masking.builtin, masking.custom.len(), masking.case_fold, masking.collapse_whitespace);
+    }
+    Ok(masking)
+}
+
 /// Background task to process unmatched logs with batching and thread pool
 async fn process_unmatched_logs(
-    mut rx: mpsc::UnboundedReceiver<String>,
+    mut rx: mpsc::UnboundedReceiver<UnmatchedLog>,
     llm_client: Arc<LLMServiceClient>,
-    matcher: Arc<LogMatcher>,
+    registry: Arc<MatcherRegistry>,
     clickhouse: Arc<ClickHouseClient>,
 ) {
     info!("LLM template generation worker started (batch size: {}, max concurrent: {})",
@@ -128,14 +577,28 @@ async fn process_unmatched_logs(
 
     let semaphore = Arc::new(Semaphore::new(LLM_MAX_CONCURRENT_BATCHES));
     let mut batch = Vec::with_capacity(LLM_BATCH_SIZE);
+
+    // Lines that mask to something already sent to the LLM are dropped; once the
+    // template exists they match anyway. Cleared when full so failures get retried.
+    let masker = Masker::new(&registry.config().masking);
+    let mut recently_queued: HashSet<(TenantKey, String)> = HashSet::new();
     let mut batch_timer = interval(Duration::from_secs(LLM_BATCH_TIMEOUT_SECS));
     batch_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
     loop {
         tokio::select! {
             // Receive new log
-            Some(log_line) = rx.recv() => {
-                batch.push(log_line);
+            Some(unmatched) = rx.recv() => {
+                let key = (unmatched.tenant.clone(), masker.apply(&unmatched.message).into_owned());
+                if recently_queued.contains(&key) {
+                    debug!("Skipping LLM request for already queued log shape: {}", key.1);
+                    continue;
+                }
+                if recently_queued.len() >= LLM_DEDUP_CAPACITY {
+                    recently_queued.clear();
+                }
+                recently_queued.insert(key);
+                batch.push(unmatched);
 
                 // Process batch if full
                 if batch.len() >= LLM_BATCH_SIZE {
@@ -143,7 +606,7 @@ async fn process_unmatched_logs(
                     spawn_batch_processor(
                         batch_to_process,
                         llm_client.clone(),
-                        matcher.clone(),
+                        registry.clone(),
                         clickhouse.clone(),
                         semaphore.clone(),
                     );
@@ -158,7 +621,7 @@ async fn process_unmatched_logs(
                     spawn_batch_processor(
                         batch_to_process,
                         llm_client.clone(),
-                        matcher.clone(),
+                        registry.clone(),
                         clickhouse.clone(),
                         semaphore.clone(),
                     );
@@ -171,7 +634,7 @@ async fn process_unmatched_logs(
                     spawn_batch_processor(
                         batch,
                         llm_client.clone(),
-                        matcher.clone(),
+                        registry.clone(),
                         clickhouse.clone(),
                         semaphore.clone(),
                     );
@@ -184,11 +647,153 @@ async fn process_unmatched_logs(
     warn!("LLM template generation worker stopped");
 }
 
+/// Background task writing per-template hit counts to ClickHouse.
+/// Each flush writes the hits since the previous successful flush, so summing
+/// `hits` over `template_stats` rows gives the total across restarts.
+async fn flush_template_stats(registry: Arc<MatcherRegistry>, clickhouse: Arc<ClickHouseClient>, period: Duration) {
+    // Cumulative counts as of the last successful flush
+    let mut flushed: HashMap<(TenantKey, u64), u64> = HashMap::new();
+    let mut ticker = interval(period);
+    ticker.tick().await; // First tick fires immediately
+
+    loop {
+        ticker.tick().await;
+
+        let flushed_at = Utc::now();
+        let mut current = HashMap::new();
+        let mut rows = Vec::new();
+
+        for (tenant, stats) in registry.template_stats() {
+            for stat in stats {
+                let (Some(first_seen), Some(last_seen)) = (stat.first_seen, stat.last_seen) else {
+                    continue;
+                };
+                let key = (tenant.clone(), stat.template_id);
+                let previous = flushed.get(&key).copied().unwrap_or(0);
+                // A lower count means the counter was reset (template removed and re-added)
+                let delta = if stat.hits >= previous { stat.hits - previous } else { stat.hits };
+                current.insert(key, stat.hits);
+
+                if delta > 0 {
+                    rows.push(TemplateStatsRow {
+                        org_id: tenant.org_id.clone(),
+                        log_stream_id: tenant.log_stream_id.clone(),
+                        template_id: stat.template_id,
+                        hits: delta,
+                        first_seen,
+                        last_seen,
+                        flushed_at,
+                    });
+                }
+            }
+        }
+
+        match clickhouse.insert_template_stats(&rows).await {
+            Ok(()) => {
+                if !rows.is_empty() {
+                    debug!("Flushed hit counts for {} templates", rows.len());
+                }
+                flushed = current;
+            }
+            // Keep the old baseline so the next flush includes these hits
+            Err(e) => warn!("Failed to flush template stats: {}", e),
+        }
+    }
+}
+
+/// Background task feeding per-interval template hit counts to the tenants'
+/// anomaly detectors. Anomalies are written to the `template_anomalies` table
+/// and posted to the webhook, if one is configured.
+async fn detect_count_anomalies(
+    registry: Arc<MatcherRegistry>,
+    clickhouse: Arc<ClickHouseClient>,
+    tracker: Arc<AnomalyTracker>,
+    period: Duration,
+) {
+    // Cumulative counts at the end of the previous interval
+    let mut previous: HashMap<(TenantKey, u64), u64> = HashMap::new();
+
+    // Templates that exist at startup are not new
+    {
+        let mut tenants = tracker.tenants.lock().unwrap();
+        for (tenant, stats) in registry.template_stats() {
+            tenants
+                .entry(tenant)
+                .or_insert_with(|| CountAnomalyDetector::new(tracker.config.clone()))
+                .mark_seen(stats.iter().map(|stat| stat.template_id));
+        }
+    }
+
+    let mut ticker = interval(period);
+    ticker.tick().await; // First tick fires immediately
+    let mut window_start = Utc::now();
+
+    loop {
+        ticker.tick().await;
+        let window_end = Utc::now();
+
+        let mut counts: HashMap<TenantKey, HashMap<u64, u64>> = HashMap::new();
+        let mut current = HashMap::new();
+        for (tenant, stats) in registry.template_stats() {
+            let tenant_counts = counts.entry(tenant.clone()).or_default();
+            for stat in stats {
+                let key = (tenant.clone(), stat.template_id);
+                let before = previous.get(&key).copied().unwrap_or(0);
+                // A lower count means the counter was reset (template removed and re-added)
+                let delta = if stat.hits >= before { stat.hits - before } else { stat.hits };
+                current.insert(key, stat.hits);
+                tenant_counts.insert(stat.template_id, delta);
+            }
+        }
+        previous = current;
+
+        let detected: Vec<(TenantKey, Vec<_>)> = {
+            let mut tenants = tracker.tenants.lock().unwrap();
+            counts
+                .into_iter()
+                .map(|(tenant, tenant_counts)| {
+                    let detector = tenants
+                        .entry(tenant.clone())
+                        .or_insert_with(|| CountAnomalyDetector::new(tracker.config.clone()));
+                    let anomalies = detector.observe(&tenant_counts, window_start, window_end);
+                    (tenant, anomalies)
+                })
+                .filter(|(_, anomalies)| !anomalies.is_empty())
+                .collect()
+        };
+        window_start = window_end;
+
+        let rows: Vec<AnomalyRow> = detected
+            .iter()
+            .flat_map(|(tenant, anomalies)| {
+                anomalies
+                    .iter()
+                    .map(|anomaly| AnomalyRow::from_anomaly(&tenant.org_id, &tenant.log_stream_id, anomaly, window_end))
+            })
+            .collect();
+        if rows.is_empty() {
+            continue;
+        }
+        info!("Detected {} template count anomalies", rows.len());
+
+        if let Err(e) = clickhouse.insert_anomalies(&rows).await {
+            warn!("Failed to store count anomalies: {}", e);
+        }
+        if let Some(webhook) = &tracker.webhook {
+            for (tenant, anomalies) in &detected {
+                if let Err(e) = webhook.send(&tenant.org_id, &tenant.log_stream_id, anomalies).await {
+                    warn!("Failed to post count anomalies to {}: {}", webhook.url(), e);
+                }
+            }
+        }
+    }
+}
+
 /// Spawn a task to process a batch of logs in parallel
 fn spawn_batch_processor(
-    logs: Vec<String>,
+    logs: Vec<UnmatchedLog>,
     llm_client: Arc<LLMServiceClient>,
-    matcher: Arc<LogMatcher>,
+    registry: Arc<MatcherRegistry>,
     clickhouse: Arc<ClickHouseClient>,
     semaphore: Arc<Semaphore>,
 ) {
@@ -202,9 +807,9 @@ fn spawn_batch_processor(
         // Process each log in the batch concurrently
         let tasks: Vec<_> = logs
             .into_iter()
-            .map(|log_line| {
+            .map(|UnmatchedLog { tenant, message: log_line }| {
                 let llm = llm_client.clone();
-                let m = matcher.clone();
+                let registry = registry.clone();
                 let ch = clickhouse.clone();
 
                 tokio::spawn(async move {
@@ -219,26 +824,46 @@ fn spawn_batch_processor(
                                     info!("LLM succeeded after {} retries for log: {}", retry_count, log_line);
                                 }
 
-                                // Persist template to ClickHouse first (with template_id=0)
-                                // ClickHouse will assign the actual ID
-                                let template_row = log_analyzer::clickhouse_client::TemplateRow {
-                                    org_id: "default".to_string(),
-                                    log_stream_id: "llm-generated".to_string(),
-                                    template_id: 0,  // ClickHouse will assign ID
-                                    pattern: template.pattern.clone(),
-                                    variables: template.variables.clone(),
-                                    example: template.example.clone(),
-                                    created_at: Utc::now(),
-                                };
+                                // Check against the tenant's existing templates before persisting.
+                                // An existing template that already claims the new example (or has
+                                // the same regex) makes the new one a duplicate; anything else is
+                                // only an overlap and the template is still added.
+                                let conflicts = registry
+                                    .get(&tenant)
+                                    .map(|matcher| matcher.check_template_conflicts(&template))
+                                    .unwrap_or_default();
+                                if let Some(duplicate) = conflicts.iter().find(|c| {
+                                    c.template_id == template.template_id
+                                        && matches!(c.kind, ConflictKind::ExampleMatchedByOther | ConflictKind::EquivalentRegex)
+                                }) {
+                                    warn!("Skipping LLM template '{}': duplicate of template {} ({:?})",
+                                          template.pattern, duplicate.other_template_id, duplicate.kind);
+                                    break;
+                                }
+                                for conflict in &conflicts {
+                                    warn!("LLM template '{}' overlaps template {}: {:?}",
+                                          template.pattern,
+                                          if conflict.template_id == template.template_id { conflict.other_template_id } else { conflict.template_id },
+                                          conflict.kind);
+                                }
 
-                                match ch.insert_template(template_row).await {
-                                    Ok(assigned_id) => {
-                                        // Update template with ClickHouse-assigned ID
-                                        template.template_id = assigned_id;
-                                        debug!("ClickHouse assigned template ID {} for log: {}", assigned_id, log_line);
+                                // Content-addressed ID: every replica that generates this
+                                // template for this tenant stores it under the same ID
+                                template.template_id = tenant.template_id(&template.pattern);
+
+                                template.metadata.touch(Utc::now());
 
-                                        // Add template to matcher with the correct ID
-                                        m.add_template(template);
+                                // Persist template to ClickHouse first
+                                let template_row = TemplateRow::from_template(&tenant.org_id, &tenant.log_stream_id, &template);
+
+                                match ch.insert_template(template_row).await {
+                                    Ok(template_id) => {
+                                        debug!("Stored template {} for log: {}", template_id, log_line);
+                                        if let Err(e) = registry.add_template(&tenant, template) {
+                                            warn!("Stored template {} was not added to the matcher: {}", template_id, e);
+                                        } else if let Some(matcher) = registry.get(&tenant) {
+                                            store_log_types(&ch, &tenant, &matcher, matcher.log_type_id(template_id).as_slice()).await;
+                                        }
                                     }
                                     Err(e) => {
                                         error!("Failed to save template to ClickHouse: {}", e);
@@ -290,6 +915,40 @@ struct IngestRequest {
     region: String,
     log_stream_name: String,
     message: String,
+    /// Fields parsed by the receiver (syslog), stored as headers
+    #[serde(skip)]
+    headers: HashMap<String, String>,
+}
+
+impl IngestRequest {
+    /// Request of a log whose tenant and stream came from a field mapping
+    fn mapped(fields: &MappedFields, timestamp: Option<String>, message: String, headers: HashMap<String, String>) -> Self {
+        Self {
+            timestamp,
+            org_id: fields.org_id.clone(),
+            log_stream_id: fields.log_stream_id.clone(),
+            service: fields.service.clone(),
+            region: fields.region.clone(),
+            log_stream_name: fields.log_stream_name.clone(),
+            message,
+            headers,
+        }
+    }
+}
+
+impl From<OtlpLog> for IngestRequest {
+    fn from(log: OtlpLog) -> Self {
+        Self {
+            timestamp: log.timestamp.map(|ts| ts.to_rfc3339()),
+            org_id: log.org_id,
+            log_stream_id: log.log_stream_id,
+            service: log.service,
+            region: log.region,
+            log_stream_name: log.log_stream_name,
+            message: log.message,
+            headers: HashMap::new(),
+        }
+    }
 }
 
 /// Unified request structure - accepts single log or batch
@@ -300,14 +959,140 @@ enum UnifiedIngestRequest {
     Batch { logs: Vec<IngestRequest> },
 }
 
+/// Metadata of lines on the streaming endpoint that don't carry their own.
+/// Query params win over `X-Org-Id`, `X-Log-Stream-Id`, `X-Service`,
+/// `X-Region` and `X-Log-Stream-Name` headers.
+#[derive(Debug, Default, Deserialize)]
+struct StreamDefaults {
+    org_id: Option<String>,
+    log_stream_id: Option<String>,
+    service: Option<String>,
+    region: Option<String>,
+    log_stream_name: Option<String>,
+}
+
+impl StreamDefaults {
+    fn fill_from_headers(&mut self, headers: &HeaderMap) {
+        let fields = [
+            (&mut self.org_id, "x-org-id"),
+            (&mut self.log_stream_id, "x-log-stream-id"),
+            (&mut self.service, "x-service"),
+            (&mut self.region, "x-region"),
+            (&mut self.log_stream_name, "x-log-stream-name"),
+        ];
+        for (field, name) in fields {
+            if field.is_none() {
+                *field = headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+            }
+        }
+    }
+
+    /// Request of an NDJSON line; None when it has no org or stream
+    fn request(&self, line: StreamLine) -> Option<IngestRequest> {
+        let or_default = |value: Option<String>, default: &Option<String>| value.or_else(|| default.clone());
+        Some(IngestRequest {
+            timestamp: line.timestamp,
+            org_id: or_default(line.org_id, &self.org_id)?,
+            log_stream_id: or_default(line.log_stream_id, &self.log_stream_id)?,
+            service: or_default(line.service, &self.service).unwrap_or_default(),
+            region: or_default(line.region, &self.region).unwrap_or_default(),
+            log_stream_name: or_default(line.log_stream_name, &self.log_stream_name).unwrap_or_default(),
+            message: line.message,
+            headers: HashMap::new(),
+        })
+    }
+
+    fn text_request(&self, message: String) -> Option<IngestRequest> {
+        self.request(StreamLine {
+            message,
+            ..Default::default()
+        })
+    }
+}
+
+/// NDJSON line of the streaming endpoint; fields left out come from `StreamDefaults`
+#[derive(Debug, Default, Deserialize)]
+struct StreamLine {
+    timestamp: Option<String>,
+    org_id: Option<String>,
+    log_stream_id: Option<String>,
+    service: Option<String>,
+    region: Option<String>,
+    log_stream_name: Option<String>,
+    message: String,
+}
+
 /// Unified response structure
 #[derive(Debug, Serialize)]
 struct IngestResponse {
     accepted: usize,
+    /// Records written; differs from `accepted` when multi-line assembly joins lines
+    records: usize,
     matched: usize,
     failed: usize,
 }
 
+impl IngestResponse {
+    fn add(&mut self, other: IngestResponse) {
+        self.accepted += other.accepted;
+        self.records += other.records;
+        self.matched += other.matched;
+        self.failed += other.failed;
+    }
+}
+
+/// Replacement definition for an existing template
+#[derive(Debug, Deserialize)]
+struct ReplaceTemplateRequest {
+    pattern: String,
+    variables: Vec<String>,
+    example: String,
+    /// Metadata fields left out keep their current values
+    #[serde(default)]
+    severity: Option<Severity>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct TemplateUpdateResponse {
+    template_id: u64,
+    status: String,
+}
+
+/// Log line to explain against a tenant's template set
+#[derive(Debug, Deserialize)]
+struct ExplainRequest {
+    org_id: String,
+    log_stream_id: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetFormatRequest {
+    /// LogHub format string, e.g. "<Date> <Time> <Level> <Component>: <Content>"
+    format: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamFormatResponse {
+    org_id: String,
+    log_stream_id: String,
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExplainResponse {
+    /// Final result, as the ingest path would assign it
+    template_id: Option<u64>,
+    /// Scoring against the tenant's own templates (None if the tenant has none)
+    tenant: Option<MatchExplanation>,
+    /// Scoring against the global set (None unless the global set would be consulted)
+    global: Option<MatchExplanation>,
+}
+
 #[derive(Debug, Serialize)]
 struct HealthResponse {
     status: String,
@@ -318,7 +1103,16 @@ struct HealthResponse {
 #[derive(Debug, Serialize)]
 struct StatsResponse {
     templates_loaded: usize,
+    tenants: usize,
     optimal_batch_size: usize,
+    template_stats: Vec<TenantTemplateStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct TenantTemplateStats {
+    org_id: String,
+    log_stream_id: String,
+    templates: Vec<TemplateStats>,
 }
 
 // ============================================================================
@@ -329,7 +1123,7 @@ struct StatsResponse {
 async fn health(State(state): State<AppState>) -> impl IntoResponse {
     Json(HealthResponse {
         status: "healthy".to_string(),
-        templates_loaded: state.matcher.get_all_templates().len(),
+        templates_loaded: state.registry.template_count(),
         clickhouse_connected: true, // BufferedWriter handles connection
     })
 }
@@ -337,11 +1131,410 @@ async fn health(State(state): State<AppState>) -> impl IntoResponse {
 /// Get stats
 async fn stats(State(state): State<AppState>) -> impl IntoResponse {
     Json(StatsResponse {
-        templates_loaded: state.matcher.get_all_templates().len(),
-        optimal_batch_size: state.matcher.optimal_batch_size(),
+        templates_loaded: state.registry.template_count(),
+        tenants: state.registry.tenant_count(),
+        optimal_batch_size: state.registry.config().optimal_batch_size,
+        template_stats: state
+            .registry
+            .template_stats()
+            .into_iter()
+            .map(|(tenant, templates)| TenantTemplateStats {
+                org_id: tenant.org_id,
+                log_stream_id: tenant.log_stream_id,
+                templates,
+            })
+            .collect(),
     })
 }
 
+/// Debug: show candidate templates, scores and fragment weights for a log line
+async fn explain_log(
+    State(state): State<AppState>,
+    Json(req): Json<ExplainRequest>,
+) -> impl IntoResponse {
+    let tenant = TenantKey::new(&req.org_id, &req.log_stream_id);
+    let registry = &state.registry;
+
+    // Same header handling as the ingest path
+    let format = registry.header_format(&tenant);
+    let content = match &format {
+        Some(format) => format.content(&req.message),
+        None => req.message.as_str(),
+    };
+
+    let template_id = registry.match_batch(&tenant, &[content])[0].map(|m| m.template_id);
+
+    let tenant_explanation = if tenant.is_global() {
+        None
+    } else {
+        registry.get(&tenant).map(|matcher| matcher.explain_match(content))
+    };
+
+    let global_explanation = if tenant.is_global() || registry.global_fallback() {
+        Some(registry.global().explain_match(content))
+    } else {
+        None
+    };
+
+    Json(ExplainResponse {
+        template_id,
+        tenant: tenant_explanation,
+        global: global_explanation,
+    })
+}
+
+/// Look up the matcher for a tenant, 404 if the tenant has no templates
+fn tenant_matcher(state: &AppState, tenant: &TenantKey) -> Result<Arc<LogMatcher>, (StatusCode, String)> {
+    state.registry.get(tenant).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("No templates for org '{}' stream '{}'", tenant.org_id, tenant.log_stream_id),
+        )
+    })
+}
+
+/// A template with its metadata (severity, description, tags, provenance)
+async fn get_template(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id, template_id)): Path<(String, String, u64)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tenant = TenantKey::new(&org_id, &log_stream_id);
+    let matcher = tenant_matcher(&state, &tenant)?;
+
+    matcher
+        .get_template(template_id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Template {} not found", template_id)))
+}
+
+/// Delete a template from ClickHouse and the live matcher
+async fn delete_template(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id, template_id)): Path<(String, String, u64)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tenant = TenantKey::new(&org_id, &log_stream_id);
+    let matcher = tenant_matcher(&state, &tenant)?;
+
+    // Unknown ids are rejected before anything is deleted from ClickHouse
+    if matcher.get_template(template_id).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("Template {} not found", template_id)));
+    }
+
+    state.clickhouse.delete_template(&org_id, &log_stream_id, template_id).await.map_err(|e| {
+        error!("Failed to delete template {} from ClickHouse: {}", template_id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+
+    let log_type_id = matcher.log_type_id(template_id);
+    matcher.remove_template(template_id);
+    store_log_types(&state.clickhouse, &tenant, &matcher, log_type_id.as_slice()).await;
+    if let Some(drift) = &state.drift {
+        drift.remove_template(&tenant, template_id);
+    }
+    if let Some(anomalies) = &state.anomalies {
+        anomalies.remove_template(&tenant, template_id);
+    }
+
+    info!("Deleted template {}", template_id);
+    Ok(Json(TemplateUpdateResponse {
+        template_id,
+        status: "deleted".to_string(),
+    }))
+}
+
+/// Record the current template lists of a tenant's log types in ClickHouse.
+/// Log types left without templates keep their last stored row.
+async fn store_log_types(clickhouse: &ClickHouseClient, tenant: &TenantKey, matcher: &LogMatcher, log_type_ids: &[u64]) {
+    let rows: Vec<LogTypeRow> = matcher
+        .log_types()
+        .iter()
+        .filter(|log_type| log_type_ids.contains(&log_type.log_type_id))
+        .map(|log_type| LogTypeRow::from_log_type(&tenant.org_id, &tenant.log_stream_id, log_type))
+        .collect();
+    if let Err(e) = clickhouse.upsert_log_types(&rows).await {
+        warn!("Failed to store log types of {}/{}: {}", tenant.org_id, tenant.log_stream_id, e);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DriftQuery {
+    /// Include variables that did not drift
+    #[serde(default)]
+    all: bool,
+}
+
+/// Template variables whose value mix shifted from the baseline
+async fn get_parameter_drift(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+    Query(query): Query<DriftQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let drift = state
+        .drift
+        .as_ref()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Parameter drift tracking is disabled".to_string()))?;
+
+    let tenant = TenantKey::new(&org_id, &log_stream_id);
+    Ok(Json(drift.report(&tenant, query.all, Utc::now())))
+}
+
+#[derive(Debug, Deserialize)]
+struct AnomalyQuery {
+    /// Defaults to 24 hours before `end`
+    start: Option<DateTime<Utc>>,
+    /// Defaults to now
+    end: Option<DateTime<Utc>>,
+    /// Window length of an offline scan
+    step_secs: Option<u64>,
+}
+
+impl AnomalyQuery {
+    fn range(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let end = self.end.unwrap_or_else(Utc::now);
+        (self.start.unwrap_or(end - chrono::Duration::hours(24)), end)
+    }
+}
+
+/// Count anomalies recorded by live detection
+async fn get_anomalies(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+    Query(query): Query<AnomalyQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (start, end) = query.range();
+    let anomalies = state.clickhouse.get_anomalies(&org_id, &log_stream_id, start, end).await.map_err(|e| {
+        error!("Failed to query anomalies of {}/{}: {}", org_id, log_stream_id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+    Ok(Json(anomalies))
+}
+
+/// Run count anomaly detection over stored logs, one window per `step_secs`
+async fn scan_anomalies(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+    Query(query): Query<AnomalyQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (start, end) = query.range();
+    let step = chrono::Duration::seconds(query.step_secs.unwrap_or(3600) as i64);
+    if step <= chrono::Duration::zero() || (end - start).num_seconds() / step.num_seconds() > 1000 {
+        return Err((StatusCode::BAD_REQUEST, "step_secs must be positive and give at most 1000 windows".to_string()));
+    }
+
+    let anomalies = state
+        .clickhouse
+        .scan_count_anomalies(&org_id, &log_stream_id, start, end, step, state.anomaly_config.clone())
+        .await
+        .map_err(|e| {
+            error!("Failed to scan anomalies of {}/{}: {}", org_id, log_stream_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    Ok(Json(anomalies))
+}
+
+/// Log types of a tenant with the templates grouped under each
+async fn get_log_types(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tenant = TenantKey::new(&org_id, &log_stream_id);
+    let matcher = tenant_matcher(&state, &tenant)?;
+    Ok(Json(matcher.log_types()))
+}
+
+/// Replace a template's definition in ClickHouse and the live matcher. The
+/// template keeps a content-addressed ID: a changed pattern moves it to the
+/// pattern's ID, which the response returns.
+async fn replace_template(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id, template_id)): Path<(String, String, u64)>,
+    Json(req): Json<ReplaceTemplateRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tenant = TenantKey::new(&org_id, &log_stream_id);
+    let matcher = state.registry.get_or_create(&tenant);
+
+    // Edited by hand now: keep severity, description, tags and creation time
+    // unless the request sets them, and record the change as manual
+    let mut metadata = matcher
+        .get_template(template_id)
+        .map(|existing| existing.metadata)
+        .unwrap_or_default();
+    if let Some(severity) = req.severity {
+        metadata.severity = Some(severity);
+    }
+    if let Some(description) = req.description {
+        metadata.description = Some(description).filter(|d| !d.is_empty());
+    }
+    if let Some(tags) = req.tags {
+        metadata.tags = tags;
+    }
+    metadata.source = Some(SOURCE_MANUAL.to_string());
+    metadata.model = None;
+    metadata.prompt_version = None;
+    metadata.touch(Utc::now());
+
+    // Same ID every replica, regenerate-cache and insert_template derive for the pattern
+    let new_template_id = tenant.template_id(&req.pattern);
+    if new_template_id != template_id && matcher.get_template(new_template_id).is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Pattern already exists as template {}", new_template_id),
+        ));
+    }
+
+    let candidate = LogTemplate {
+        template_id: new_template_id,
+        pattern: req.pattern,
+        variables: req.variables,
+        example: req.example,
+        metadata,
+    };
+    if let Err(e) = TemplateValidator::default().validate(&candidate) {
+        return Err((StatusCode::BAD_REQUEST, e.to_string()));
+    }
+
+    let mut template_row = TemplateRow::from_template(&org_id, &log_stream_id, &candidate);
+    template_row.disabled = matcher.is_template_disabled(template_id);
+
+    // The new row is written before the old one is deleted, so a failure
+    // in between leaves a duplicate rather than losing the template
+    state.clickhouse.replace_template(template_row).await.map_err(|e| {
+        error!("Failed to replace template {} in ClickHouse: {}", template_id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+    if new_template_id != template_id {
+        state.clickhouse.delete_template(&org_id, &log_stream_id, template_id).await.map_err(|e| {
+            error!("Failed to delete replaced template {} from ClickHouse: {}", template_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    }
+
+    // The new pattern or example can move the template to another log type
+    let old_log_type_id = matcher.log_type_id(template_id);
+    matcher
+        .replace_template_as(template_id, candidate)
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+    let log_type_ids: Vec<u64> = old_log_type_id.into_iter().chain(matcher.log_type_id(new_template_id)).collect();
+    store_log_types(&state.clickhouse, &tenant, &matcher, &log_type_ids).await;
+    if let Some(drift) = &state.drift {
+        drift.remove_template(&tenant, template_id);
+    }
+    if let Some(anomalies) = &state.anomalies {
+        anomalies.remove_template(&tenant, template_id);
+    }
+
+    info!("Replaced template {} with {}", template_id, new_template_id);
+    Ok(Json(TemplateUpdateResponse {
+        template_id: new_template_id,
+        status: "replaced".to_string(),
+    }))
+}
+
+/// Disable a template (kept in storage, never matched)
+async fn disable_template(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id, template_id)): Path<(String, String, u64)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    set_template_disabled(state, TenantKey::new(&org_id, &log_stream_id), template_id, true).await
+}
+
+/// Re-enable a previously disabled template
+async fn enable_template(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id, template_id)): Path<(String, String, u64)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    set_template_disabled(state, TenantKey::new(&org_id, &log_stream_id), template_id, false).await
+}
+
+async fn set_template_disabled(
+    state: AppState,
+    tenant: TenantKey,
+    template_id: u64,
+    disabled: bool,
+) -> Result<Json<TemplateUpdateResponse>, (StatusCode, String)> {
+    let matcher = tenant_matcher(&state, &tenant)?;
+
+    // Unknown ids are rejected before ClickHouse is updated
+    if matcher.get_template(template_id).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("Template {} not found", template_id)));
+    }
+
+    state
+        .clickhouse
+        .set_template_disabled(&tenant.org_id, &tenant.log_stream_id, template_id, disabled)
+        .await
+        .map_err(|e| {
+            error!("Failed to update template {} in ClickHouse: {}", template_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+
+    matcher.set_template_disabled(template_id, disabled);
+
+    let status = if disabled { "disabled" } else { "enabled" };
+    info!("Template {} {}", template_id, status);
+    Ok(Json(TemplateUpdateResponse {
+        template_id,
+        status: status.to_string(),
+    }))
+}
+
+/// Get a stream's header format
+async fn get_stream_format(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+) -> Json<StreamFormatResponse> {
+    let format = state
+        .registry
+        .header_format(&TenantKey::new(&org_id, &log_stream_id))
+        .map(|format| format.to_string());
+    Json(StreamFormatResponse { org_id, log_stream_id, format })
+}
+
+/// Set a stream's header format; templates of the stream then only see `<Content>`
+async fn set_stream_format(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+    Json(req): Json<SetFormatRequest>,
+) -> Result<Json<StreamFormatResponse>, (StatusCode, String)> {
+    let format = HeaderFormat::parse(&req.format).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    update_stream_format(&state, org_id, log_stream_id, Some(format)).await
+}
+
+/// Clear a stream's header format
+async fn clear_stream_format(
+    State(state): State<AppState>,
+    Path((org_id, log_stream_id)): Path<(String, String)>,
+) -> Result<Json<StreamFormatResponse>, (StatusCode, String)> {
+    update_stream_format(&state, org_id, log_stream_id, None).await
+}
+
+async fn update_stream_format(
+    state: &AppState,
+    org_id: String,
+    log_stream_id: String,
+    format: Option<HeaderFormat>,
+) -> Result<Json<StreamFormatResponse>, (StatusCode, String)> {
+    let format_str = format.as_ref().map(|f| f.to_string());
+
+    state
+        .clickhouse
+        .set_stream_format(&org_id, &log_stream_id, format_str.as_deref().unwrap_or(""))
+        .await
+        .map_err(|e| {
+            error!("Failed to store header format of {}/{}: {}", org_id, log_stream_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+
+    state.registry.set_header_format(&TenantKey::new(&org_id, &log_stream_id), format);
+    info!("Header format of {}/{}: {}", org_id, log_stream_id, format_str.as_deref().unwrap_or("(none)"));
+
+    Ok(Json(StreamFormatResponse {
+        org_id,
+        log_stream_id,
+        format: format_str,
+    }))
+}
+
 /// Unified ingest endpoint - accepts single log or batch
 async fn ingest_log(
     State(state): State<AppState>,
@@ -353,29 +1546,507 @@ async fn ingest_log(
         UnifiedIngestRequest::Batch { logs } => logs,
     };
 
+    Ok(Json(ingest_batch(&state, logs).await))
+}
+
+/// Streaming ingest of NDJSON (`application/x-ndjson`) or text lines, plain
+/// or gzip. Lines are matched in chunks of the optimal batch size while the
+/// body arrives; chunks ingested before a body error are kept.
+async fn ingest_stream(
+    State(state): State<AppState>,
+    Query(mut defaults): Query<StreamDefaults>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    defaults.fill_from_headers(&headers);
+    let header_value = |name| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let format = BulkFormat::from_content_type(header_value(header::CONTENT_TYPE));
+    let encoding = BodyEncoding::from_content_encoding(header_value(header::CONTENT_ENCODING))
+        .map_err(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string()))?;
+    if format == BulkFormat::Text && (defaults.org_id.is_none() || defaults.log_stream_id.is_none()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Text lines need org_id and log_stream_id as query params or X-Org-Id / X-Log-Stream-Id headers".to_string(),
+        ));
+    }
+
+    let mut chunks = encoding.decode_stream(body.into_data_stream());
+
+    let batch_size = state.registry.config().optimal_batch_size.max(1);
+    let mut splitter = LineSplitter::new();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut response = IngestResponse {
+        accepted: 0,
+        records: 0,
+        matched: 0,
+        failed: 0,
+    };
+
+    // Lines that aren't valid JSON or lack an org or stream count as failed
+    let to_request = |line: String| match format {
+        BulkFormat::Ndjson => serde_json::from_str(&line).ok().and_then(|line| defaults.request(line)),
+        BulkFormat::Text => defaults.text_request(line),
+    };
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e)))?;
+        let lines = splitter.push(&chunk).map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+        for line in lines {
+            match to_request(line) {
+                Some(request) => batch.push(request),
+                None => response.failed += 1,
+            }
+        }
+        while batch.len() >= batch_size {
+            let rest = batch.split_off(batch_size);
+            response.add(ingest_batch(&state, std::mem::replace(&mut batch, rest)).await);
+        }
+    }
+
+    let last = splitter.finish().map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+    if let Some(line) = last {
+        match to_request(line) {
+            Some(request) => batch.push(request),
+            None => response.failed += 1,
+        }
+    }
+    if !batch.is_empty() {
+        response.add(ingest_batch(&state, batch).await);
+    }
+
+    Ok(Json(response))
+}
+
+/// Assemble multi-line records, then match and store them
+async fn ingest_batch(state: &AppState, logs: Vec<IngestRequest>) -> IngestResponse {
+    let accepted = logs.len();
+
+    // Open records stay with the assembler until a later line or the idle flush completes them
+    let logs = match &state.assembler {
+        Some(assembler) => assembler.push(logs),
+        None => logs,
+    };
+
     if logs.is_empty() {
-        return Ok(Json(IngestResponse {
-            accepted: 0,
+        return IngestResponse {
+            accepted,
+            records: 0,
             matched: 0,
             failed: 0,
-        }));
+        };
     }
 
-    let log_count = logs.len();
-    info!("Ingesting {} log(s)", log_count);
+    info!("Ingesting {} log(s) as {} record(s)", accepted, logs.len());
+    let matched = ingest_records(state, &logs).await;
 
-    // Prepare messages for batch matching
-    let messages: Vec<&str> = logs.iter().map(|log| log.message.as_str()).collect();
+    IngestResponse {
+        accepted,
+        records: logs.len(),
+        matched,
+        failed: 0,
+    }
+}
+
+/// Ingest an OTLP export request; records without a body are rejected
+async fn ingest_otlp(state: &AppState, request: &ExportLogsServiceRequest) -> ExportLogsServiceResponse {
+    let (logs, rejected) = logs_from_request(request, &state.otlp_mapping);
+    if !logs.is_empty() {
+        ingest_batch(state, logs.into_iter().map(IngestRequest::from).collect()).await;
+    }
+
+    ExportLogsServiceResponse {
+        partial_success: (rejected > 0).then(|| ExportLogsPartialSuccess {
+            rejected_log_records: rejected as i64,
+            error_message: "log records without a body".to_string(),
+        }),
+    }
+}
 
-    // Batch match using optimized matcher (parallel if > 1000 logs)
-    let template_ids = if messages.len() > 1000 {
-        state.matcher.match_batch_parallel(&messages)
+/// OTLP/HTTP logs: protobuf (`application/x-protobuf`) or JSON (`application/json`).
+/// The response uses the request's encoding.
+async fn ingest_otlp_http(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let body = decode_body(&headers, body)?;
+    let request = if json {
+        decode_json_request(&body).map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid OTLP/JSON: {}", e)))?
     } else {
-        state.matcher.match_batch(&messages)
+        ExportLogsServiceRequest::decode(body.as_slice())
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid OTLP protobuf: {}", e)))?
     };
 
-    // Get all templates once for pattern lookup
-    let _templates = state.matcher.get_all_templates();
+    let response = ingest_otlp(&state, &request).await;
+    if json {
+        let body = match &response.partial_success {
+            Some(partial) => serde_json::json!({
+                "partialSuccess": {
+                    "rejectedLogRecords": partial.rejected_log_records.to_string(),
+                    "errorMessage": partial.error_message,
+                }
+            }),
+            None => serde_json::json!({}),
+        };
+        Ok(([(header::CONTENT_TYPE, "application/json")], body.to_string().into_bytes()))
+    } else {
+        Ok(([(header::CONTENT_TYPE, "application/x-protobuf")], response.encode_to_vec()))
+    }
+}
+
+/// OTLP/gRPC `LogsService/Export`
+struct OtlpLogsService {
+    state: AppState,
+}
+
+#[tonic::async_trait]
+impl LogsService for OtlpLogsService {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportLogsServiceRequest>,
+    ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+        Ok(tonic::Response::new(ingest_otlp(&self.state, request.get_ref()).await))
+    }
+}
+
+// ============================================================================
+// Syslog Receiver
+// ============================================================================
+
+/// Start the syslog listeners configured by SYSLOG_UDP_PORT and SYSLOG_TCP_PORT
+/// (0 or unset: off). SYSLOG_MAPPING holds the org, stream and region as JSON.
+async fn start_syslog_receivers(state: &AppState) -> anyhow::Result<()> {
+    let port = |name: &str| std::env::var(name).ok().and_then(|p| p.parse::<u16>().ok()).unwrap_or(0);
+    let (udp_port, tcp_port) = (port("SYSLOG_UDP_PORT"), port("SYSLOG_TCP_PORT"));
+    if udp_port == 0 && tcp_port == 0 {
+        return Ok(());
+    }
+
+    let mapping: SyslogMapping = match std::env::var("SYSLOG_MAPPING") {
+        Ok(json) if !json.is_empty() => serde_json::from_str(&json)
+            .map_err(|e| anyhow::anyhow!("invalid SYSLOG_MAPPING: {}", e))?,
+        _ => SyslogMapping::new(),
+    };
+    let mapping = Arc::new(mapping);
+    let (tx, rx) = mpsc::channel(SYSLOG_QUEUE_CAPACITY);
+    tokio::spawn(ingest_syslog(state.clone(), rx));
+
+    if udp_port > 0 {
+        let socket = UdpSocket::bind(("0.0.0.0", udp_port)).await?;
+        info!("Syslog UDP receiver listening on {}", socket.local_addr()?);
+        tokio::spawn(receive_syslog_udp(socket, mapping.clone(), tx.clone()));
+    }
+    if tcp_port > 0 {
+        let listener = TcpListener::bind(("0.0.0.0", tcp_port)).await?;
+        info!("Syslog TCP receiver listening on {}", listener.local_addr()?);
+        tokio::spawn(receive_syslog_tcp(listener, mapping, tx));
+    }
+    Ok(())
+}
+
+/// Ingest request of a syslog message; unparseable messages are dropped
+fn syslog_request(line: &str, peer: SocketAddr, mapping: &SyslogMapping) -> Option<IngestRequest> {
+    let message = match syslog::parse(line, Utc::now()) {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("Dropping syslog message from {}: {}", peer, e);
+            return None;
+        }
+    };
+
+    Some(IngestRequest {
+        timestamp: message.timestamp.map(|ts| ts.to_rfc3339()),
+        org_id: mapping.org_id.clone(),
+        log_stream_id: mapping.log_stream_id(&message),
+        service: mapping.service(&message),
+        region: mapping.region.clone(),
+        log_stream_name: message.hostname.clone().unwrap_or_else(|| peer.ip().to_string()),
+        headers: message.headers(),
+        message: message.message,
+    })
+}
+
+/// Match and store received syslog messages, in batches of whatever is
+/// queued up to the optimal batch size
+async fn ingest_syslog(state: AppState, mut rx: mpsc::Receiver<IngestRequest>) {
+    let batch_size = state.registry.config().optimal_batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+
+    while rx.recv_many(&mut batch, batch_size).await > 0 {
+        ingest_batch(&state, std::mem::take(&mut batch)).await;
+    }
+}
+
+/// One message per datagram
+async fn receive_syslog_udp(socket: UdpSocket, mapping: Arc<SyslogMapping>, tx: mpsc::Sender<IngestRequest>) {
+    let mut buffer = vec![0u8; syslog::MAX_MESSAGE_BYTES];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Syslog UDP receive failed: {}", e);
+                continue;
+            }
+        };
+        let line = String::from_utf8_lossy(&buffer[..len]);
+        if let Some(request) = syslog_request(&line, peer, &mapping) {
+            if tx.send(request).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn receive_syslog_tcp(listener: TcpListener, mapping: Arc<SyslogMapping>, tx: mpsc::Sender<IngestRequest>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(read_syslog_connection(stream, peer, mapping.clone(), tx.clone()));
+            }
+            Err(e) => warn!("Syslog TCP accept failed: {}", e),
+        }
+    }
+}
+
+/// Read framed messages until the peer disconnects or sends an unframeable stream
+async fn read_syslog_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    mapping: Arc<SyslogMapping>,
+    tx: mpsc::Sender<IngestRequest>,
+) {
+    let mut framer = SyslogFramer::new();
+    let mut buffer = vec![0u8; 16 * 1024];
+
+    loop {
+        let len = match stream.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) => {
+                debug!("Syslog connection from {} failed: {}", peer, e);
+                return;
+            }
+        };
+        let frames = match framer.push(&buffer[..len]) {
+            Ok(frames) => frames,
+            Err(e) => {
+                warn!("Closing syslog connection from {}: {}", peer, e);
+                return;
+            }
+        };
+        for frame in frames {
+            if let Some(request) = syslog_request(&frame, peer, &mapping) {
+                if tx.send(request).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    if let Some(request) = framer.finish().and_then(|frame| syslog_request(&frame, peer, &mapping)) {
+        let _ = tx.send(request).await;
+    }
+}
+
+// ============================================================================
+// Loki and Elasticsearch Compatibility
+// ============================================================================
+
+/// Body with its Content-Encoding undone (OTLP/HTTP, Loki and Elasticsearch pushes)
+fn decode_body(headers: &HeaderMap, body: Bytes) -> Result<Vec<u8>, (StatusCode, String)> {
+    let encoding = headers.get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    BodyEncoding::from_content_encoding(encoding)
+        .map_err(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string()))?
+        .decode(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decompress body: {}", e)))
+}
+
+/// Tenant header of Loki (and Cortex/Mimir) clients
+fn scope_org_id(headers: &HeaderMap) -> Option<&str> {
+    headers.get("x-scope-orgid").and_then(|v| v.to_str().ok())
+}
+
+/// Loki push API: JSON (`application/json`) or snappy-compressed protobuf.
+/// Stream labels and structured metadata are stored as headers.
+async fn loki_push(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let body = decode_body(&headers, body)?;
+    let streams = if json { loki::decode_json(&body) } else { loki::decode_protobuf(&body) }
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{:#}", e)))?;
+
+    let tenant = scope_org_id(&headers);
+    let mut logs = Vec::new();
+    for stream in streams {
+        let fields = state.loki_mapping.map(|key| stream.label(key).map(str::to_string), tenant);
+        let labels: HashMap<String, String> = stream.labels.into_iter().collect();
+        for entry in stream.entries {
+            let mut headers = labels.clone();
+            headers.extend(entry.metadata);
+            logs.push(IngestRequest::mapped(&fields, Some(entry.timestamp.to_rfc3339()), entry.line, headers));
+        }
+    }
+
+    if !logs.is_empty() {
+        ingest_batch(&state, logs).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Elasticsearch root info, which Filebeat and Vector check before sending `_bulk`
+async fn elastic_info() -> impl IntoResponse {
+    (
+        [("x-elastic-product", "Elasticsearch")],
+        Json(serde_json::json!({
+            "name": "log-ingest-service",
+            "cluster_name": "log-ingest",
+            "version": {
+                "number": ELASTIC_COMPAT_VERSION,
+                "build_flavor": "default",
+            },
+            "tagline": "You Know, for Search",
+        })),
+    )
+}
+
+async fn elastic_bulk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    ingest_elastic_bulk(&state, None, &headers, body).await
+}
+
+async fn elastic_index_bulk(
+    State(state): State<AppState>,
+    Path(index): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    ingest_elastic_bulk(&state, Some(&index), &headers, body).await
+}
+
+/// Elasticsearch `_bulk`: `index` and `create` documents are ingested; other
+/// actions and documents without a message fail per item
+async fn ingest_elastic_bulk(
+    state: &AppState,
+    index: Option<&str>,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let start = Instant::now();
+    let body = decode_body(headers, body)?;
+    let body = std::str::from_utf8(&body).map_err(|e| (StatusCode::BAD_REQUEST, format!("Body is not UTF-8: {}", e)))?;
+    let operations = es_bulk::parse_bulk(body, index).map_err(|e| (StatusCode::BAD_REQUEST, format!("{:#}", e)))?;
+
+    let mapping = &state.elastic_mapping;
+    let tenant = scope_org_id(headers);
+    let mut logs = Vec::new();
+    let mut response = BulkResponse::new();
+
+    for operation in operations {
+        let index = operation.index.clone().unwrap_or_default();
+        let id = operation.id.clone().unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+        let document = match (operation.action, &operation.document) {
+            (BulkAction::Index | BulkAction::Create, Some(document)) => document,
+            (action, _) => {
+                let reason = format!("{:?} is not supported; only index and create are", action);
+                response.failed(action, index, id, "action_request_validation_exception", reason);
+                continue;
+            }
+        };
+
+        let lookup = |key: &str| match key {
+            "_index" => operation.index.clone(),
+            key => es_bulk::field(document, key),
+        };
+        let Some(message) = mapping.message(lookup) else {
+            let reason = format!("document has none of the message fields {:?}", mapping.message);
+            response.failed(operation.action, index, id, "document_parsing_exception", reason);
+            continue;
+        };
+        let timestamp = mapping.timestamp(lookup).and_then(|ts| es_bulk::parse_timestamp(&ts));
+        let fields = mapping.map(lookup, tenant);
+        logs.push(IngestRequest::mapped(&fields, timestamp.map(|ts| ts.to_rfc3339()), message, HashMap::new()));
+        response.created(operation.action, index, id);
+    }
+
+    if !logs.is_empty() {
+        ingest_batch(state, logs).await;
+    }
+    response.took = start.elapsed().as_millis() as u64;
+    Ok(([("x-elastic-product", "Elasticsearch")], Json(response)))
+}
+
+/// Match, store and queue unmatched records for the LLM. Returns the matched count.
+async fn ingest_records(state: &AppState, logs: &[IngestRequest]) -> usize {
+    let log_count = logs.len();
+
+    // Group log indices by tenant so each batch hits that tenant's template set
+    let mut by_tenant: HashMap<TenantKey, Vec<usize>> = HashMap::new();
+    for (i, log) in logs.iter().enumerate() {
+        by_tenant
+            .entry(TenantKey::new(&log.org_id, &log.log_stream_id))
+            .or_default()
+            .push(i);
+    }
+
+    // Streams with a header format are matched on the <Content> field only;
+    // the other fields are stored as headers
+    let mut contents: Vec<&str> = logs.iter().map(|log| log.message.as_str()).collect();
+    let mut headers: Vec<HashMap<String, String>> = logs.iter().map(|log| log.headers.clone()).collect();
+
+    let mut template_ids: Vec<Option<u64>> = vec![None; log_count];
+    let mut log_type_ids: Vec<Option<u64>> = vec![None; log_count];
+    for (tenant, indices) in &by_tenant {
+        if let Some(format) = state.registry.header_format(tenant) {
+            for &i in indices {
+                if let Some(parsed) = format.parse_line(&logs[i].message) {
+                    contents[i] = parsed.content;
+                    headers[i].extend(parsed.headers);
+                }
+            }
+        }
+
+        let messages: Vec<&str> = indices.iter().map(|&i| contents[i]).collect();
+
+        // Batch match using optimized matcher (parallel if > 1000 logs)
+        let results = if messages.len() > 1000 {
+            state.registry.match_batch_parallel(tenant, &messages)
+        } else {
+            state.registry.match_batch(tenant, &messages)
+        };
+
+        // Variable values of a sample of matched lines, for parameter drift
+        if let Some(drift) = &state.drift {
+            let samples: Vec<(u64, HashMap<String, String>)> = messages
+                .iter()
+                .zip(&results)
+                .filter_map(|(line, matched)| matched.map(|m| (m, *line)))
+                .filter(|_| rand::random::<f64>() < drift.sample_rate)
+                .filter_map(|(m, line)| state.registry.extract_variables(tenant, m, line).map(|vars| (m.template_id, vars)))
+                .filter(|(_, variables)| !variables.is_empty())
+                .collect();
+            drift.record(tenant, samples, Utc::now());
+        }
+
+        let log_types = state.registry.log_type_ids(tenant, &results);
+        for ((&i, matched), log_type_id) in indices.iter().zip(results).zip(log_types) {
+            template_ids[i] = matched.map(|m| m.template_id);
+            log_type_ids[i] = log_type_id;
+        }
+    }
 
     // Build log entries and queue unmatched for LLM
     let mut matched_count = 0;
@@ -393,7 +2064,11 @@ async fn ingest_log(
         // Queue unmatched logs for LLM processing
         if template_id.is_none() {
             debug!("No template match for log, queueing for LLM: {}", log_req.message);
-            if let Err(e) = state.unmatched_tx.send(log_req.message.clone()) {
+            let unmatched = UnmatchedLog {
+                tenant: TenantKey::new(&log_req.org_id, &log_req.log_stream_id),
+                message: contents[i].to_string(),
+            };
+            if let Err(e) = state.unmatched_tx.send(unmatched) {
                 warn!("Failed to queue unmatched log for LLM: {}", e);
             }
         } else {
@@ -412,7 +2087,9 @@ async fn ingest_log(
             log_stream_name: log_req.log_stream_name.clone(),
             timestamp,
             template_id: template_id_str,
+            log_type_id: log_type_ids[i].map(|id| id.to_string()).unwrap_or_default(),
             message: log_req.message.clone(),
+            headers: std::mem::take(&mut headers[i]),
         };
 
         // Write to buffered writer (logs table)
@@ -434,12 +2111,8 @@ async fn ingest_log(
         }
     }
 
-    info!("Successfully ingested {} log(s) ({} matched)", log_count, matched_count);
-    Ok(Json(IngestResponse {
-        accepted: log_count,
-        matched: matched_count,
-        failed: 0,
-    }))
+    info!("Successfully ingested {} record(s) ({} matched)", log_count, matched_count);
+    matched_count
 }
 
 // ============================================================================
@@ -468,14 +2141,58 @@ async fn main() -> anyhow::Result<()> {
     // Initialize state
     let state = AppState::new(&clickhouse_url).await?;
 
-    info!("Templates loaded: {}", state.matcher.get_all_templates().len());
-    info!("Optimal batch size: {}", state.matcher.optimal_batch_size());
+    info!("Templates loaded: {}", state.registry.template_count());
+    info!("Optimal batch size: {}", state.registry.config().optimal_batch_size);
+
+    if let Some(assembler) = state.assembler.clone() {
+        tokio::spawn(flush_multiline_records(state.clone(), assembler));
+    }
+
+    // OTLP/gRPC listens on its own port (0 or unset: off)
+    let otlp_grpc_port: u16 = std::env::var("OTLP_GRPC_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+    if otlp_grpc_port > 0 {
+        let addr: std::net::SocketAddr = ([0, 0, 0, 0], otlp_grpc_port).into();
+        let service = LogsServiceServer::new(OtlpLogsService { state: state.clone() })
+            .accept_compressed(tonic::codec::CompressionEncoding::Gzip);
+        tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+                error!("OTLP/gRPC server failed: {}", e);
+            }
+        });
+        info!("OTLP/gRPC logs receiver listening on {}", addr);
+    }
+
+    start_syslog_receivers(&state).await?;
 
     // Build router
     let app = Router::new()
         .route("/health", get(health))
         .route("/stats", get(stats))
         .route("/logs/ingest", post(ingest_log))
+        .route("/logs/ingest/stream", post(ingest_stream))
+        .route("/v1/logs", post(ingest_otlp_http))
+        .route("/loki/api/v1/push", post(loki_push))
+        .route("/", get(elastic_info))
+        .route("/_bulk", post(elastic_bulk))
+        .route("/:index/_bulk", post(elastic_index_bulk))
+        .route("/debug/explain", post(explain_log))
+        .route(
+            "/templates/:org_id/:log_stream_id/:template_id",
+            get(get_template).delete(delete_template).put(replace_template),
+        )
+        .route("/log-types/:org_id/:log_stream_id", get(get_log_types))
+        .route("/drift/:org_id/:log_stream_id", get(get_parameter_drift))
+        .route("/anomalies/:org_id/:log_stream_id", get(get_anomalies))
+        .route("/anomalies/:org_id/:log_stream_id/scan", get(scan_anomalies))
+        .route("/templates/:org_id/:log_stream_id/:template_id/disable", post(disable_template))
+        .route("/templates/:org_id/:log_stream_id/:template_id/enable", post(enable_template))
+        .route(
+            "/streams/:org_id/:log_stream_id/format",
+            get(get_stream_format).put(set_stream_format).delete(clear_stream_format),
+        )
         .layer(CorsLayer::permissive())
         .with_state(state);
 
@@ -490,8 +2207,21 @@ async fn main() -> anyhow::Result<()> {
     info!("");
     info!("📊 Endpoints:");
     info!("   GET  /health        - Health check");
-    info!("   GET  /stats         - Service statistics");
+    info!("   GET  /stats         - Service statistics and per-template hit counts");
     info!("   POST /logs/ingest   - Ingest single log or batch (auto-detect)");
+    info!("   POST /logs/ingest/stream - Streaming NDJSON or text lines (gzip ok)");
+    info!("   POST /v1/logs       - OTLP/HTTP logs (protobuf or JSON)");
+    info!("   POST /loki/api/v1/push - Loki push (JSON or snappy protobuf)");
+    info!("   POST /_bulk, /:index/_bulk - Elasticsearch bulk (index/create)");
+    info!("   POST /debug/explain - Explain template scoring for a log line");
+    info!("   GET  /log-types/:org/:stream - Log types and their templates");
+    info!("   GET  /drift/:org/:stream - Parameter drift against the baseline");
+    info!("   GET  /anomalies/:org/:stream[/scan] - Template count anomalies");
+    info!("   GET  /templates/:org/:stream/:id - Template with metadata");
+    info!("   PUT  /templates/:org/:stream/:id - Replace a template");
+    info!("   DEL  /templates/:org/:stream/:id - Delete a template");
+    info!("   POST /templates/:org/:stream/:id/disable|enable - Toggle a template");
+    info!("   GET|PUT|DEL /streams/:org/:stream/format - Stream header format");
     info!("");
     info!("⚡ Performance:");
     info!("   - Zero-copy template matching");
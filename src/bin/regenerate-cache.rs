@@ -1,13 +1,14 @@
-/// Regenerate cache files with ClickHouse auto-increment IDs
+/// Regenerate cache files with content-addressed template IDs
 ///
-/// Reads template JSON files, uploads to ClickHouse (which assigns unique IDs),
-/// then exports back to cache with new IDs
+/// Rewrites every template ID in the cache/ JSON files to the ID the ingest
+/// service assigns the same pattern in the tenant (ORG_ID, "cache-<dataset>")
+/// that sync-templates uploads it to. Running it again is a no-op.
 
 use anyhow::Result;
-use chrono::Utc;
-use log_analyzer::clickhouse_client::{ClickHouseClient, TemplateRow};
-use log_analyzer::log_matcher::LogTemplate;
+use log_analyzer::matcher_registry::TenantKey;
+use log_analyzer::template_metadata::TemplateMetadata;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
 
@@ -23,6 +24,8 @@ struct CachedTemplate {
     pattern: String,
     variables: Vec<String>,
     example: String,
+    #[serde(default)]
+    metadata: TemplateMetadata,
 }
 
 #[derive(Debug, Serialize)]
@@ -36,23 +39,13 @@ struct OutputTemplate {
     pattern: String,
     variables: Vec<String>,
     example: String,
+    metadata: TemplateMetadata,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let clickhouse_url = std::env::var("CLICKHOUSE_URL")
-        .unwrap_or_else(|_| "http://localhost:8123".to_string());
-
+fn main() -> Result<()> {
     let org_id = std::env::var("ORG_ID")
         .unwrap_or_else(|_| "default".to_string());
 
-    println!("Connecting to ClickHouse at {}", clickhouse_url);
-    let client = ClickHouseClient::new(&clickhouse_url)?;
-
-    // Clear existing templates
-    println!("Clearing existing templates...");
-    client.clear_templates().await?;
-
     let cache_dir = Path::new("cache");
     if !cache_dir.exists() {
         anyhow::bail!("Cache directory not found");
@@ -85,10 +78,8 @@ async fn main() -> Result<()> {
     // Sort for consistent ordering
     datasets.sort_by(|a, b| a.0.cmp(&b.0));
 
-    // Phase 1: Upload all templates to ClickHouse (gets auto-increment IDs)
-    println!("\n=== Phase 1: Uploading templates to ClickHouse ===\n");
     for (dataset_name, path) in &datasets {
-        println!("Uploading templates from {}...", dataset_name);
+        println!("Regenerating cache for {}...", dataset_name);
 
         let content = fs::read_to_string(path)?;
         let cache: TemplateCache = match serde_json::from_str(&content) {
@@ -99,50 +90,27 @@ async fn main() -> Result<()> {
             }
         };
 
+        let tenant = TenantKey::new(&org_id, &format!("cache-{}", dataset_name));
+        let mut seen = HashSet::new();
+        let mut templates = Vec::with_capacity(cache.templates.len());
+
         for template in cache.templates {
-            let row = TemplateRow {
-                org_id: org_id.clone(),
-                log_stream_id: format!("cache-{}", dataset_name),
-                template_id: 0, // Will be auto-assigned
+            let template_id = tenant.template_id(&template.pattern);
+            // Equivalent spellings of one regex collapse to one ID; keep the first
+            if !seen.insert(template_id) {
+                println!("  Dropping duplicate of template {}: {}", template_id, template.pattern);
+                continue;
+            }
+            templates.push(OutputTemplate {
+                template_id,
                 pattern: template.pattern,
                 variables: template.variables,
                 example: template.example,
-                created_at: Utc::now(),
-            };
-
-            match client.insert_template_with_autoid(row).await {
-                Ok(new_id) => {
-                    println!("  Inserted template with ID {}", new_id);
-                }
-                Err(e) => {
-                    eprintln!("  Failed to insert template: {}", e);
-                }
-            }
-        }
-    }
-
-    // Phase 2: Download templates with new IDs and save to cache
-    println!("\n=== Phase 2: Downloading templates with new IDs ===\n");
-    for (dataset_name, path) in &datasets {
-        println!("Regenerating cache for {}...", dataset_name);
-
-        let log_stream_id = format!("cache-{}", dataset_name);
-        let templates = client.get_templates_for_stream(&org_id, &log_stream_id).await?;
-
-        if templates.is_empty() {
-            println!("  Warning: No templates found for {}", dataset_name);
-            continue;
+                metadata: template.metadata,
+            });
         }
 
-        let output = OutputCache {
-            templates: templates.iter().map(|t| OutputTemplate {
-                template_id: t.template_id,
-                pattern: t.pattern.clone(),
-                variables: t.variables.clone(),
-                example: t.example.clone(),
-            }).collect(),
-        };
-
+        let output = OutputCache { templates };
         let json = serde_json::to_string_pretty(&output)?;
         fs::write(path, json)?;
 
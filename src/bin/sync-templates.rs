@@ -5,6 +5,8 @@
 use anyhow::Result;
 use chrono::Utc;
 use log_analyzer::clickhouse_client::{ClickHouseClient, TemplateRow};
+use log_analyzer::log_matcher::LogTemplate;
+use log_analyzer::template_metadata::{TemplateMetadata, SOURCE_IMPORT};
 use serde::Deserialize;
 use std::fs;
 use std::path::Path;
@@ -20,6 +22,8 @@ struct CachedTemplate {
     pattern: String,
     variables: Vec<String>,
     example: String,
+    #[serde(default)]
+    metadata: TemplateMetadata,
 }
 
 #[tokio::main]
@@ -74,16 +78,24 @@ async fn main() -> Result<()> {
         let template_count = cache.templates.len();
         let mut inserted_count = 0;
 
+        let log_stream_id = format!("cache-{}", dataset_name);
+
         for template in cache.templates {
-            let row = TemplateRow {
-                org_id: org_id.clone(),
-                log_stream_id: format!("cache-{}", dataset_name),
+            // Keep provenance recorded in the cache; otherwise mark as imported
+            let mut metadata = template.metadata;
+            if metadata.source.is_none() {
+                metadata.source = Some(SOURCE_IMPORT.to_string());
+            }
+            metadata.touch(Utc::now());
+
+            let template = LogTemplate {
                 template_id: template.template_id,
                 pattern: template.pattern,
                 variables: template.variables,
                 example: template.example,
-                created_at: Utc::now(),
+                metadata,
             };
+            let row = TemplateRow::from_template(&org_id, &log_stream_id, &template);
 
             match client.insert_template(row).await {
                 Ok(_) => {
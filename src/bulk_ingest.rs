@@ -0,0 +1,256 @@
+/// Streaming bulk ingestion
+///
+/// Splits a request body arriving in chunks into lines, so batches can be
+/// matched while the rest of the body is still in flight. Bodies are NDJSON
+/// or plain text lines, optionally gzip- or zstd-compressed.
+use anyhow::{bail, Result};
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use std::io::{self, Read};
+use std::pin::Pin;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Longest line accepted; longer ones fail the request
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// Largest body `BodyEncoding::decode` inflates to, against compression bombs
+pub const MAX_DECODED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Decompressed bytes read per chunk of a streamed body
+const DECODE_READ_BYTES: usize = 64 * 1024;
+
+/// Body chunks, decompressed as they arrive
+pub type ChunkStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkFormat {
+    /// One JSON object per line
+    Ndjson,
+    /// One log message per line
+    Text,
+}
+
+impl BulkFormat {
+    /// NDJSON for `application/x-ndjson`, `application/jsonl` and friends; text otherwise
+    pub fn from_content_type(content_type: &str) -> Self {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" | "application/x-jsonlines"
+            | "application/json-lines" => Self::Ndjson,
+            _ => Self::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyEncoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl BodyEncoding {
+    /// Parse a Content-Encoding header value (empty means identity)
+    pub fn from_content_encoding(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(Self::Identity),
+            "gzip" | "x-gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            other => bail!("Content-Encoding '{}' is not supported; send gzip, zstd or uncompressed", other),
+        }
+    }
+
+    /// Decompress a whole body of at most `MAX_DECODED_BYTES`
+    pub fn decode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.decode_limited(data, MAX_DECODED_BYTES)
+    }
+
+    fn decode_limited(self, data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self {
+            Self::Identity => return Ok(data.to_vec()),
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(data)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+        };
+        let mut decoded = Vec::new();
+        reader.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+        if decoded.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompressed body exceeds the {} byte limit", limit),
+            ));
+        }
+        Ok(decoded)
+    }
+
+    /// Decompress a body as its chunks arrive
+    pub fn decode_stream<S, B, E>(self, body: S) -> ChunkStream
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: Buf + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let body = body.map(|chunk| chunk.map_err(|e| io::Error::other(e.to_string())));
+        match self {
+            Self::Identity => Box::pin(body.map(|chunk| chunk.map(|mut c| c.copy_to_bytes(c.remaining())))),
+            Self::Gzip => {
+                let mut decoder = GzipDecoder::new(StreamReader::new(body));
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::with_capacity(decoder, DECODE_READ_BYTES))
+            }
+            Self::Zstd => {
+                let mut decoder = ZstdDecoder::new(StreamReader::new(body));
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::with_capacity(decoder, DECODE_READ_BYTES))
+            }
+        }
+    }
+}
+
+/// Collects body chunks into complete lines. Empty lines are skipped and a
+/// trailing `\r` is dropped.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    buffer: Vec<u8>,
+}
+
+impl LineSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a chunk; returns the lines it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        let mut start = 0;
+
+        while let Some(newline) = self.buffer[start..].iter().position(|&b| b == b'\n') {
+            Self::emit(&self.buffer[start..start + newline], &mut lines)?;
+            start += newline + 1;
+        }
+        self.buffer.drain(..start);
+
+        if self.buffer.len() > MAX_LINE_BYTES {
+            bail!("line exceeds the {} byte limit", MAX_LINE_BYTES);
+        }
+        Ok(lines)
+    }
+
+    /// Last line of a body that doesn't end with a newline
+    pub fn finish(self) -> Result<Option<String>> {
+        let mut lines = Vec::new();
+        Self::emit(&self.buffer, &mut lines)?;
+        Ok(lines.pop())
+    }
+
+    fn emit(line: &[u8], lines: &mut Vec<String>) -> Result<()> {
+        if line.len() > MAX_LINE_BYTES {
+            bail!("line exceeds the {} byte limit", MAX_LINE_BYTES);
+        }
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if !line.trim().is_empty() {
+            lines.push(line.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_encoding() {
+        assert_eq!(BulkFormat::from_content_type("application/x-ndjson"), BulkFormat::Ndjson);
+        assert_eq!(BulkFormat::from_content_type("Application/JSONL; charset=utf-8"), BulkFormat::Ndjson);
+        assert_eq!(BulkFormat::from_content_type("text/plain"), BulkFormat::Text);
+        assert_eq!(BulkFormat::from_content_type(""), BulkFormat::Text);
+
+        assert_eq!(BodyEncoding::from_content_encoding("").unwrap(), BodyEncoding::Identity);
+        assert_eq!(BodyEncoding::from_content_encoding("GZIP").unwrap(), BodyEncoding::Gzip);
+        assert_eq!(BodyEncoding::from_content_encoding("zstd").unwrap(), BodyEncoding::Zstd);
+        assert!(BodyEncoding::from_content_encoding("br").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let text = b"GET /health 200\nGET /users 500\n".repeat(100);
+        let gzip = {
+            use std::io::Write;
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&text).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstd = zstd::encode_all(text.as_slice(), 3).unwrap();
+
+        assert_eq!(BodyEncoding::Identity.decode(&text).unwrap(), text);
+        assert_eq!(BodyEncoding::Gzip.decode(&gzip).unwrap(), text);
+        assert_eq!(BodyEncoding::Zstd.decode(&zstd).unwrap(), text);
+
+        assert!(BodyEncoding::Gzip.decode(&gzip[..gzip.len() / 2]).is_err());
+        assert!(BodyEncoding::Zstd.decode(&text).is_err());
+        assert!(BodyEncoding::Zstd.decode_limited(&zstd, text.len() - 1).is_err());
+        assert!(BodyEncoding::Zstd.decode_limited(&zstd, text.len()).is_ok());
+    }
+
+    #[test]
+    fn test_line_splitter() {
+        let mut splitter = LineSplitter::new();
+        assert_eq!(splitter.push(b"first\r\nsec").unwrap(), vec!["first"]);
+        assert!(splitter.push(b"ond").unwrap().is_empty());
+        assert_eq!(splitter.push(b"\n\n  \nthird\nfour").unwrap(), vec!["second", "third"]);
+        assert_eq!(splitter.finish().unwrap().as_deref(), Some("four"));
+
+        let mut splitter = LineSplitter::new();
+        assert!(splitter.push(b"done\n").is_ok());
+        assert_eq!(splitter.finish().unwrap(), None);
+
+        let mut splitter = LineSplitter::new();
+        assert!(splitter.push(&vec![b'x'; MAX_LINE_BYTES + 1]).is_err());
+    }
+
+    /// Decode `compressed` sent in `chunk_size` pieces and split it into lines
+    async fn stream_lines(encoding: BodyEncoding, compressed: &[u8], chunk_size: usize) -> Result<Vec<String>, io::Error> {
+        let chunks: Vec<Result<Bytes, io::Error>> =
+            compressed.chunks(chunk_size).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+        let mut stream = encoding.decode_stream(futures::stream::iter(chunks));
+
+        let mut splitter = LineSplitter::new();
+        let mut lines = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            lines.extend(splitter.push(&chunk?).unwrap());
+        }
+        lines.extend(splitter.finish().unwrap());
+        Ok(lines)
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_gzip() {
+        let compressed = include_bytes!("../tests/fixtures/requests.log.gz");
+        let lines = stream_lines(BodyEncoding::Gzip, compressed, 50).await.unwrap();
+        assert_eq!(lines.len(), 2000);
+        assert_eq!(lines[7], "2024-03-10 12:00:07 INFO request 2 served");
+
+        assert!(stream_lines(BodyEncoding::Gzip, &compressed[..100], 50).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_zstd() {
+        let text: String = (0..2000).map(|i| format!("2024-03-10 12:00:00 INFO request {} served\n", i)).collect();
+        let compressed = zstd::encode_all(text.as_bytes(), 3).unwrap();
+
+        // Chunk boundaries fall inside frames, blocks and lines
+        let lines = stream_lines(BodyEncoding::Zstd, &compressed, 7).await.unwrap();
+        assert_eq!(lines.len(), 2000);
+        assert_eq!(lines[1999], "2024-03-10 12:00:00 INFO request 1999 served");
+
+        // Concatenated frames decode as one body
+        let mut two_frames = zstd::encode_all(&b"first\n"[..], 3).unwrap();
+        two_frames.extend(zstd::encode_all(&b"second\n"[..], 3).unwrap());
+        assert_eq!(stream_lines(BodyEncoding::Zstd, &two_frames, 5).await.unwrap(), vec!["first", "second"]);
+
+        assert!(stream_lines(BodyEncoding::Zstd, &compressed[..compressed.len() / 2], 7).await.is_err());
+    }
+}
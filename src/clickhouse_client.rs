@@ -3,9 +3,15 @@
 /// Provides high-performance log ingestion and querying using ClickHouse
 
 use anyhow::Result;
+use crate::count_anomaly::{AnomalyConfig, CountAnomaly, CountAnomalyDetector};
+use crate::log_matcher::{ensure_same_pattern, LogTemplate, LogType};
+use crate::matcher_registry::TenantKey;
+use crate::template_frequency::{relative_change, BaselineWindow};
+use crate::template_metadata::TemplateMetadata;
 use clickhouse::Client;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Deserialize, clickhouse::Row)]
 pub struct LogEntry {
@@ -16,7 +22,14 @@ pub struct LogEntry {
     pub log_stream_name: String,
     pub timestamp: DateTime<Utc>,
     pub template_id: String,
+    /// Log type of the matched template (empty when unmatched)
+    #[serde(default)]
+    pub log_type_id: String,
     pub message: String,
+    /// Header fields split off by the stream's header format.
+    /// Written on insert; not read back by the RowBinary queries.
+    #[serde(default, skip_deserializing)]
+    pub headers: HashMap<String, String>,
 }
 
 // Custom serialization for ClickHouse JSON format
@@ -26,7 +39,7 @@ impl Serialize for LogEntry {
         S: serde::Serializer,
     {
         use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("LogEntry", 8)?;
+        let mut state = serializer.serialize_struct("LogEntry", 10)?;
         state.serialize_field("org_id", &self.org_id)?;
         state.serialize_field("log_stream_id", &self.log_stream_id)?;
         state.serialize_field("service", &self.service)?;
@@ -36,7 +49,9 @@ impl Serialize for LogEntry {
         let ts_str = self.timestamp.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
         state.serialize_field("timestamp", &ts_str)?;
         state.serialize_field("template_id", &self.template_id)?;
+        state.serialize_field("log_type_id", &self.log_type_id)?;
         state.serialize_field("message", &self.message)?;
+        state.serialize_field("headers", &self.headers)?;
         state.end()
     }
 }
@@ -50,12 +65,209 @@ pub struct TemplateRow {
     pub variables: Vec<String>,
     pub example: String,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Metadata columns; empty strings stand for unset values
+    #[serde(default)]
+    pub severity: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub prompt_version: String,
+    pub updated_at: DateTime<Utc>,
 }
 
+impl TemplateRow {
+    /// Row for a tenant's template. Unset timestamps become now.
+    pub fn from_template(org_id: &str, log_stream_id: &str, template: &LogTemplate) -> Self {
+        let metadata = &template.metadata;
+        let now = Utc::now();
+        Self {
+            org_id: org_id.to_string(),
+            log_stream_id: log_stream_id.to_string(),
+            template_id: template.template_id,
+            pattern: template.pattern.clone(),
+            variables: template.variables.clone(),
+            example: template.example.clone(),
+            created_at: metadata.created_at.unwrap_or(now),
+            disabled: false,
+            severity: metadata.severity.map(|s| s.to_string()).unwrap_or_default(),
+            description: metadata.description.clone().unwrap_or_default(),
+            tags: metadata.tags.clone(),
+            source: metadata.source.clone().unwrap_or_default(),
+            model: metadata.model.clone().unwrap_or_default(),
+            prompt_version: metadata.prompt_version.clone().unwrap_or_default(),
+            updated_at: metadata.updated_at.or(metadata.created_at).unwrap_or(now),
+        }
+    }
+
+    pub fn to_template(&self) -> LogTemplate {
+        let non_empty = |value: &str| Some(value.to_string()).filter(|v| !v.is_empty());
+        LogTemplate {
+            template_id: self.template_id,
+            pattern: self.pattern.clone(),
+            variables: self.variables.clone(),
+            example: self.example.clone(),
+            metadata: TemplateMetadata {
+                severity: self.severity.parse().ok(),
+                description: non_empty(&self.description),
+                tags: self.tags.clone(),
+                source: non_empty(&self.source),
+                model: non_empty(&self.model),
+                prompt_version: non_empty(&self.prompt_version),
+                created_at: Some(self.created_at),
+                updated_at: Some(self.updated_at),
+            },
+        }
+    }
+}
+
+/// Header format configured for a stream (LogHub format string)
+#[derive(Debug, Clone, Serialize, Deserialize, clickhouse::Row)]
+pub struct StreamFormatRow {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub format: String,
+}
+
+/// A log type with the templates grouped under it
+#[derive(Debug, Clone)]
+pub struct LogTypeRow {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub log_type_id: u64,
+    pub signature: String,
+    pub template_ids: Vec<u64>,
+}
+
+impl LogTypeRow {
+    pub fn from_log_type(org_id: &str, log_stream_id: &str, log_type: &LogType) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            log_stream_id: log_stream_id.to_string(),
+            log_type_id: log_type.log_type_id,
+            signature: log_type.signature.clone(),
+            template_ids: log_type.template_ids.clone(),
+        }
+    }
+}
+
+/// Template hit counts accumulated since the previous flush
+#[derive(Debug, Clone)]
+pub struct TemplateStatsRow {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub template_id: u64,
+    /// Hits since the previous flush (sum rows for totals)
+    pub hits: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub flushed_at: DateTime<Utc>,
+}
+
+/// A detected template count anomaly
+#[derive(Debug, Clone, Serialize, Deserialize, clickhouse::Row)]
+pub struct AnomalyRow {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub template_id: u64,
+    /// `spike`, `dip` or `new_template`
+    pub kind: String,
+    pub count: u64,
+    pub expected: f64,
+    pub std_dev: f64,
+    pub z_score: f64,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl AnomalyRow {
+    pub fn from_anomaly(org_id: &str, log_stream_id: &str, anomaly: &CountAnomaly, detected_at: DateTime<Utc>) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            log_stream_id: log_stream_id.to_string(),
+            template_id: anomaly.template_id,
+            kind: anomaly.kind.to_string(),
+            count: anomaly.count,
+            expected: anomaly.expected,
+            std_dev: anomaly.std_dev,
+            z_score: anomaly.z_score,
+            window_start: anomaly.window_start,
+            window_end: anomaly.window_end,
+            detected_at,
+        }
+    }
+}
+
+/// Additive schema changes applied after the base schema.
+/// Each statement must be idempotent since it runs on every startup.
+const SCHEMA_MIGRATIONS: &[&str] = &[
+    "ALTER TABLE templates ADD COLUMN IF NOT EXISTS disabled Bool DEFAULT false",
+    "CREATE TABLE IF NOT EXISTS template_stats (
+        org_id String,
+        log_stream_id String,
+        template_id UInt64,
+        hits UInt64,
+        first_seen DateTime64(3),
+        last_seen DateTime64(3),
+        flushed_at DateTime64(3)
+    ) ENGINE = MergeTree()
+    ORDER BY (org_id, log_stream_id, template_id, flushed_at)",
+    "ALTER TABLE logs ADD COLUMN IF NOT EXISTS headers Map(String, String)",
+    "ALTER TABLE templates
+        ADD COLUMN IF NOT EXISTS severity LowCardinality(String) DEFAULT '',
+        ADD COLUMN IF NOT EXISTS description String DEFAULT '',
+        ADD COLUMN IF NOT EXISTS tags Array(String),
+        ADD COLUMN IF NOT EXISTS source LowCardinality(String) DEFAULT '',
+        ADD COLUMN IF NOT EXISTS model LowCardinality(String) DEFAULT '',
+        ADD COLUMN IF NOT EXISTS prompt_version LowCardinality(String) DEFAULT '',
+        ADD COLUMN IF NOT EXISTS updated_at DateTime64(3) DEFAULT created_at",
+    "CREATE TABLE IF NOT EXISTS stream_formats (
+        org_id String,
+        log_stream_id String,
+        format String,
+        updated_at DateTime64(3)
+    ) ENGINE = ReplacingMergeTree(updated_at)
+    ORDER BY (org_id, log_stream_id)",
+    "ALTER TABLE logs ADD COLUMN IF NOT EXISTS log_type_id String DEFAULT ''",
+    "CREATE TABLE IF NOT EXISTS log_types (
+        org_id String,
+        log_stream_id String,
+        log_type_id UInt64,
+        signature String,
+        template_ids Array(UInt64),
+        updated_at DateTime64(3)
+    ) ENGINE = ReplacingMergeTree(updated_at)
+    ORDER BY (org_id, log_stream_id, log_type_id)",
+    "CREATE TABLE IF NOT EXISTS template_anomalies (
+        org_id String,
+        log_stream_id String,
+        template_id UInt64,
+        kind LowCardinality(String),
+        count UInt64,
+        expected Float64,
+        std_dev Float64,
+        z_score Float64,
+        window_start DateTime64(3),
+        window_end DateTime64(3),
+        detected_at DateTime64(3)
+    ) ENGINE = MergeTree()
+    ORDER BY (org_id, log_stream_id, window_start, template_id)",
+];
+
 #[derive(Clone)]
 pub struct ClickHouseClient {
     client: Client,
     url: String,
+    /// Pooled client for the HTTP interface (JSONEachRow inserts, raw queries)
+    http: reqwest::Client,
 }
 
 impl ClickHouseClient {
@@ -76,7 +288,29 @@ impl ClickHouseClient {
             client = client.with_database(database);
         }
 
-        Ok(Self { client, url: url.to_string() })
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            http: reqwest::Client::new(),
+        })
+    }
+
+    /// Insert rows serialized as JSON lines with `INSERT INTO <table> FORMAT JSONEachRow`
+    async fn insert_json_each_row(&self, table: &str, lines: &[String]) -> Result<()> {
+        let response = self
+            .http
+            .post(&self.url)
+            .query(&[("query", format!("INSERT INTO {} FORMAT JSONEachRow", table))])
+            .body(lines.join("\n"))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            anyhow::bail!("ClickHouse insert failed: {}", error_text);
+        }
+
+        Ok(())
     }
 
     /// Initialize database schema
@@ -91,6 +325,10 @@ impl ClickHouseClient {
             }
         }
 
+        for migration in SCHEMA_MIGRATIONS {
+            self.client.query(migration).execute().await?;
+        }
+
         Ok(())
     }
 
@@ -99,20 +337,7 @@ impl ClickHouseClient {
         // Use JSON format for consistency
         let json_line = serde_json::to_string(&log)?;
 
-        let http_client = reqwest::Client::new();
-        let response = http_client
-            .post(&self.url)
-            .query(&[("query", "INSERT INTO logs FORMAT JSONEachRow")])
-            .body(json_line)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("ClickHouse insert failed: {}", error_text);
-        }
-
-        Ok(())
+        self.insert_json_each_row("logs", &[json_line]).await
     }
 
     /// Insert logs in batch (much faster)
@@ -125,22 +350,193 @@ impl ClickHouseClient {
         let json_lines: Vec<String> = logs.iter()
             .map(|log| serde_json::to_string(log).unwrap())
             .collect();
-        let body = json_lines.join("\n");
 
-        let http_client = reqwest::Client::new();
-        let response = http_client
-            .post(&self.url)
-            .query(&[("query", "INSERT INTO logs FORMAT JSONEachRow")])
-            .body(body)
-            .send()
+        self.insert_json_each_row("logs", &json_lines).await
+    }
+
+    /// Insert a batch of template hit count rows
+    pub async fn insert_template_stats(&self, rows: &[TemplateStatsRow]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        #[derive(Serialize)]
+        struct StatsLine<'a> {
+            org_id: &'a str,
+            log_stream_id: &'a str,
+            template_id: u64,
+            hits: u64,
+            first_seen: String,
+            last_seen: String,
+            flushed_at: String,
+        }
+
+        let format_ts = |ts: &DateTime<Utc>| ts.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let json_lines = rows
+            .iter()
+            .map(|row| {
+                serde_json::to_string(&StatsLine {
+                    org_id: &row.org_id,
+                    log_stream_id: &row.log_stream_id,
+                    template_id: row.template_id,
+                    hits: row.hits,
+                    first_seen: format_ts(&row.first_seen),
+                    last_seen: format_ts(&row.last_seen),
+                    flushed_at: format_ts(&row.flushed_at),
+                })
+            })
+            .collect::<serde_json::Result<Vec<String>>>()?;
+
+        self.insert_json_each_row("template_stats", &json_lines).await
+    }
+
+    /// Record log types and their templates (latest row per log type wins)
+    pub async fn upsert_log_types(&self, rows: &[LogTypeRow]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        #[derive(Serialize)]
+        struct LogTypeLine<'a> {
+            org_id: &'a str,
+            log_stream_id: &'a str,
+            log_type_id: u64,
+            signature: &'a str,
+            template_ids: &'a [u64],
+            updated_at: String,
+        }
+
+        let updated_at = Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let json_lines = rows
+            .iter()
+            .map(|row| {
+                serde_json::to_string(&LogTypeLine {
+                    org_id: &row.org_id,
+                    log_stream_id: &row.log_stream_id,
+                    log_type_id: row.log_type_id,
+                    signature: &row.signature,
+                    template_ids: &row.template_ids,
+                    updated_at: updated_at.clone(),
+                })
+            })
+            .collect::<serde_json::Result<Vec<String>>>()?;
+
+        self.insert_json_each_row("log_types", &json_lines).await
+    }
+
+    /// Append detected anomalies to the `template_anomalies` table
+    pub async fn insert_anomalies(&self, rows: &[AnomalyRow]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        #[derive(Serialize)]
+        struct AnomalyLine<'a> {
+            org_id: &'a str,
+            log_stream_id: &'a str,
+            template_id: u64,
+            kind: &'a str,
+            count: u64,
+            expected: f64,
+            std_dev: f64,
+            z_score: f64,
+            window_start: String,
+            window_end: String,
+            detected_at: String,
+        }
+
+        let format_ts = |ts: &DateTime<Utc>| ts.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let json_lines = rows
+            .iter()
+            .map(|row| {
+                serde_json::to_string(&AnomalyLine {
+                    org_id: &row.org_id,
+                    log_stream_id: &row.log_stream_id,
+                    template_id: row.template_id,
+                    kind: &row.kind,
+                    count: row.count,
+                    expected: row.expected,
+                    std_dev: row.std_dev,
+                    z_score: row.z_score,
+                    window_start: format_ts(&row.window_start),
+                    window_end: format_ts(&row.window_end),
+                    detected_at: format_ts(&row.detected_at),
+                })
+            })
+            .collect::<serde_json::Result<Vec<String>>>()?;
+
+        self.insert_json_each_row("template_anomalies", &json_lines).await
+    }
+
+    /// Anomalies recorded for a stream whose window starts in the range, latest first
+    pub async fn get_anomalies(
+        &self,
+        org_id: &str,
+        log_stream_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<AnomalyRow>> {
+        let start_str = start_time.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let end_str = end_time.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+
+        let anomalies = self.client
+            .query("
+                SELECT
+                    org_id, log_stream_id, template_id, kind, count,
+                    expected, std_dev, z_score, window_start, window_end, detected_at
+                FROM template_anomalies
+                WHERE org_id = ?
+                  AND log_stream_id = ?
+                  AND window_start >= parseDateTime64BestEffort(?)
+                  AND window_start <= parseDateTime64BestEffort(?)
+                ORDER BY window_start DESC, abs(z_score) DESC
+                LIMIT 1000
+            ")
+            .bind(org_id)
+            .bind(log_stream_id)
+            .bind(start_str)
+            .bind(end_str)
+            .fetch_all::<AnomalyRow>()
             .await?;
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("ClickHouse insert failed: {}", error_text);
+        Ok(anomalies)
+    }
+
+    /// Run count anomaly detection offline over stored logs: the range is cut
+    /// into `step`-long windows whose `query_logs_grouped` counts are fed to a
+    /// fresh detector in order
+    pub async fn scan_count_anomalies(
+        &self,
+        org_id: &str,
+        log_stream_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        step: chrono::Duration,
+        config: AnomalyConfig,
+    ) -> Result<Vec<CountAnomaly>> {
+        if step <= chrono::Duration::zero() {
+            anyhow::bail!("scan step must be positive");
         }
 
-        Ok(())
+        let mut detector = CountAnomalyDetector::new(config);
+        let mut anomalies = Vec::new();
+        let mut window_start = start_time;
+
+        while window_start < end_time {
+            let window_end = (window_start + step).min(end_time);
+            let groups = self
+                .query_logs_grouped(org_id, log_stream_id, window_start, window_end, BaselineWindow::Previous)
+                .await?;
+            // Unmatched logs have no template id
+            let counts: HashMap<u64, u64> = groups
+                .iter()
+                .filter_map(|group| Some((group.template_id.parse().ok()?, group.log_count)))
+                .collect();
+            anomalies.extend(detector.observe(&counts, window_start, window_end));
+            window_start = window_end;
+        }
+
+        Ok(anomalies)
     }
 
     /// Query logs for a time range
@@ -159,7 +555,7 @@ impl ClickHouseClient {
             .query("
                 SELECT
                     org_id, log_stream_id, service, region, log_stream_name,
-                    timestamp, template_id, message
+                    timestamp, template_id, log_type_id, message
                 FROM logs
                 WHERE org_id = ?
                   AND log_stream_id = ?
@@ -178,18 +574,133 @@ impl ClickHouseClient {
         Ok(logs)
     }
 
-    /// Query logs grouped by template
+    /// Query logs grouped by template: the 20 most frequent templates of the
+    /// window, each compared with its count in the `baseline` window, followed
+    /// by up to 20 templates seen in the baseline only (flagged `disappeared`)
     pub async fn query_logs_grouped(
         &self,
         org_id: &str,
         log_stream_id: &str,
         start_time: DateTime<Utc>,
         end_time: DateTime<Utc>,
+        baseline: BaselineWindow,
     ) -> Result<Vec<LogGroup>> {
         #[derive(Debug, clickhouse::Row, Deserialize)]
         struct GroupRow {
             template_id: String,
             log_count: u64,
+            baseline_count: u64,
+            sample_messages: Vec<String>,
+        }
+
+        #[derive(Debug, clickhouse::Row, Deserialize)]
+        struct DisappearedRow {
+            template_id: String,
+            baseline_count: u64,
+        }
+
+        let (baseline_start, baseline_end) = baseline.range(start_time, end_time);
+        let format_ts = |ts: DateTime<Utc>| ts.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let (duration, baseline_duration) = (end_time - start_time, baseline_end - baseline_start);
+
+        let present = self.client
+            .query("
+                WITH
+                    parseDateTime64BestEffort(?) AS window_start,
+                    parseDateTime64BestEffort(?) AS window_end,
+                    parseDateTime64BestEffort(?) AS baseline_start,
+                    parseDateTime64BestEffort(?) AS baseline_end
+                SELECT
+                    template_id,
+                    countIf(timestamp >= window_start AND timestamp <= window_end) as log_count,
+                    countIf(timestamp >= baseline_start AND timestamp < baseline_end) as baseline_count,
+                    groupArrayIf(5)(message, timestamp >= window_start AND timestamp <= window_end) as sample_messages
+                FROM logs
+                WHERE org_id = ?
+                  AND log_stream_id = ?
+                  AND ((timestamp >= window_start AND timestamp <= window_end)
+                    OR (timestamp >= baseline_start AND timestamp < baseline_end))
+                GROUP BY template_id
+                HAVING log_count > 0
+                ORDER BY log_count DESC, template_id
+                LIMIT 20
+            ")
+            .bind(format_ts(start_time))
+            .bind(format_ts(end_time))
+            .bind(format_ts(baseline_start))
+            .bind(format_ts(baseline_end))
+            .bind(org_id)
+            .bind(log_stream_id)
+            .fetch_all::<GroupRow>()
+            .await?;
+
+        let disappeared = self.client
+            .query("
+                SELECT
+                    template_id,
+                    count() as baseline_count
+                FROM logs
+                WHERE org_id = ?
+                  AND log_stream_id = ?
+                  AND timestamp >= parseDateTime64BestEffort(?)
+                  AND timestamp < parseDateTime64BestEffort(?)
+                  AND template_id NOT IN (
+                    SELECT DISTINCT template_id
+                    FROM logs
+                    WHERE org_id = ?
+                      AND log_stream_id = ?
+                      AND timestamp >= parseDateTime64BestEffort(?)
+                      AND timestamp <= parseDateTime64BestEffort(?)
+                  )
+                GROUP BY template_id
+                ORDER BY baseline_count DESC, template_id
+                LIMIT 20
+            ")
+            .bind(org_id)
+            .bind(log_stream_id)
+            .bind(format_ts(baseline_start))
+            .bind(format_ts(baseline_end))
+            .bind(org_id)
+            .bind(log_stream_id)
+            .bind(format_ts(start_time))
+            .bind(format_ts(end_time))
+            .fetch_all::<DisappearedRow>()
+            .await?;
+
+        let present = present.into_iter().map(|row| LogGroup {
+            relative_change: relative_change(row.log_count, row.baseline_count, duration, baseline_duration),
+            is_new: row.baseline_count == 0,
+            disappeared: false,
+            template_id: row.template_id,
+            log_count: row.log_count,
+            baseline_count: row.baseline_count,
+            sample_messages: row.sample_messages,
+        });
+        let disappeared = disappeared.into_iter().map(|row| LogGroup {
+            relative_change: relative_change(0, row.baseline_count, duration, baseline_duration),
+            is_new: false,
+            disappeared: true,
+            template_id: row.template_id,
+            log_count: 0,
+            baseline_count: row.baseline_count,
+            sample_messages: Vec::new(),
+        });
+        Ok(present.chain(disappeared).collect())
+    }
+
+    /// Query logs rolled up by log type (many template variants per group)
+    pub async fn query_logs_by_log_type(
+        &self,
+        org_id: &str,
+        log_stream_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<LogTypeGroup>> {
+        #[derive(Debug, clickhouse::Row, Deserialize)]
+        struct GroupRow {
+            log_type_id: String,
+            log_count: u64,
+            template_ids: Vec<String>,
             sample_messages: Vec<String>,
         }
 
@@ -199,15 +710,17 @@ impl ClickHouseClient {
         let groups = self.client
             .query("
                 SELECT
-                    template_id,
+                    log_type_id,
                     count() as log_count,
+                    groupUniqArray(template_id) as template_ids,
                     groupArray(5)(message) as sample_messages
                 FROM logs
                 WHERE org_id = ?
                   AND log_stream_id = ?
+                  AND log_type_id != ''
                   AND timestamp >= parseDateTime64BestEffort(?)
                   AND timestamp <= parseDateTime64BestEffort(?)
-                GROUP BY template_id
+                GROUP BY log_type_id
                 ORDER BY log_count DESC
                 LIMIT 20
             ")
@@ -218,20 +731,33 @@ impl ClickHouseClient {
             .fetch_all::<GroupRow>()
             .await?;
 
-        Ok(groups.into_iter().map(|g| LogGroup {
-            template_id: g.template_id,
+        Ok(groups.into_iter().map(|g| LogTypeGroup {
+            log_type_id: g.log_type_id,
             log_count: g.log_count,
+            template_ids: g.template_ids,
             sample_messages: g.sample_messages,
-            relative_change: 0.0, // TODO: Calculate from baseline
         }).collect())
     }
 
-    /// Store template and return the assigned template_id
-    /// If template_id is 0, generates next available ID from ClickHouse
+    /// Store template and return its template_id.
+    /// A template_id of 0 is replaced with the tenant's content-addressed ID for
+    /// the pattern, so replicas inserting the same template agree on the ID.
+    /// Fails if the tenant already has a different pattern under that ID.
     pub async fn insert_template(&self, mut template: TemplateRow) -> Result<u64> {
-        // If template_id is 0, get next available ID
         if template.template_id == 0 {
-            template.template_id = self.get_next_template_id().await?;
+            template.template_id =
+                TenantKey::new(&template.org_id, &template.log_stream_id).template_id(&template.pattern);
+        }
+
+        let existing = self.client
+            .query("SELECT pattern FROM templates WHERE org_id = ? AND log_stream_id = ? AND template_id = ? LIMIT 1")
+            .bind(&template.org_id)
+            .bind(&template.log_stream_id)
+            .bind(template.template_id)
+            .fetch_optional::<String>()
+            .await?;
+        if let Some(existing) = existing {
+            ensure_same_pattern(template.template_id, &existing, &template.pattern)?;
         }
 
         let mut insert = self.client.insert("templates")?;
@@ -241,25 +767,10 @@ impl ClickHouseClient {
         Ok(template.template_id)
     }
 
-    /// Get next available template ID from ClickHouse
-    async fn get_next_template_id(&self) -> Result<u64> {
-        #[derive(Debug, clickhouse::Row, Deserialize)]
-        struct MaxIdRow {
-            max_id: u64,
-        }
-
-        let result = self.client
-            .query("SELECT COALESCE(max(template_id), 0) as max_id FROM templates")
-            .fetch_one::<MaxIdRow>()
-            .await?;
-
-        Ok(result.max_id + 1)
-    }
-
     /// Get all templates
     pub async fn get_templates(&self) -> Result<Vec<TemplateRow>> {
         let templates = self.client
-            .query("SELECT org_id, log_stream_id, template_id, pattern, variables, example, created_at FROM templates")
+            .query("SELECT org_id, log_stream_id, template_id, pattern, variables, example, created_at, disabled, severity, description, tags, source, model, prompt_version, updated_at FROM templates")
             .fetch_all::<TemplateRow>()
             .await?;
 
@@ -295,20 +806,7 @@ impl ClickHouseClient {
 
         let json_line = serde_json::to_string(&example)?;
 
-        let http_client = reqwest::Client::new();
-        let response = http_client
-            .post(&self.url)
-            .query(&[("query", "INSERT INTO template_examples FORMAT JSONEachRow")])
-            .body(json_line)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("ClickHouse insert failed: {}", error_text);
-        }
-
-        Ok(())
+        self.insert_json_each_row("template_examples", &[json_line]).await
     }
 
     /// Get example logs for a template
@@ -331,8 +829,8 @@ impl ClickHouseClient {
             org_id, log_stream_id, template_id, limit
         );
 
-        let http_client = reqwest::Client::new();
-        let response = http_client
+        let response = self
+            .http
             .post(&self.url)
             .body(query)
             .send()
@@ -365,7 +863,9 @@ impl ClickHouseClient {
                         .ok()?
                         .with_timezone(&Utc),
                     template_id: row.template_id,
+                    log_type_id: String::new(), // Not stored in template_examples
                     message: row.message,
+                    headers: HashMap::new(),
                 })
             })
             .collect();
@@ -373,15 +873,10 @@ impl ClickHouseClient {
         Ok(examples)
     }
 
-    /// Insert template with auto-generated ID (alias for insert_template)
-    pub async fn insert_template_with_autoid(&self, template: TemplateRow) -> Result<u64> {
-        self.insert_template(template).await
-    }
-
     /// Get templates for a specific org and log stream
     pub async fn get_templates_for_stream(&self, org_id: &str, log_stream_id: &str) -> Result<Vec<TemplateRow>> {
         let templates = self.client
-            .query("SELECT org_id, log_stream_id, template_id, pattern, variables, example, created_at FROM templates WHERE org_id = ? AND log_stream_id = ? ORDER BY template_id")
+            .query("SELECT org_id, log_stream_id, template_id, pattern, variables, example, created_at, disabled, severity, description, tags, source, model, prompt_version, updated_at FROM templates WHERE org_id = ? AND log_stream_id = ? ORDER BY template_id")
             .bind(org_id)
             .bind(log_stream_id)
             .fetch_all::<TemplateRow>()
@@ -390,6 +885,75 @@ impl ClickHouseClient {
         Ok(templates)
     }
 
+    /// Delete a single template
+    pub async fn delete_template(&self, org_id: &str, log_stream_id: &str, template_id: u64) -> Result<()> {
+        self.client
+            .query("DELETE FROM templates WHERE org_id = ? AND log_stream_id = ? AND template_id = ?")
+            .bind(org_id)
+            .bind(log_stream_id)
+            .bind(template_id)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Replace a template (same template_id) with a new definition
+    pub async fn replace_template(&self, template: TemplateRow) -> Result<()> {
+        if template.template_id == 0 {
+            anyhow::bail!("Cannot replace template without a template_id");
+        }
+
+        self.delete_template(&template.org_id, &template.log_stream_id, template.template_id)
+            .await?;
+
+        let mut insert = self.client.insert("templates")?;
+        insert.write(&template).await?;
+        insert.end().await?;
+
+        Ok(())
+    }
+
+    /// Header formats set per stream (an empty format means the format was cleared)
+    pub async fn get_stream_formats(&self) -> Result<Vec<StreamFormatRow>> {
+        let formats = self.client
+            .query("SELECT org_id, log_stream_id, format FROM stream_formats FINAL WHERE format != ''")
+            .fetch_all::<StreamFormatRow>()
+            .await?;
+
+        Ok(formats)
+    }
+
+    /// Set a stream's header format; an empty string clears it
+    pub async fn set_stream_format(&self, org_id: &str, log_stream_id: &str, format: &str) -> Result<()> {
+        let row = serde_json::json!({
+            "org_id": org_id,
+            "log_stream_id": log_stream_id,
+            "format": format,
+            "updated_at": Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        });
+
+        self.insert_json_each_row("stream_formats", &[row.to_string()]).await
+    }
+
+    /// Enable or disable a template without deleting it
+    pub async fn set_template_disabled(
+        &self,
+        org_id: &str,
+        log_stream_id: &str,
+        template_id: u64,
+        disabled: bool,
+    ) -> Result<()> {
+        self.client
+            .query("ALTER TABLE templates UPDATE disabled = ? WHERE org_id = ? AND log_stream_id = ? AND template_id = ?")
+            .bind(disabled)
+            .bind(org_id)
+            .bind(log_stream_id)
+            .bind(template_id)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
     /// Clear all templates from the database
     pub async fn clear_templates(&self) -> Result<()> {
         self.client.query("TRUNCATE TABLE templates").execute().await?;
@@ -402,7 +966,22 @@ pub struct LogGroup {
     pub template_id: String,
     pub log_count: u64,
     pub sample_messages: Vec<String>,
+    /// Change of the template's rate against the baseline window (see `template_frequency`)
     pub relative_change: f64,
+    pub baseline_count: u64,
+    /// Not seen in the baseline window
+    pub is_new: bool,
+    /// Seen in the baseline window only
+    pub disappeared: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogTypeGroup {
+    pub log_type_id: String,
+    pub log_count: u64,
+    /// Templates of this log type seen in the range
+    pub template_ids: Vec<String>,
+    pub sample_messages: Vec<String>,
 }
 
 #[cfg(test)]
@@ -429,7 +1008,9 @@ mod tests {
             log_stream_name: "/aws/api/production".to_string(),
             timestamp: Utc::now(),
             template_id: "template-1".to_string(),
+            log_type_id: String::new(),
             message: "Test error message".to_string(),
+            headers: HashMap::new(),
         };
 
         client.insert_log(log.clone()).await.unwrap();
@@ -0,0 +1,461 @@
+/// Template count anomaly detection
+///
+/// Feeds per-template log counts of consecutive intervals into a model of each
+/// template's usual count, either a rolling mean and standard deviation over
+/// the last N intervals or an exponentially weighted moving average (EWMA),
+/// and flags counts whose z-score reaches the threshold: spikes above, dips
+/// below. A template never seen in an earlier interval is reported as new.
+/// The same detector runs on live hit counts in `log-ingest-service` and on
+/// windows of `ClickHouseClient::query_logs_grouped` results.
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Expected counts below this are treated as zero; such series are dropped
+const NEGLIGIBLE_COUNT: f64 = 1e-3;
+
+/// How the usual count of a template is estimated
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "method")]
+pub enum AnomalyMethod {
+    /// Mean and standard deviation of the last `window` intervals
+    Rolling { window: usize },
+    /// Exponentially weighted mean and variance; higher `alpha` forgets faster
+    Ewma { alpha: f64 },
+}
+
+impl Default for AnomalyMethod {
+    fn default() -> Self {
+        AnomalyMethod::Ewma { alpha: 0.1 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnomalyConfig {
+    pub method: AnomalyMethod,
+    /// |z-score| at which a count is a spike or a dip
+    pub z_threshold: f64,
+    /// Intervals a template needs before its spikes and dips are reported
+    pub min_history: usize,
+    /// Floor of the standard deviation, so near-constant series don't alert on
+    /// small changes. The square root of the expected count (Poisson noise) is
+    /// also used as a floor.
+    pub min_std_dev: f64,
+    /// Report templates not seen in any earlier interval
+    pub alert_new: bool,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            method: AnomalyMethod::default(),
+            z_threshold: 3.0,
+            min_history: 10,
+            min_std_dev: 1.0,
+            alert_new: true,
+        }
+    }
+}
+
+impl AnomalyConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_method(mut self, method: AnomalyMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub fn with_z_threshold(mut self, threshold: f64) -> Self {
+        self.z_threshold = threshold;
+        self
+    }
+
+    pub fn with_min_history(mut self, intervals: usize) -> Self {
+        self.min_history = intervals;
+        self
+    }
+
+    pub fn with_min_std_dev(mut self, std_dev: f64) -> Self {
+        self.min_std_dev = std_dev;
+        self
+    }
+
+    pub fn with_new_template_alerts(mut self, enabled: bool) -> Self {
+        self.alert_new = enabled;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.method {
+            AnomalyMethod::Rolling { window } if window < 2 => {
+                anyhow::bail!("rolling window must be at least 2 intervals")
+            }
+            AnomalyMethod::Ewma { alpha } if !(alpha > 0.0 && alpha <= 1.0) => {
+                anyhow::bail!("EWMA alpha must be in (0, 1]")
+            }
+            _ => {}
+        }
+        if self.z_threshold.is_nan() || self.z_threshold <= 0.0 {
+            anyhow::bail!("z-score threshold must be positive");
+        }
+        if self.min_std_dev < 0.0 {
+            anyhow::bail!("minimum standard deviation must not be negative");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    Spike,
+    Dip,
+    /// First interval the template was seen in
+    NewTemplate,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::Spike => "spike",
+            AnomalyKind::Dip => "dip",
+            AnomalyKind::NewTemplate => "new_template",
+        }
+    }
+}
+
+impl FromStr for AnomalyKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "spike" => Ok(AnomalyKind::Spike),
+            "dip" => Ok(AnomalyKind::Dip),
+            "new_template" => Ok(AnomalyKind::NewTemplate),
+            other => anyhow::bail!("unknown anomaly kind '{}'", other),
+        }
+    }
+}
+
+impl fmt::Display for AnomalyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A template count that is far from its usual count, or a new template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountAnomaly {
+    pub template_id: u64,
+    pub kind: AnomalyKind,
+    pub count: u64,
+    /// Usual count per interval before this one (0 for new templates)
+    pub expected: f64,
+    pub std_dev: f64,
+    /// (count - expected) / std_dev, with std_dev floored (0 for new templates)
+    pub z_score: f64,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+}
+
+/// Count history of one template
+#[derive(Debug, Clone, Default)]
+struct CountSeries {
+    /// Last counts (rolling method only)
+    recent: VecDeque<f64>,
+    /// EWMA state (EWMA method only)
+    mean: f64,
+    variance: f64,
+    observed: usize,
+}
+
+impl CountSeries {
+    /// Expected count and standard deviation from the intervals so far
+    fn estimate(&self, method: AnomalyMethod) -> (f64, f64) {
+        match method {
+            AnomalyMethod::Rolling { .. } => {
+                let n = self.recent.len().max(1) as f64;
+                let mean = self.recent.iter().sum::<f64>() / n;
+                let variance = self.recent.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / n;
+                (mean, variance.sqrt())
+            }
+            AnomalyMethod::Ewma { .. } => (self.mean, self.variance.sqrt()),
+        }
+    }
+
+    fn push(&mut self, count: f64, method: AnomalyMethod) {
+        match method {
+            AnomalyMethod::Rolling { window } => {
+                if self.recent.len() == window {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(count);
+            }
+            AnomalyMethod::Ewma { alpha } => {
+                if self.observed == 0 {
+                    self.mean = count;
+                } else {
+                    let diff = count - self.mean;
+                    self.mean += alpha * diff;
+                    self.variance = (1.0 - alpha) * (self.variance + alpha * diff * diff);
+                }
+            }
+        }
+        self.observed += 1;
+    }
+}
+
+/// Spike, dip and new-template detector over per-interval template counts
+pub struct CountAnomalyDetector {
+    config: AnomalyConfig,
+    series: FxHashMap<u64, CountSeries>,
+    /// Templates seen in any interval (or marked as known)
+    seen: FxHashSet<u64>,
+    intervals: usize,
+}
+
+impl CountAnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            series: FxHashMap::default(),
+            seen: FxHashSet::default(),
+            intervals: 0,
+        }
+    }
+
+    pub fn config(&self) -> &AnomalyConfig {
+        &self.config
+    }
+
+    /// Intervals observed so far
+    pub fn intervals(&self) -> usize {
+        self.intervals
+    }
+
+    /// Templates with a count history
+    pub fn tracked_templates(&self) -> usize {
+        self.series.len()
+    }
+
+    /// Treat these templates as already seen, so their first counts are not
+    /// reported as new (templates that existed before the detector started)
+    pub fn mark_seen<I: IntoIterator<Item = u64>>(&mut self, template_ids: I) {
+        self.seen.extend(template_ids);
+    }
+
+    /// Forget a template's history (deleted or replaced)
+    pub fn remove_template(&mut self, template_id: u64) {
+        self.series.remove(&template_id);
+    }
+
+    /// Add the counts of one interval and return its anomalies. Tracked
+    /// templates missing from `counts` count as 0, so they can dip. New
+    /// templates are only reported from the second interval on: the first
+    /// interval has nothing to compare with.
+    pub fn observe(
+        &mut self,
+        counts: &HashMap<u64, u64>,
+        window_start: DateTime<Utc>,
+        window_end: DateTime<Utc>,
+    ) -> Vec<CountAnomaly> {
+        let method = self.config.method;
+        let mut anomalies = Vec::new();
+
+        for (&template_id, &count) in counts {
+            if count > 0 && self.seen.insert(template_id) && self.config.alert_new && self.intervals > 0 {
+                anomalies.push(CountAnomaly {
+                    template_id,
+                    kind: AnomalyKind::NewTemplate,
+                    count,
+                    expected: 0.0,
+                    std_dev: 0.0,
+                    z_score: 0.0,
+                    window_start,
+                    window_end,
+                });
+            }
+            if count > 0 {
+                self.series.entry(template_id).or_default();
+            }
+        }
+
+        self.series.retain(|template_id, series| {
+            let count = counts.get(template_id).copied().unwrap_or(0) as f64;
+
+            if series.observed >= self.config.min_history {
+                let (expected, std_dev) = series.estimate(method);
+                let std_dev = std_dev.max(expected.sqrt()).max(self.config.min_std_dev);
+                let z_score = if std_dev > 0.0 { (count - expected) / std_dev } else { 0.0 };
+                if z_score.abs() >= self.config.z_threshold {
+                    anomalies.push(CountAnomaly {
+                        template_id: *template_id,
+                        kind: if z_score > 0.0 { AnomalyKind::Spike } else { AnomalyKind::Dip },
+                        count: count as u64,
+                        expected,
+                        std_dev,
+                        z_score,
+                        window_start,
+                        window_end,
+                    });
+                }
+            }
+
+            series.push(count, method);
+            // Templates that stopped appearing long ago don't need a history
+            count > 0.0 || series.estimate(method).0 >= NEGLIGIBLE_COUNT
+        });
+
+        self.intervals += 1;
+        anomalies.sort_by(|a, b| {
+            b.z_score
+                .abs()
+                .total_cmp(&a.z_score.abs())
+                .then_with(|| a.template_id.cmp(&b.template_id))
+        });
+        anomalies
+    }
+}
+
+/// Anomalies of one tenant as posted to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyNotification {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub anomalies: Vec<CountAnomaly>,
+}
+
+/// Posts anomalies as JSON (`AnomalyNotification`) to a webhook URL
+#[derive(Clone)]
+pub struct AnomalyWebhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl AnomalyWebhook {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            url: url.to_string(),
+            client,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Send one notification; nothing is sent for an empty list
+    pub async fn send(&self, org_id: &str, log_stream_id: &str, anomalies: &[CountAnomaly]) -> Result<()> {
+        if anomalies.is_empty() {
+            return Ok(());
+        }
+
+        let notification = AnomalyNotification {
+            org_id: org_id.to_string(),
+            log_stream_id: log_stream_id.to_string(),
+            anomalies: anomalies.to_vec(),
+        };
+        let response = self.client.post(&self.url).json(&notification).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("Anomaly webhook returned {}: {}", status, error_text);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(i: i64) -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = DateTime::from_timestamp(1_700_000_000 + i * 60, 0).unwrap();
+        (start, start + chrono::Duration::seconds(60))
+    }
+
+    fn run(detector: &mut CountAnomalyDetector, i: i64, counts: &[(u64, u64)]) -> Vec<CountAnomaly> {
+        let (start, end) = window(i);
+        detector.observe(&counts.iter().copied().collect(), start, end)
+    }
+
+    #[test]
+    fn test_spike_and_dip() {
+        for method in [AnomalyMethod::Rolling { window: 20 }, AnomalyMethod::Ewma { alpha: 0.1 }] {
+            let config = AnomalyConfig::new().with_method(method).with_min_history(10);
+            let mut detector = CountAnomalyDetector::new(config);
+
+            // Steady traffic with some noise
+            for i in 0..30 {
+                let anomalies = run(&mut detector, i, &[(1, 100 + (i as u64 % 5) * 3), (2, 50)]);
+                assert!(anomalies.is_empty(), "{:?}: {:?}", method, anomalies);
+            }
+
+            let anomalies = run(&mut detector, 30, &[(1, 400)]);
+            assert_eq!(anomalies.len(), 2, "{:?}", method);
+            assert_eq!((anomalies[0].template_id, anomalies[0].kind), (1, AnomalyKind::Spike));
+            assert!(anomalies[0].z_score >= 3.0);
+            // Template 2 is missing from the interval: a dip to zero
+            assert_eq!((anomalies[1].template_id, anomalies[1].kind, anomalies[1].count), (2, AnomalyKind::Dip, 0));
+            assert!((anomalies[1].expected - 50.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_min_history_and_noise_floor() {
+        let mut detector = CountAnomalyDetector::new(AnomalyConfig::new().with_min_history(5));
+
+        // Too little history to judge a jump
+        run(&mut detector, 0, &[(1, 10)]);
+        assert!(run(&mut detector, 1, &[(1, 1000)]).is_empty());
+
+        // A constant low-volume series doesn't alert on small changes
+        let mut detector = CountAnomalyDetector::new(AnomalyConfig::new().with_min_history(5));
+        for i in 0..10 {
+            run(&mut detector, i, &[(1, 4)]);
+        }
+        assert!(run(&mut detector, 10, &[(1, 8)]).is_empty());
+        assert_eq!(run(&mut detector, 11, &[(1, 40)])[0].kind, AnomalyKind::Spike);
+    }
+
+    #[test]
+    fn test_new_templates() {
+        let mut detector = CountAnomalyDetector::new(AnomalyConfig::new());
+        detector.mark_seen([3]);
+
+        // Nothing to compare with in the first interval
+        assert!(run(&mut detector, 0, &[(1, 10)]).is_empty());
+
+        let anomalies = run(&mut detector, 1, &[(1, 10), (2, 1), (3, 5)]);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!((anomalies[0].template_id, anomalies[0].kind), (2, AnomalyKind::NewTemplate));
+
+        // Reported once, and not again after a gap
+        assert!(run(&mut detector, 2, &[(1, 10)]).is_empty());
+        assert!(run(&mut detector, 3, &[(1, 10), (2, 1)]).is_empty());
+
+        let mut detector = CountAnomalyDetector::new(AnomalyConfig::new().with_new_template_alerts(false));
+        run(&mut detector, 0, &[(1, 10)]);
+        assert!(run(&mut detector, 1, &[(2, 1)]).is_empty());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(AnomalyConfig::new().validate().is_ok());
+        assert!(AnomalyConfig::new().with_method(AnomalyMethod::Rolling { window: 1 }).validate().is_err());
+        assert!(AnomalyConfig::new().with_method(AnomalyMethod::Ewma { alpha: 0.0 }).validate().is_err());
+        assert!(AnomalyConfig::new().with_z_threshold(0.0).validate().is_err());
+        assert_eq!("new_template".parse::<AnomalyKind>().unwrap(), AnomalyKind::NewTemplate);
+    }
+}
@@ -0,0 +1,234 @@
+/// Elasticsearch `_bulk` API compatibility
+///
+/// Parses NDJSON bulk bodies into actions and their documents, reads document
+/// fields by dotted path, and builds the per-item response bulk clients check.
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    Index,
+    Create,
+    Update,
+    Delete,
+}
+
+impl BulkAction {
+    /// Actions followed by a source line
+    fn has_source(self) -> bool {
+        self != Self::Delete
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkOperation {
+    pub action: BulkAction,
+    /// `_index` of the action, else the index of the request path
+    pub index: Option<String>,
+    pub id: Option<String>,
+    pub document: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ActionMeta {
+    #[serde(rename = "_index")]
+    index: Option<String>,
+    #[serde(rename = "_id")]
+    id: Option<String>,
+}
+
+/// Parse a bulk body. A malformed action or source line fails the whole request,
+/// as in Elasticsearch.
+pub fn parse_bulk(body: &str, default_index: Option<&str>) -> Result<Vec<BulkOperation>> {
+    let mut lines = body.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let mut operations = Vec::new();
+
+    while let Some((n, line)) = lines.next() {
+        let action: HashMap<BulkAction, ActionMeta> = serde_json::from_str(line)
+            .with_context(|| format!("line {}: malformed action, expected one of index, create, update, delete", n + 1))?;
+        if action.len() != 1 {
+            bail!("line {}: expected exactly one action", n + 1);
+        }
+        let (action, meta) = action.into_iter().next().expect("one action");
+
+        let document = if action.has_source() {
+            let (n, line) = lines.next().with_context(|| format!("line {}: {:?} action without a source line", n + 1, action))?;
+            let document: Value = serde_json::from_str(line).with_context(|| format!("line {}: malformed source", n + 1))?;
+            if !document.is_object() {
+                bail!("line {}: source must be a JSON object", n + 1);
+            }
+            Some(document)
+        } else {
+            None
+        };
+
+        operations.push(BulkOperation {
+            action,
+            index: meta.index.or_else(|| default_index.map(str::to_string)),
+            id: meta.id,
+            document,
+        });
+    }
+    Ok(operations)
+}
+
+/// Scalar field of a document: a literal dotted key (`"log.level": ...`)
+/// or a path into nested objects (`{"log": {"level": ...}}`)
+pub fn field(document: &Value, path: &str) -> Option<String> {
+    let value = document.get(path).or_else(|| path.split('.').try_fold(document, |value, key| value.get(key)))?;
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// RFC 3339 or epoch milliseconds, the two forms shippers send
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(millis) = value.parse::<i64>() {
+        return DateTime::from_timestamp_millis(millis);
+    }
+    DateTime::parse_from_rfc3339(value).ok().map(|ts| ts.with_timezone(&Utc))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkError {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkItemResult {
+    #[serde(rename = "_index")]
+    pub index: String,
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BulkError>,
+}
+
+/// Response body of `_bulk`: one item per action, in request order
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkResponse {
+    pub took: u64,
+    pub errors: bool,
+    pub items: Vec<HashMap<BulkAction, BulkItemResult>>,
+}
+
+impl BulkResponse {
+    pub fn new() -> Self {
+        Self {
+            took: 0,
+            errors: false,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn created(&mut self, action: BulkAction, index: String, id: String) {
+        let result = BulkItemResult {
+            index,
+            id,
+            status: 201,
+            result: Some("created".to_string()),
+            error: None,
+        };
+        self.items.push(HashMap::from([(action, result)]));
+    }
+
+    pub fn failed(&mut self, action: BulkAction, index: String, id: String, kind: &str, reason: String) {
+        let result = BulkItemResult {
+            index,
+            id,
+            status: 400,
+            result: None,
+            error: Some(BulkError {
+                kind: kind.to_string(),
+                reason,
+            }),
+        };
+        self.errors = true;
+        self.items.push(HashMap::from([(action, result)]));
+    }
+}
+
+impl Default for BulkResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bulk() {
+        let body = r#"{"index": {"_index": "logs-api", "_id": "1"}}
+{"message": "GET /health 200", "service": {"name": "api"}}
+
+{"create": {}}
+{"log": "worker started", "@timestamp": "2024-03-10T12:00:00Z"}
+{"delete": {"_index": "logs-api", "_id": "1"}}
+{"update": {"_id": "2"}}
+{"doc": {"message": "x"}}
+"#;
+        let operations = parse_bulk(body, Some("default-index")).unwrap();
+        let actions: Vec<BulkAction> = operations.iter().map(|op| op.action).collect();
+        assert_eq!(actions, vec![BulkAction::Index, BulkAction::Create, BulkAction::Delete, BulkAction::Update]);
+        assert_eq!((operations[0].index.as_deref(), operations[0].id.as_deref()), (Some("logs-api"), Some("1")));
+        assert_eq!((operations[1].index.as_deref(), operations[1].id.as_deref()), (Some("default-index"), None));
+        assert!(operations[2].document.is_none());
+        assert!(operations[3].document.is_some());
+    }
+
+    #[test]
+    fn test_parse_bulk_errors() {
+        assert!(parse_bulk(r#"{"upsert": {}}"#, None).is_err());
+        assert!(parse_bulk(r#"{"index": {}, "create": {}}"#, None).is_err());
+        assert!(parse_bulk(r#"{"index": {}}"#, None).is_err());
+        assert!(parse_bulk("{\"index\": {}}\n[1, 2]", None).is_err());
+        assert!(parse_bulk("", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_field_and_timestamp() {
+        let document: Value = serde_json::json!({
+            "service": {"name": "api"},
+            "log.file.path": "/var/log/api.log",
+            "status": 500,
+            "tags": ["a"]
+        });
+        assert_eq!(field(&document, "service.name").as_deref(), Some("api"));
+        assert_eq!(field(&document, "log.file.path").as_deref(), Some("/var/log/api.log"));
+        assert_eq!(field(&document, "status").as_deref(), Some("500"));
+        assert_eq!(field(&document, "tags"), None);
+        assert_eq!(field(&document, "missing"), None);
+
+        assert_eq!(parse_timestamp("1700000000123").unwrap().timestamp_millis(), 1_700_000_000_123);
+        assert_eq!(parse_timestamp("2024-03-10T12:00:00.5+01:00").unwrap().to_rfc3339(), "2024-03-10T11:00:00.500+00:00");
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let mut response = BulkResponse::new();
+        response.created(BulkAction::Index, "logs".to_string(), "1".to_string());
+        assert!(!response.errors);
+        response.failed(BulkAction::Delete, "logs".to_string(), "2".to_string(), "illegal_argument_exception", "no".to_string());
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["errors"], true);
+        assert_eq!(json["items"][0]["index"]["status"], 201);
+        assert_eq!(json["items"][1]["delete"]["error"]["type"], "illegal_argument_exception");
+        assert!(json["items"][0]["index"].get("error").is_none());
+    }
+}
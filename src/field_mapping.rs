@@ -0,0 +1,164 @@
+/// Label and field mapping for push-compatible ingestion
+///
+/// Loki streams carry labels and Elasticsearch `_bulk` documents carry fields;
+/// `FieldMapping` says which of them fill the tenant and stream fields of a
+/// log. Keys are tried in order and the first one present wins.
+use crate::otlp::UNKNOWN_SERVICE;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldMapping {
+    /// Falls back to the tenant header (`X-Scope-OrgID`), then `default_org_id`
+    pub org_id: Vec<String>,
+    /// Falls back to the service
+    pub log_stream_id: Vec<String>,
+    /// Falls back to `unknown_service`
+    pub service: Vec<String>,
+    pub region: Vec<String>,
+    pub log_stream_name: Vec<String>,
+    /// Message of a document (Elasticsearch only; Loki entries carry a line)
+    pub message: Vec<String>,
+    /// RFC 3339 or epoch milliseconds (Elasticsearch only)
+    pub timestamp: Vec<String>,
+    pub default_org_id: String,
+}
+
+/// Tenant and stream fields of one log
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedFields {
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub service: String,
+    pub region: String,
+    pub log_stream_name: String,
+}
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|key| key.to_string()).collect()
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self::loki()
+    }
+}
+
+impl FieldMapping {
+    /// Labels Promtail, Grafana Agent and Vector's Loki sink usually set
+    pub fn loki() -> Self {
+        Self {
+            org_id: keys(&["org_id", "tenant"]),
+            log_stream_id: keys(&["log_stream_id", "job"]),
+            service: keys(&["service_name", "service", "app", "job"]),
+            region: keys(&["region", "cluster"]),
+            log_stream_name: keys(&["log_stream_name", "filename", "pod", "container", "instance", "host"]),
+            message: Vec::new(),
+            timestamp: Vec::new(),
+            default_org_id: "default".to_string(),
+        }
+    }
+
+    /// ECS fields as sent by Filebeat, plus the Fluent Bit and Vector defaults.
+    /// `_index` is the document's target index.
+    pub fn elasticsearch() -> Self {
+        Self {
+            org_id: keys(&["org_id"]),
+            log_stream_id: keys(&["log_stream_id", "_index"]),
+            service: keys(&["service.name", "service", "app"]),
+            region: keys(&["cloud.region", "region"]),
+            log_stream_name: keys(&["log_stream_name", "log.file.path", "host.name", "hostname", "host"]),
+            message: keys(&["message", "log", "msg"]),
+            timestamp: keys(&["@timestamp", "timestamp"]),
+            default_org_id: "default".to_string(),
+        }
+    }
+
+    /// Replace the keys named in a JSON object, e.g. `{"org_id": ["namespace"]}`
+    pub fn with_overrides(self, json: &str) -> Result<Self> {
+        let overrides: serde_json::Value = serde_json::from_str(json).context("mapping is not valid JSON")?;
+        let Some(overrides) = overrides.as_object() else {
+            bail!("mapping must be a JSON object");
+        };
+        let mut mapping = serde_json::to_value(self)?;
+        for (key, value) in overrides {
+            if mapping.get(key).is_none() {
+                bail!("unknown mapping field '{}'", key);
+            }
+            mapping[key] = value.clone();
+        }
+        Ok(serde_json::from_value(mapping)?)
+    }
+
+    /// Map a log through `lookup`, which returns the label or field of a key
+    pub fn map<F>(&self, lookup: F, tenant: Option<&str>) -> MappedFields
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let first = |keys: &[String]| keys.iter().find_map(|key| lookup(key).filter(|value| !value.is_empty()));
+
+        let service = first(&self.service).unwrap_or_else(|| UNKNOWN_SERVICE.to_string());
+        MappedFields {
+            org_id: first(&self.org_id)
+                .or_else(|| tenant.filter(|t| !t.is_empty()).map(str::to_string))
+                .unwrap_or_else(|| self.default_org_id.clone()),
+            log_stream_id: first(&self.log_stream_id).unwrap_or_else(|| service.clone()),
+            region: first(&self.region).unwrap_or_default(),
+            log_stream_name: first(&self.log_stream_name).unwrap_or_default(),
+            service,
+        }
+    }
+
+    /// First present message key
+    pub fn message<F>(&self, lookup: F) -> Option<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.message.iter().find_map(|key| lookup(key))
+    }
+
+    /// First present timestamp key
+    pub fn timestamp<F>(&self, lookup: F) -> Option<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        self.timestamp.iter().find_map(|key| lookup(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_map_labels() {
+        let mapping = FieldMapping::loki();
+        let labels = HashMap::from([("job", "varlogs"), ("filename", "/var/log/syslog"), ("cluster", "eu-1")]);
+        let lookup = |key: &str| labels.get(key).map(|v| v.to_string());
+
+        let fields = mapping.map(lookup, None);
+        assert_eq!(fields.org_id, "default");
+        assert_eq!((fields.log_stream_id.as_str(), fields.service.as_str()), ("varlogs", "varlogs"));
+        assert_eq!((fields.region.as_str(), fields.log_stream_name.as_str()), ("eu-1", "/var/log/syslog"));
+        assert_eq!(mapping.map(lookup, Some("tenant-1")).org_id, "tenant-1");
+
+        let fields = mapping.map(|_| None, Some(""));
+        assert_eq!((fields.org_id.as_str(), fields.service.as_str(), fields.log_stream_id.as_str()), ("default", UNKNOWN_SERVICE, UNKNOWN_SERVICE));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mapping = FieldMapping::elasticsearch()
+            .with_overrides(r#"{"org_id": ["kubernetes.namespace"], "default_org_id": "1"}"#)
+            .unwrap();
+        assert_eq!(mapping.org_id, vec!["kubernetes.namespace"]);
+        assert_eq!(mapping.default_org_id, "1");
+        assert_eq!(mapping.message, FieldMapping::elasticsearch().message);
+
+        assert!(FieldMapping::loki().with_overrides(r#"{"orgid": ["x"]}"#).is_err());
+        assert!(FieldMapping::loki().with_overrides(r#"["x"]"#).is_err());
+        assert!(FieldMapping::loki().with_overrides(r#"{"org_id": "x"}"#).is_err());
+    }
+}
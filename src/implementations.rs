@@ -1,5 +1,6 @@
 use crate::llm_service::LLMServiceClient;
 use crate::log_matcher::{LogMatcher, LogTemplate};
+use crate::loghub_loader::read_raw_lines;
 use crate::traits::{DatasetLoader, GroundTruthEntry, LogMatcherTrait, TemplateGenerator};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -63,7 +64,15 @@ impl Default for RegexLogMatcher {
 
 impl LogMatcherTrait for RegexLogMatcher {
     fn add_template(&mut self, template: LogTemplate) {
-        self.matcher.add_template(template);
+        if let Err(e) = self.matcher.add_template(template) {
+            tracing::warn!("Skipping template: {}", e);
+        }
+    }
+
+    fn add_templates(&mut self, templates: Vec<LogTemplate>) {
+        if let Err(e) = self.matcher.add_templates(templates) {
+            tracing::warn!("Skipping template batch: {}", e);
+        }
     }
 
     fn match_log(&self, log_line: &str) -> Option<u64> {
@@ -74,6 +83,14 @@ impl LogMatcherTrait for RegexLogMatcher {
         self.matcher.match_batch(log_lines)
     }
 
+    fn match_log_bytes(&self, log_line: &[u8]) -> Option<u64> {
+        self.matcher.match_log_bytes(log_line)
+    }
+
+    fn match_batch_bytes(&self, log_lines: &[&[u8]]) -> Vec<Option<u64>> {
+        self.matcher.match_batch_bytes(log_lines)
+    }
+
     fn match_batch_parallel(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
         self.matcher.match_batch_parallel(log_lines)
     }
@@ -141,6 +158,10 @@ impl DatasetLoader for OpenStackDatasetLoader {
         Ok(logs)
     }
 
+    fn load_raw_log_bytes(&self) -> Result<Vec<Vec<u8>>> {
+        read_raw_lines(&format!("{}/OpenStack_2k.log", self.data_dir))
+    }
+
     fn load_ground_truth(&self) -> Result<Vec<GroundTruthEntry>> {
         let path = format!("{}/OpenStack_2k.log_structured.csv", self.data_dir);
         let templates = self.load_template_definitions();
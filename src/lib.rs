@@ -4,6 +4,21 @@ pub mod llm_config;
 pub mod log_format_detector;
 pub mod log_matcher;
 pub mod matcher_config;
+pub mod masking;
+pub mod multiline;
+pub mod matcher_registry;
+pub mod template_validator;
+pub mod template_metadata;
+pub mod parameter_drift;
+pub mod template_frequency;
+pub mod count_anomaly;
+pub mod session_anomaly;
+pub mod otlp;
+pub mod bulk_ingest;
+pub mod field_mapping;
+pub mod loki;
+pub mod es_bulk;
+pub mod syslog;
 pub mod clickhouse_client;
 pub mod buffered_writer;
 
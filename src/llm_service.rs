@@ -3,12 +3,21 @@ use rustc_hash::FxHashMap;
 
 use crate::log_matcher::LogTemplate;
 use crate::llm_config::{MultiLLMConfig, LLMProviderConfig, ConsensusStrategy};
+use crate::masking::{Masker, MaskingConfig};
+use crate::template_metadata::{llm_source, Severity, TemplateMetadata};
+use crate::template_validator::TemplateValidator;
 
 // Removed unused structs: TemplateGenerationRequest, TemplateExample, TemplateGenerationResponse
 
+/// Version of the template prompt, recorded on every LLM-generated template.
+/// Bump when `build_prompt` changes in a way that affects the patterns produced.
+pub const PROMPT_VERSION: &str = "2";
+
 pub struct LLMServiceClient {
     config: MultiLLMConfig,
     http_client: reqwest::Client,
+    validator: TemplateValidator,
+    masker: Masker,
 }
 
 /// Single provider client for making API calls
@@ -18,21 +27,27 @@ struct ProviderClient {
 }
 
 impl ProviderClient {
-    /// Generate template using this provider
-    async fn generate_template(&self, log_line: &str) -> Result<LogTemplate> {
-        match self.config.provider.as_str() {
-            "openai" => self.call_openai(log_line).await,
-            "ollama" => self.call_ollama(log_line).await,
-            "anthropic" => self.call_anthropic(log_line).await,
+    /// Generate template using this provider.
+    /// `masked` is the masked form of the line, passed to the model as a hint.
+    async fn generate_template(&self, log_line: &str, masked: Option<&str>) -> Result<LogTemplate> {
+        let mut template = match self.config.provider.as_str() {
+            "openai" => self.call_openai(log_line, masked).await,
+            "ollama" => self.call_ollama(log_line, masked).await,
+            "anthropic" => self.call_anthropic(log_line, masked).await,
             _ => anyhow::bail!("Unsupported provider: {}", self.config.provider),
-        }
+        }?;
+
+        template.metadata.source = Some(llm_source(&self.config.name));
+        template.metadata.model = Some(self.config.model.clone());
+        template.metadata.prompt_version = Some(PROMPT_VERSION.to_string());
+        Ok(template)
     }
 
-    async fn call_ollama(&self, log_line: &str) -> Result<LogTemplate> {
+    async fn call_ollama(&self, log_line: &str, masked: Option<&str>) -> Result<LogTemplate> {
         let endpoint = self.config.endpoint.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Ollama endpoint not configured"))?;
 
-        let prompt = Self::build_prompt(log_line);
+        let prompt = Self::build_prompt(log_line, masked);
 
         let request_body = serde_json::json!({
             "model": self.config.model,
@@ -59,11 +74,11 @@ impl ProviderClient {
         }
     }
 
-    async fn call_openai(&self, log_line: &str) -> Result<LogTemplate> {
+    async fn call_openai(&self, log_line: &str, masked: Option<&str>) -> Result<LogTemplate> {
         let api_key = self.config.api_key.as_ref()
             .ok_or_else(|| anyhow::anyhow!("OpenAI API key not configured"))?;
 
-        let prompt = Self::build_prompt(log_line);
+        let prompt = Self::build_prompt(log_line, masked);
 
         let request_body = serde_json::json!({
             "model": self.config.model,
@@ -105,11 +120,11 @@ impl ProviderClient {
         }
     }
 
-    async fn call_anthropic(&self, log_line: &str) -> Result<LogTemplate> {
+    async fn call_anthropic(&self, log_line: &str, masked: Option<&str>) -> Result<LogTemplate> {
         let api_key = self.config.api_key.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Anthropic API key not configured"))?;
 
-        let prompt = Self::build_prompt(log_line);
+        let prompt = Self::build_prompt(log_line, masked);
 
         let request_body = serde_json::json!({
             "model": self.config.model,
@@ -150,7 +165,16 @@ impl ProviderClient {
         }
     }
 
-    fn build_prompt(log_line: &str) -> String {
+    fn build_prompt(log_line: &str, masked: Option<&str>) -> String {
+        let masked_hint = match masked {
+            Some(masked) => format!(
+                "MASKED FORM: {}\n(Placeholders like <NUM>, <IP>, <UUID>, <HEX> and <*> mark values known to change - \
+                 each must become a capture group. The pattern must still match the original LOG LINE.)\n\n",
+                masked
+            ),
+            None => String::new(),
+        };
+
         format!(
             r#"Create a regex pattern for this log line by replacing ONLY ephemeral (changing) values with capture groups.
 
@@ -161,10 +185,14 @@ CRITICAL RULES:
 
 LOG LINE: {log_line}
 
+{masked_hint}Also give the event's severity (one of trace, debug, info, warn, error, fatal) and a
+one-line description of what the event means, without any of the variable values.
+
 Respond with ONLY the JSON object, no explanation:
-{{"pattern": "^...$", "variables": [...]}}
+{{"pattern": "^...$", "variables": [...], "severity": "...", "description": "..."}}
 "#,
-            log_line = log_line
+            log_line = log_line,
+            masked_hint = masked_hint
         )
     }
 
@@ -193,7 +221,8 @@ Respond with ONLY the JSON object, no explanation:
                 let pattern = json
                     .get("pattern")
                     .and_then(|v| v.as_str())
-                    .unwrap_or(log_line)
+                    .filter(|p| !p.trim().is_empty())
+                    .ok_or_else(|| anyhow::anyhow!("LLM response has no pattern. Response: {}", llm_output))?
                     .to_string();
 
                 let variables = json
@@ -206,12 +235,30 @@ Respond with ONLY the JSON object, no explanation:
                     })
                     .unwrap_or_else(Vec::new);
 
+                // Severity and description are optional; fall back to the line's level word
+                let severity = json
+                    .get("severity")
+                    .and_then(|v| v.as_str())
+                    .and_then(|s| s.parse::<Severity>().ok())
+                    .or_else(|| Severity::detect(log_line));
+                let description = json
+                    .get("description")
+                    .and_then(|v| v.as_str())
+                    .map(str::trim)
+                    .filter(|d| !d.is_empty())
+                    .map(str::to_string);
+
                 // Use placeholder ID - ClickHouse will assign
                 Ok(LogTemplate {
                     template_id: 0,
                     pattern,
                     variables,
                     example: log_line.to_string(),
+                    metadata: TemplateMetadata {
+                        severity,
+                        description,
+                        ..Default::default()
+                    },
                 })
             }
             Err(e) => {
@@ -247,9 +294,34 @@ impl LLMServiceClient {
                 .timeout(std::time::Duration::from_secs(60))
                 .build()
                 .unwrap_or_else(|_| reqwest::Client::new()),
+            validator: TemplateValidator::default(),
+            masker: Masker::default(),
         })
     }
 
+    /// Use a custom validator for generated templates
+    pub fn with_validator(mut self, validator: TemplateValidator) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Mask log lines before prompt construction (normally the matcher's masking config).
+    /// The masked form is sent alongside the raw line so the model sees which
+    /// values vary; generated patterns are still validated against the raw line.
+    pub fn with_masking(mut self, masking: &MaskingConfig) -> Self {
+        self.masker = Masker::new(masking);
+        self
+    }
+
+    /// Generate a template with one provider and reject it if it fails linting
+    async fn generate_validated(&self, client: &ProviderClient, log_line: &str) -> Result<LogTemplate> {
+        let masked = self.masker.apply(log_line);
+        let hint = Some(masked.as_ref()).filter(|masked| *masked != log_line);
+        let template = client.generate_template(log_line, hint).await?;
+        self.validator.validate(&template)?;
+        Ok(template)
+    }
+
     /// Create from legacy single provider (backward compatibility)
     pub fn new(provider: String, api_key: String, model: String) -> Self {
         let ollama_endpoint = std::env::var("OLLAMA_ENDPOINT")
@@ -278,25 +350,30 @@ impl LLMServiceClient {
 
         match self.config.consensus_strategy {
             ConsensusStrategy::FirstSuccess => {
-                // Try providers in order until one succeeds
+                // Try providers in order until one returns a valid template
+                let mut last_error = None;
                 for provider_config in &self.config.providers {
                     let client = ProviderClient {
                         config: provider_config.clone(),
                         http_client: self.http_client.clone(),
                     };
 
-                    match client.generate_template(log_line).await {
+                    match self.generate_validated(&client, log_line).await {
                         Ok(template) => {
                             tracing::debug!("Provider {} succeeded", provider_config.name);
                             return Ok(template);
                         }
                         Err(e) => {
                             tracing::warn!("Provider {} failed: {}", provider_config.name, e);
+                            last_error = Some(e);
                             continue;
                         }
                     }
                 }
-                anyhow::bail!("All LLM providers failed")
+                match last_error {
+                    Some(e) => anyhow::bail!("All LLM providers failed, last error: {}", e),
+                    None => anyhow::bail!("All LLM providers failed"),
+                }
             }
             _ => {
                 // Call all providers in parallel
@@ -317,7 +394,7 @@ impl LLMServiceClient {
             };
             let log_line = log_line.to_string();
             async move {
-                (provider_config.name.clone(), client.generate_template(&log_line).await)
+                (provider_config.name.clone(), self.generate_validated(&client, &log_line).await)
             }
         }).collect();
 
@@ -589,3 +666,24 @@ impl ProviderClient {
         Ok(classifications)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_llm_response_metadata() {
+        let line = "ERROR db: connection to 10.0.0.5 timed out";
+        let output = r#"Here you go: {"pattern": "^ERROR db: connection to (\\S+) timed out$", "variables": ["host"], "severity": "error", "description": "Database connection timed out"}"#;
+        let template = ProviderClient::parse_llm_response(line, output).unwrap();
+        assert_eq!(template.variables, ["host"]);
+        assert_eq!(template.metadata.severity, Some(Severity::Error));
+        assert_eq!(template.metadata.description.as_deref(), Some("Database connection timed out"));
+
+        // Without a severity the line's level word is used
+        let output = r#"{"pattern": "^ERROR db: connection to (\\S+) timed out$", "variables": ["host"]}"#;
+        let template = ProviderClient::parse_llm_response(line, output).unwrap();
+        assert_eq!(template.metadata.severity, Some(Severity::Error));
+        assert_eq!(template.metadata.description, None);
+    }
+}
@@ -1,5 +1,9 @@
 /// Detects the format of log lines and extracts structural patterns
+use anyhow::Result;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum LogFormat {
@@ -78,6 +82,168 @@ impl LogFormatDetector {
     }
 }
 
+/// Field holding the log message; templates only ever see this part
+pub const CONTENT_FIELD: &str = "Content";
+
+/// Header layout given as a LogHub format string, e.g.
+/// `<Date> <Time> <Pid> <Level> <Component>: <Content>`.
+///
+/// `<Name>` marks a field, anything else is literal text (a run of spaces
+/// matches any whitespace). `<Content>` must appear exactly once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HeaderFormat {
+    format: String,
+    regex: Regex,
+    /// Field names in capture group order (group i + 1)
+    fields: Vec<String>,
+}
+
+/// A log line split by a `HeaderFormat`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedLine<'a> {
+    /// The `<Content>` field
+    pub content: &'a str,
+    /// All other fields by name
+    pub headers: HashMap<String, String>,
+}
+
+impl HeaderFormat {
+    /// Compile a LogHub format string
+    pub fn parse(format: &str) -> Result<Self> {
+        let field = Regex::new(r"<([^<>]+)>").unwrap();
+        let spaces = Regex::new(r" +").unwrap();
+
+        let mut pattern = String::from("^");
+        let mut fields: Vec<String> = Vec::new();
+        let mut last_end = 0;
+
+        let push_literal = |pattern: &mut String, literal: &str| {
+            pattern.push_str(&spaces.replace_all(&regex::escape(literal), r"\s+"));
+        };
+
+        for caps in field.captures_iter(format) {
+            let whole = caps.get(0).unwrap();
+            push_literal(&mut pattern, &format[last_end..whole.start()]);
+
+            let name = caps[1].trim().to_string();
+            if name.is_empty() {
+                anyhow::bail!("empty field name in log format '{}'", format);
+            }
+            if fields.contains(&name) {
+                anyhow::bail!("field <{}> appears twice in log format '{}'", name, format);
+            }
+            pattern.push_str("(.*?)");
+            fields.push(name);
+            last_end = whole.end();
+        }
+        push_literal(&mut pattern, &format[last_end..]);
+        pattern.push('$');
+
+        if !fields.iter().any(|f| f == CONTENT_FIELD) {
+            anyhow::bail!("log format '{}' has no <{}> field", format, CONTENT_FIELD);
+        }
+
+        Ok(Self {
+            format: format.to_string(),
+            regex: Regex::new(&pattern)?,
+            fields,
+        })
+    }
+
+    /// Header formats of the LogHub benchmark datasets (None for unknown
+    /// datasets and for those whose headers have optional parts)
+    pub fn loghub(dataset: &str) -> Option<Self> {
+        let format = match dataset {
+            "HDFS" => "<Date> <Time> <Pid> <Level> <Component>: <Content>",
+            "Hadoop" => "<Date> <Time> <Level> [<Process>] <Component>: <Content>",
+            "Spark" => "<Date> <Time> <Level> <Component>: <Content>",
+            "Zookeeper" => "<Date> <Time> - <Level>  [<Node>:<Component>@<Id>] - <Content>",
+            "BGL" => "<Label> <Timestamp> <Date> <Node> <Time> <NodeRepeat> <Type> <Component> <Level> <Content>",
+            "HPC" => "<LogId> <Node> <Component> <State> <Time> <Flag> <Content>",
+            "Windows" => "<Date> <Time>, <Level> <Component> <Content>",
+            "Android" => "<Date> <Time> <Pid> <Tid> <Level> <Component>: <Content>",
+            "HealthApp" => "<Time>|<Component>|<Pid>|<Content>",
+            "Apache" => "[<Time>] [<Level>] <Content>",
+            "Proxifier" => "[<Time>] <Program> - <Content>",
+            "OpenSSH" => "<Date> <Day> <Time> <Component> sshd[<Pid>]: <Content>",
+            "OpenStack" => "<Logrecord> <Date> <Time> <Pid> <Level> <Component> [<ADDR>] <Content>",
+            _ => return None,
+        };
+        Some(Self::parse(format).expect("built-in LogHub format"))
+    }
+
+    /// The format string this was compiled from
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Split a line into header fields and content.
+    /// Returns None if the line doesn't follow the format.
+    ///
+    /// Multi-line records (see `multiline`) are parsed on their first line;
+    /// when `<Content>` ends that line, the continuation lines are part of the content.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Option<ParsedLine<'a>> {
+        let head = line.split('\n').next().unwrap_or(line).trim_end_matches('\r');
+        let caps = self.regex.captures(head)?;
+        let mut content = "";
+        let mut headers = HashMap::with_capacity(self.fields.len() - 1);
+
+        for (i, name) in self.fields.iter().enumerate() {
+            let Some(value) = caps.get(i + 1) else {
+                headers.insert(name.clone(), String::new());
+                continue;
+            };
+            if name == CONTENT_FIELD {
+                content = if value.end() == head.len() {
+                    &line[value.start()..]
+                } else {
+                    value.as_str()
+                };
+            } else {
+                headers.insert(name.clone(), value.as_str().to_string());
+            }
+        }
+
+        Some(ParsedLine { content, headers })
+    }
+
+    /// The content of a line, or the whole line if it doesn't follow the format
+    pub fn content<'a>(&self, line: &'a str) -> &'a str {
+        self.parse_line(line).map(|parsed| parsed.content).unwrap_or(line)
+    }
+}
+
+impl PartialEq for HeaderFormat {
+    fn eq(&self, other: &Self) -> bool {
+        self.format == other.format
+    }
+}
+
+impl fmt::Display for HeaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format)
+    }
+}
+
+impl TryFrom<String> for HeaderFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(format: String) -> Result<Self> {
+        Self::parse(&format)
+    }
+}
+
+impl From<HeaderFormat> for String {
+    fn from(format: HeaderFormat) -> Self {
+        format.format
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SyslogComponents {
     pub timestamp: String,
@@ -121,6 +287,62 @@ mod tests {
         assert_eq!(components.message, "authentication failure; logname=");
     }
 
+    #[test]
+    fn test_header_format() {
+        let format = HeaderFormat::parse("<Date> <Time> <Pid> <Level> <Component>: <Content>").unwrap();
+        assert_eq!(format.fields(), ["Date", "Time", "Pid", "Level", "Component", "Content"]);
+
+        let line = "081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 for block blk_38865049064139660 terminating";
+        let parsed = format.parse_line(line).unwrap();
+        assert_eq!(parsed.content, "PacketResponder 1 for block blk_38865049064139660 terminating");
+        assert_eq!(parsed.headers["Date"], "081109");
+        assert_eq!(parsed.headers["Level"], "INFO");
+        assert_eq!(parsed.headers["Component"], "dfs.DataNode$PacketResponder");
+        assert!(!parsed.headers.contains_key("Content"));
+
+        // Lines that don't follow the format are passed through whole
+        assert!(format.parse_line("garbage").is_none());
+        assert_eq!(format.content("garbage"), "garbage");
+        assert_eq!(format.content(line), parsed.content);
+
+        // Multi-line records are parsed on their first line and keep the rest as content
+        let record = "081109 203615 148 WARN dfs.DataNode: Exception in receiveBlock\njava.io.IOException: closed\n\tat dfs.DataNode.run(DataNode.java:42)";
+        let parsed = format.parse_line(record).unwrap();
+        assert_eq!(parsed.headers["Level"], "WARN");
+        assert_eq!(
+            parsed.content,
+            "Exception in receiveBlock\njava.io.IOException: closed\n\tat dfs.DataNode.run(DataNode.java:42)"
+        );
+    }
+
+    #[test]
+    fn test_header_format_literals_and_presets() {
+        // Brackets are literal text; fields may contain spaces when the next literal bounds them
+        let apache = HeaderFormat::loghub("Apache").unwrap();
+        let parsed = apache
+            .parse_line("[Sun Dec 04 04:47:44 2005] [notice] workerEnv.init() ok /etc/httpd/conf/workers2.properties")
+            .unwrap();
+        assert_eq!(parsed.headers["Time"], "Sun Dec 04 04:47:44 2005");
+        assert_eq!(parsed.headers["Level"], "notice");
+        assert_eq!(parsed.content, "workerEnv.init() ok /etc/httpd/conf/workers2.properties");
+
+        let openstack = HeaderFormat::loghub("OpenStack").unwrap();
+        let parsed = openstack
+            .parse_line("nova-api.log.1.2017-05-16_13:53:08 2017-05-16 00:00:00.008 25746 INFO nova.osapi_compute.wsgi.server [req-38101a0b-2096-447d-96ea-a692162415ae 113d3a99c3da401fbd62cc2caa5b96d2 54fadb412c4e40cdbaed9335e4c35a9e - - -] 10.11.10.1 \"GET /v2/54fadb412c4e40cdbaed9335e4c35a9e/servers/detail HTTP/1.1\" status: 200 len: 1893 time: 0.2477829")
+            .unwrap();
+        assert_eq!(parsed.headers["Pid"], "25746");
+        assert!(parsed.content.starts_with("10.11.10.1 \"GET"));
+
+        assert!(HeaderFormat::loghub("Unknown").is_none());
+        assert!(HeaderFormat::parse("<Date> <Time> <Level>").is_err());
+        assert!(HeaderFormat::parse("<Level> <Level> <Content>").is_err());
+
+        // Serialized as the format string
+        let json = serde_json::to_string(&apache).unwrap();
+        assert_eq!(json, "\"[<Time>] [<Level>] <Content>\"");
+        assert_eq!(serde_json::from_str::<HeaderFormat>(&json).unwrap(), apache);
+    }
+
     #[test]
     fn test_extract_syslog_no_pid() {
         let log = "Jul 27 14:41:58 combo kernel: PCI: Using configuration type 1";
@@ -9,27 +9,100 @@
 //!
 //! Expected improvement: 20-40% faster than non-optimized version
 
+use crate::masking::Masker;
 use crate::matcher_config::MatcherConfig;
+use crate::template_metadata::{TemplateMetadata, SOURCE_BUILTIN};
+use crate::token_classifier::{log_type_id, template_log_type};
 use aho_corasick::AhoCorasick;
+use chrono::{DateTime, Utc};
 use arc_swap::ArcSwap;
-use regex::Regex;
+use regex::{CaptureLocations, Regex};
 use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::sync::{
-    atomic::{AtomicU64, Ordering},
+    atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
     Arc,
 };
 
 // Thread-local scratch space for zero-copy matching
 thread_local! {
     static SCRATCH: RefCell<ScratchSpace> = RefCell::new(ScratchSpace::new());
+    // Byte regexes for verifying non-UTF-8 lines; separate from SCRATCH because
+    // verification runs while SCRATCH is borrowed
+    static BYTES_PATTERNS: RefCell<FxHashMap<u64, regex::bytes::Regex>> = RefCell::new(FxHashMap::default());
+    // Hit counter shard used by this thread, assigned round-robin
+    static HIT_SHARD: usize = NEXT_HIT_SHARD.fetch_add(1, Ordering::Relaxed) % HIT_SHARDS;
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// Head line of a multi-line record (see `multiline`), None for single lines.
+/// Templates usually describe the head line; the rest is trace detail.
+pub(crate) fn head_line(text: &str) -> Option<&str> {
+    text.find('\n').map(|end| text[..end].trim_end_matches('\r'))
+}
+
+fn head_line_bytes(text: &[u8]) -> Option<&[u8]> {
+    let end = text.iter().position(|&b| b == b'\n')?;
+    Some(text[..end].strip_suffix(b"\r").unwrap_or(&text[..end]))
+}
+
+/// Counter shards per template; each shard sits on its own cache line so
+/// threads counting hits for the same hot template don't contend
+const HIT_SHARDS: usize = 4;
+static NEXT_HIT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+#[repr(align(64))]
+struct PaddedCounter(AtomicU64);
+
+/// Hit count and first/last match time for one template.
+/// Shared by `Arc` across snapshots, so counts survive template updates.
+#[derive(Default)]
+struct TemplateCounter {
+    hits: [PaddedCounter; HIT_SHARDS],
+    // Unix millis, 0 = never matched
+    first_seen_ms: AtomicI64,
+    last_seen_ms: AtomicI64,
+}
+
+impl TemplateCounter {
+    #[inline]
+    fn record(&self, shard: usize, now_ms: i64) {
+        self.hits[shard].0.fetch_add(1, Ordering::Relaxed);
+        // Only write the shared timestamps when they change (at most once per ms)
+        if self.last_seen_ms.load(Ordering::Relaxed) < now_ms {
+            self.last_seen_ms.fetch_max(now_ms, Ordering::Relaxed);
+        }
+        if self.first_seen_ms.load(Ordering::Relaxed) == 0 {
+            let _ = self.first_seen_ms.compare_exchange(0, now_ms, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self, template_id: u64) -> TemplateStats {
+        let to_time = |ms: i64| match ms {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        };
+        TemplateStats {
+            template_id,
+            hits: self.hits.iter().map(|shard| shard.0.load(Ordering::Relaxed)).sum(),
+            first_seen: to_time(self.first_seen_ms.load(Ordering::Relaxed)),
+            last_seen: to_time(self.last_seen_ms.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 struct ScratchSpace {
     template_matches: FxHashMap<u64, FxHashSet<u32>>,
     candidates: Vec<(u64, usize, usize)>,
+    // Reusable capture slots per template, kept across calls (not cleared)
+    capture_locations: FxHashMap<u64, CaptureLocations>,
 }
 
 impl ScratchSpace {
@@ -37,6 +110,7 @@ impl ScratchSpace {
         Self {
             template_matches: FxHashMap::default(),
             candidates: Vec::with_capacity(32),
+            capture_locations: FxHashMap::default(),
         }
     }
 
@@ -51,17 +125,102 @@ static TOKENIZER: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
     Regex::new(r#"(?:://)|(?:(?:[\s'`";=()\[\]{}?@&<>:\n\t\r,])|(?:[\.](\s+|$))|(?:\\["']))+"#).unwrap()
 });
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LogTemplate {
     pub template_id: u64,
     pub pattern: String,
     pub variables: Vec<String>,
     pub example: String,
+    /// Severity, description, tags and provenance
+    #[serde(default)]
+    pub metadata: TemplateMetadata,
+}
+
+/// A matched template together with the values captured for its variables
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtractedMatch {
+    pub template_id: u64,
+    /// Variable name -> captured value
+    pub variables: HashMap<String, String>,
+}
+
+/// A template fragment with its specificity weight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FragmentWeight {
+    pub fragment: String,
+    pub weight: f64,
+}
+
+/// Why a template was (or was not) chosen for a log line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CandidateExplanation {
+    pub template_id: u64,
+    pub pattern: String,
+    /// Matched fragment weight / total fragment weight
+    pub score: f64,
+    pub above_threshold: bool,
+    /// Result of the regex check; None when verification is off or the candidate was not checked
+    pub regex_verified: Option<bool>,
+    pub matched_fragments: Vec<FragmentWeight>,
+    pub missing_fragments: Vec<FragmentWeight>,
+}
+
+/// Full scoring breakdown for one log line, candidates ordered best first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchExplanation {
+    pub log_line: String,
+    /// The line as the fragment search saw it, when masking changed it
+    pub masked_line: Option<String>,
+    pub matched_template_id: Option<u64>,
+    pub fragment_match_threshold: f64,
+    pub verify_with_regex: bool,
+    pub candidates: Vec<CandidateExplanation>,
+}
+
+/// Match counters for one template since the matcher was created
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateStats {
+    pub template_id: u64,
+    pub hits: u64,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Templates grouped by log type: level 1 of the `token_classifier` hierarchy,
+/// with the templates as level 2
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogType {
+    pub log_type_id: u64,
+    /// Static keywords of the template examples (see `token_classifier::template_log_type`)
+    pub signature: String,
+    pub template_ids: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// The other template's regex matches this template's example
+    ExampleMatchedByOther,
+    /// Every fragment of this template is also a fragment of the other,
+    /// so a line matching the other scores fully for this one too
+    SharedFragments,
+    /// The two regexes are the same after normalization (group names, anchors)
+    EquivalentRegex,
+}
+
+/// A pair of templates that can claim the same log lines
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateConflict {
+    pub template_id: u64,
+    pub other_template_id: u64,
+    pub kind: ConflictKind,
 }
 
 // Most templates have < 8 fragments, so we stack-allocate
 type SmallFragmentVec = SmallVec<[u32; 8]>;
 type SmallTemplateVec = SmallVec<[(u64, usize); 4]>;
+// (log type id, log type signature)
+type LogTypeEntry = (u64, Arc<str>);
 
 #[derive(Clone)]
 struct MatcherSnapshot {
@@ -72,16 +231,20 @@ struct MatcherSnapshot {
     fragment_string_to_id: FxHashMap<String, u32>,
     fragment_weights: FxHashMap<u32, f64>,  // Fragment specificity weights
     next_fragment_id: u32,
+    // Fragment ids in Aho-Corasick pattern order (pattern index -> fragment id)
+    automaton_fragments: Vec<u32>,
     patterns: FxHashMap<u64, Arc<Regex>>,
     templates: FxHashMap<u64, Arc<LogTemplate>>,
+    disabled: FxHashSet<u64>,
+    counters: FxHashMap<u64, Arc<TemplateCounter>>,
+    // Template id -> log type
+    log_types: FxHashMap<u64, LogTypeEntry>,
+    // Compiled `config.masking`, applied to log lines and fragments alike
+    masker: Arc<Masker>,
     config: MatcherConfig,
 }
 
 impl MatcherSnapshot {
-    fn new() -> Self {
-        Self::with_config(MatcherConfig::default())
-    }
-
     fn with_config(config: MatcherConfig) -> Self {
         Self {
             ac: Arc::new(AhoCorasick::new(&[""] as &[&str]).unwrap()),
@@ -91,26 +254,117 @@ impl MatcherSnapshot {
             fragment_string_to_id: FxHashMap::default(),
             fragment_weights: FxHashMap::default(),
             next_fragment_id: 0,
+            automaton_fragments: Vec::new(),
             patterns: FxHashMap::default(),
             templates: FxHashMap::default(),
+            disabled: FxHashSet::default(),
+            counters: FxHashMap::default(),
+            log_types: FxHashMap::default(),
+            masker: Arc::new(Masker::new(&config.masking)),
             config,
         }
     }
 
+    /// Count hits for matched templates; `now_ms` is taken once per batch
+    fn record_hits<I>(&self, template_ids: I, now_ms: i64)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let shard = HIT_SHARD.with(|shard| *shard);
+        for template_id in template_ids {
+            if let Some(counter) = self.counters.get(&template_id) {
+                counter.record(shard, now_ms);
+            }
+        }
+    }
+
+    fn template_stats(&self) -> Vec<TemplateStats> {
+        let mut stats: Vec<TemplateStats> = self
+            .counters
+            .iter()
+            .map(|(&template_id, counter)| counter.stats(template_id))
+            .collect();
+        stats.sort_by_key(|s| s.template_id);
+        stats
+    }
+
+    fn log_types(&self) -> Vec<LogType> {
+        let mut by_id: FxHashMap<u64, LogType> = FxHashMap::default();
+        for (&template_id, (log_type_id, signature)) in &self.log_types {
+            by_id
+                .entry(*log_type_id)
+                .or_insert_with(|| LogType {
+                    log_type_id: *log_type_id,
+                    signature: signature.to_string(),
+                    template_ids: Vec::new(),
+                })
+                .template_ids
+                .push(template_id);
+        }
+        let mut log_types: Vec<LogType> = by_id.into_values().collect();
+        for log_type in &mut log_types {
+            log_type.template_ids.sort_unstable();
+        }
+        log_types.sort_by_key(|t| t.log_type_id);
+        log_types
+    }
+
+    /// Error if a template's ID is taken by a different pattern, here or
+    /// earlier in the same batch
+    fn check_id_collisions<'a>(&self, templates: impl IntoIterator<Item = &'a LogTemplate>) -> anyhow::Result<()> {
+        let mut batch: FxHashMap<u64, &str> = FxHashMap::default();
+        for template in templates {
+            let existing = batch
+                .get(&template.template_id)
+                .copied()
+                .or_else(|| self.templates.get(&template.template_id).map(|t| t.pattern.as_str()));
+            if let Some(existing) = existing {
+                ensure_same_pattern(template.template_id, existing, &template.pattern)?;
+            }
+            batch.insert(template.template_id, &template.pattern);
+        }
+        Ok(())
+    }
+
     fn add_template(mut self, template: LogTemplate) -> Self {
+        self.insert_template(template);
+        self.rebuild_automaton();
+        self
+    }
+
+    /// Add many templates with a single fragment table / automaton build
+    fn add_templates(mut self, templates: impl IntoIterator<Item = LogTemplate>) -> Self {
+        for template in templates {
+            self.insert_template(template);
+        }
+        self.rebuild_automaton();
+        self
+    }
+
+    /// Register a template's regex and fragments without rebuilding the automaton
+    fn insert_template(&mut self, template: LogTemplate) {
         let template_id = template.template_id;
-        let fragments = extract_fragments(&template.pattern, self.config.min_fragment_length);
+        let fragments = self.pattern_fragments(&template.pattern);
 
         // Note: Path compression not feasible with Aho-Corasick
         // AC searches for literal strings, but merging fragments includes regex parts
         // like "(\d+)" which don't appear in actual logs
         // The weighted scoring already handles generic fragments effectively
 
-        if let Ok(regex) = Regex::new(&template.pattern) {
-            self.patterns.insert(template_id, Arc::new(regex));
+        match Regex::new(&template.pattern) {
+            Ok(regex) => {
+                self.patterns.insert(template_id, Arc::new(regex));
+            }
+            Err(_) => {
+                // Don't keep a stale regex around when a template is replaced
+                self.patterns.remove(&template_id);
+            }
         }
 
+        let log_type = classify_log_type(self.patterns.get(&template_id).map(|r| r.as_ref()), &template);
+        self.log_types.insert(template_id, log_type);
         self.templates.insert(template_id, Arc::new(template));
+        self.counters.entry(template_id).or_default();
 
         let mut fragment_ids = SmallFragmentVec::new();
         for frag in &fragments {
@@ -129,16 +383,93 @@ impl MatcherSnapshot {
 
                     id
                 };
-                fragment_ids.push(frag_id);
+                // Masking can turn two fragments into the same string
+                if !fragment_ids.contains(&frag_id) {
+                    fragment_ids.push(frag_id);
+                }
             }
         }
 
-        self.template_fragments.insert(template_id, fragment_ids.clone());
+        self.template_fragments.insert(template_id, fragment_ids);
+    }
+
+    /// Literal fragments of a pattern, masked the same way as log lines
+    fn pattern_fragments(&self, pattern: &str) -> Vec<String> {
+        let fragments = extract_fragments(pattern, self.config.min_fragment_length);
+        if !self.masker.is_enabled() {
+            return fragments;
+        }
+        fragments
+            .into_iter()
+            .map(|fragment| self.masker.apply(&fragment).into_owned())
+            .collect()
+    }
+
+    fn remove_template(mut self, template_id: u64) -> Self {
+        if self.detach_template(template_id) {
+            self.rebuild_automaton();
+        }
+        self
+    }
+
+    /// Swap template `template_id` for `template` (which may carry another ID)
+    /// with a single automaton build. The disabled flag and hit counts move
+    /// to the replacement.
+    fn replace_template(mut self, template_id: u64, template: LogTemplate) -> Self {
+        let disabled = self.disabled.contains(&template_id);
+        let counter = self.counters.get(&template_id).cloned();
+        self.detach_template(template_id);
+
+        let new_id = template.template_id;
+        self.insert_template(template);
+        if let Some(counter) = counter {
+            self.counters.insert(new_id, counter);
+        }
+        if disabled {
+            self.disabled.insert(new_id);
+        }
+        self.rebuild_automaton();
+        self
+    }
+
+    /// Drop a template from the tables without rebuilding the automaton;
+    /// false if it doesn't exist
+    fn detach_template(&mut self, template_id: u64) -> bool {
+        if self.templates.remove(&template_id).is_none() {
+            return false;
+        }
+        self.patterns.remove(&template_id);
+        self.template_fragments.remove(&template_id);
+        self.disabled.remove(&template_id);
+        self.counters.remove(&template_id);
+        self.log_types.remove(&template_id);
+        true
+    }
+
+    fn set_disabled(mut self, template_id: u64, disabled: bool) -> Self {
+        let changed = if disabled {
+            self.disabled.insert(template_id)
+        } else {
+            self.disabled.remove(&template_id)
+        };
+        if changed {
+            self.rebuild_automaton();
+        }
+        self
+    }
 
+    /// Rebuild the fragment -> template table and the Aho-Corasick automaton
+    /// from `template_fragments`. Disabled templates are left out of the automaton;
+    /// fragments no template references anymore are dropped.
+    fn rebuild_automaton(&mut self) {
         use std::collections::HashMap;
+        self.prune_fragments();
         let mut fragment_id_map: HashMap<u32, SmallTemplateVec> = HashMap::new();
 
         for (tid, frag_ids) in self.template_fragments.iter() {
+            if self.disabled.contains(tid) {
+                continue;
+            }
             for (frag_idx, &frag_id) in frag_ids.iter().enumerate() {
                 fragment_id_map
                     .entry(frag_id)
@@ -171,19 +502,64 @@ impl MatcherSnapshot {
             {
                 self.ac = Arc::new(ac);
             }
+        } else {
+            self.ac = Arc::new(AhoCorasick::new(&[""] as &[&str]).unwrap());
         }
 
-        self
+        self.automaton_fragments = unique_fragment_ids;
+    }
+
+    /// Drop fragments left behind by removed or replaced templates. Disabled
+    /// templates keep theirs, so re-enabling them needs no re-registration.
+    fn prune_fragments(&mut self) {
+        let live: FxHashSet<u32> = self.template_fragments.values().flatten().copied().collect();
+        if live.len() == self.fragment_id_to_string.len() {
+            return;
+        }
+        self.fragment_id_to_string.retain(|id, _| live.contains(id));
+        self.fragment_string_to_id.retain(|_, id| live.contains(id));
+        self.fragment_weights.retain(|id, _| live.contains(id));
     }
 
+    /// Fragment search runs on the masked line; regex verification on the raw one
     #[inline]
     fn match_log(&self, log_line: &str) -> Option<u64> {
+        let haystack = self.masker.apply(log_line);
+        self.match_haystack(haystack.as_bytes(), |template_id| self.verify_candidate(template_id, log_line))
+    }
+
+    /// Byte variant of `match_log`. Fragment scoring runs on the raw bytes; with
+    /// regex verification or masking on, valid UTF-8 takes the `&str` path so both agree.
+    #[inline]
+    fn match_log_bytes(&self, log_line: &[u8]) -> Option<u64> {
+        if self.config.verify_with_regex || self.masker.is_enabled() {
+            if let Ok(line) = std::str::from_utf8(log_line) {
+                return self.match_log(line);
+            }
+        }
+        if self.masker.is_enabled() {
+            let lossy = String::from_utf8_lossy(log_line);
+            let haystack = self.masker.apply(&lossy);
+            return self.match_haystack(haystack.as_bytes(), |template_id| {
+                self.verify_candidate_bytes(template_id, log_line)
+            });
+        }
+        self.match_haystack(log_line, |template_id| self.verify_candidate_bytes(template_id, log_line))
+    }
+
+    /// Score templates on fragment hits in `haystack` and pick the best one.
+    /// `verify` is only called in verification mode, for the top candidates.
+    #[inline]
+    fn match_haystack<F>(&self, haystack: &[u8], verify: F) -> Option<u64>
+    where
+        F: Fn(u64) -> bool,
+    {
         // Use thread-local scratch space to avoid allocations
         SCRATCH.with(|scratch| {
             let mut scratch = scratch.borrow_mut();
             scratch.clear();
 
-            for mat in self.ac.find_iter(log_line) {
+            for mat in self.ac.find_iter(haystack) {
                 if let Some(template_list) = self.fragment_to_template.get(&mat.pattern().as_usize()) {
                     for &(template_id, fragment_idx) in template_list {
                         if let Some(required_fragments) = self.template_fragments.get(&template_id) {
@@ -233,23 +609,7 @@ impl MatcherSnapshot {
                 .iter()
                 .filter_map(|(template_id, matched_fragments)| {
                     self.template_fragments.get(template_id).map(|required| {
-                        let matched_weight: f64 = matched_fragments
-                            .iter()
-                            .filter_map(|frag_id| self.fragment_weights.get(frag_id))
-                            .sum();
-
-                        let total_weight: f64 = required
-                            .iter()
-                            .filter_map(|frag_id| self.fragment_weights.get(frag_id))
-                            .sum();
-
-                        let weighted_score = if total_weight > 0.0 {
-                            matched_weight / total_weight
-                        } else {
-                            matched_fragments.len() as f64 / required.len().max(1) as f64
-                        };
-
-                        (*template_id, weighted_score)
+                        (*template_id, self.weighted_score(matched_fragments, required))
                     })
                 })
                 .collect();
@@ -259,16 +619,314 @@ impl MatcherSnapshot {
             });
 
             // Return best match if score meets threshold
-            for (template_id, score) in scored_candidates {
-                if score >= self.config.fragment_match_threshold {
-                    return Some(template_id);
+            if !self.config.verify_with_regex {
+                for (template_id, score) in scored_candidates {
+                    if score >= self.config.fragment_match_threshold {
+                        return Some(template_id);
+                    }
+                }
+
+                return None;
+            }
+
+            // Verification mode: confirm the top candidates against their regex,
+            // falling through to the next candidate when the check fails
+            scored_candidates
+                .into_iter()
+                .filter(|&(_, score)| score >= self.config.fragment_match_threshold)
+                .take(self.config.max_verify_candidates)
+                .find(|&(template_id, _)| verify(template_id))
+                .map(|(template_id, _)| template_id)
+        })
+    }
+
+    /// Matched fragment weight over total fragment weight for one template
+    #[inline]
+    fn weighted_score(&self, matched_fragments: &FxHashSet<u32>, required: &[u32]) -> f64 {
+        let matched_weight: f64 = matched_fragments
+            .iter()
+            .filter_map(|frag_id| self.fragment_weights.get(frag_id))
+            .sum();
+
+        let total_weight: f64 = required
+            .iter()
+            .filter_map(|frag_id| self.fragment_weights.get(frag_id))
+            .sum();
+
+        if total_weight > 0.0 {
+            matched_weight / total_weight
+        } else {
+            // Fallback to simple ratio if no weights
+            matched_fragments.len() as f64 / required.len().max(1) as f64
+        }
+    }
+
+    /// Score every template that shares a fragment with the log line.
+    /// Debug path: allocates freely and does not touch the scratch space.
+    fn explain_match(&self, log_line: &str) -> MatchExplanation {
+        let mut template_matches: FxHashMap<u64, FxHashSet<u32>> = FxHashMap::default();
+        let masked = self.masker.apply(log_line);
+
+        for mat in self.ac.find_iter(masked.as_ref()) {
+            if let Some(template_list) = self.fragment_to_template.get(&mat.pattern().as_usize()) {
+                for &(template_id, fragment_idx) in template_list {
+                    if let Some(&fragment_id) = self
+                        .template_fragments
+                        .get(&template_id)
+                        .and_then(|required| required.get(fragment_idx))
+                    {
+                        template_matches.entry(template_id).or_default().insert(fragment_id);
+                    }
+                }
+            }
+        }
+
+        let threshold = self.config.fragment_match_threshold;
+        let verify = self.config.verify_with_regex;
+
+        let mut candidates: Vec<CandidateExplanation> = template_matches
+            .iter()
+            .filter_map(|(&template_id, matched)| {
+                let required = self.template_fragments.get(&template_id)?;
+                let score = self.weighted_score(matched, required);
+
+                let mut matched_fragments = Vec::new();
+                let mut missing_fragments = Vec::new();
+                for frag_id in required {
+                    let fragment = FragmentWeight {
+                        fragment: self.fragment_id_to_string.get(frag_id).cloned().unwrap_or_default(),
+                        weight: self.fragment_weights.get(frag_id).copied().unwrap_or(0.0),
+                    };
+                    if matched.contains(frag_id) {
+                        matched_fragments.push(fragment);
+                    } else {
+                        missing_fragments.push(fragment);
+                    }
+                }
+
+                Some(CandidateExplanation {
+                    template_id,
+                    pattern: self
+                        .templates
+                        .get(&template_id)
+                        .map(|t| t.pattern.clone())
+                        .unwrap_or_default(),
+                    score,
+                    above_threshold: score >= threshold,
+                    regex_verified: None,
+                    matched_fragments,
+                    missing_fragments,
+                })
+            })
+            .collect();
+
+        candidates.sort_unstable_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.template_id.cmp(&b.template_id))
+        });
+
+        // Mirror match_log: only the top above-threshold candidates get a regex check
+        if verify {
+            for candidate in candidates
+                .iter_mut()
+                .filter(|c| c.above_threshold)
+                .take(self.config.max_verify_candidates)
+            {
+                candidate.regex_verified = Some(self.verify_candidate(candidate.template_id, log_line));
+            }
+        }
+
+        MatchExplanation {
+            log_line: log_line.to_string(),
+            masked_line: match masked {
+                std::borrow::Cow::Owned(masked) => Some(masked),
+                std::borrow::Cow::Borrowed(_) => None,
+            },
+            matched_template_id: self.match_log(log_line),
+            fragment_match_threshold: threshold,
+            verify_with_regex: verify,
+            candidates,
+        }
+    }
+
+    /// Conflict probe for a template in this snapshot (fragments from the tables)
+    fn conflict_probe<'a>(&self, template: &'a LogTemplate) -> ConflictProbe<'a> {
+        let fragments = self
+            .template_fragments
+            .get(&template.template_id)
+            .map(|ids| ids.iter().filter_map(|id| self.fragment_id_to_string.get(id).cloned()).collect())
+            .unwrap_or_default();
+        ConflictProbe::new(template, fragments)
+    }
+
+    fn template_regex(&self, template_id: u64) -> Option<&Regex> {
+        self.patterns.get(&template_id).map(|regex| regex.as_ref())
+    }
+
+    /// All conflicts among the enabled templates (disabled ones never match)
+    fn find_conflicts(&self) -> Vec<TemplateConflict> {
+        use rayon::prelude::*;
+
+        let mut templates: Vec<&LogTemplate> = self
+            .templates
+            .values()
+            .filter(|t| !self.disabled.contains(&t.template_id))
+            .map(|t| t.as_ref())
+            .collect();
+        templates.sort_unstable_by_key(|t| t.template_id);
+
+        let probes: Vec<ConflictProbe> = templates.iter().map(|t| self.conflict_probe(t)).collect();
+
+        let mut conflicts: Vec<TemplateConflict> = probes
+            .par_iter()
+            .flat_map_iter(|probe| {
+                let mut found = Vec::new();
+                for other in &probes {
+                    if other.template.template_id == probe.template.template_id {
+                        continue;
+                    }
+                    probe.directional_conflicts(other, self.template_regex(other.template.template_id), &mut found);
+                    // Symmetric, so only report it from the lower id
+                    if probe.template.template_id < other.template.template_id && probe.equivalent_to(other) {
+                        found.push(TemplateConflict {
+                            template_id: probe.template.template_id,
+                            other_template_id: other.template.template_id,
+                            kind: ConflictKind::EquivalentRegex,
+                        });
+                    }
                 }
+                found
+            })
+            .collect();
+
+        conflicts.sort_unstable_by_key(|c| (c.template_id, c.other_template_id, c.kind));
+        conflicts
+    }
+
+    /// Conflicts between a candidate template and the enabled templates in this snapshot
+    fn check_template(&self, candidate: &LogTemplate) -> Vec<TemplateConflict> {
+        let candidate_regex = Regex::new(&candidate.pattern).ok();
+        let probe = ConflictProbe::new(candidate, self.pattern_fragments(&candidate.pattern));
+
+        let mut others: Vec<&LogTemplate> = self
+            .templates
+            .values()
+            .filter(|t| t.template_id != candidate.template_id && !self.disabled.contains(&t.template_id))
+            .map(|t| t.as_ref())
+            .collect();
+        others.sort_unstable_by_key(|t| t.template_id);
+
+        let mut conflicts = Vec::new();
+        for other in others {
+            let other_probe = self.conflict_probe(other);
+            probe.directional_conflicts(&other_probe, self.template_regex(other.template_id), &mut conflicts);
+            other_probe.directional_conflicts(&probe, candidate_regex.as_ref(), &mut conflicts);
+            if probe.equivalent_to(&other_probe) {
+                conflicts.push(TemplateConflict {
+                    template_id: candidate.template_id,
+                    other_template_id: other.template_id,
+                    kind: ConflictKind::EquivalentRegex,
+                });
             }
+        }
+        conflicts
+    }
+
+    /// Check a candidate template's compiled regex against the log line.
+    /// Templates whose pattern failed to compile cannot be verified and are rejected.
+    /// Multi-line records pass if the regex matches the whole record or its head line.
+    #[inline]
+    fn verify_candidate(&self, template_id: u64, log_line: &str) -> bool {
+        self.patterns
+            .get(&template_id)
+            .map(|regex| regex.is_match(log_line) || head_line(log_line).is_some_and(|head| regex.is_match(head)))
+            .unwrap_or(false)
+    }
+
+    /// Verify a candidate against a line that is not valid UTF-8. The template regex
+    /// is recompiled as a byte regex with Unicode off (so `.` and classes match raw
+    /// bytes) and cached per thread.
+    fn verify_candidate_bytes(&self, template_id: u64, log_line: &[u8]) -> bool {
+        let Some(regex) = self.patterns.get(&template_id) else {
+            return false;
+        };
 
-            None
+        BYTES_PATTERNS.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            // Cached per template_id; recompile if the template was replaced
+            if cache.get(&template_id).map(|r| r.as_str()) != Some(regex.as_str()) {
+                match regex::bytes::RegexBuilder::new(regex.as_str()).unicode(false).build() {
+                    Ok(bytes_regex) => {
+                        cache.insert(template_id, bytes_regex);
+                    }
+                    Err(_) => return false,
+                }
+            }
+            let regex = &cache[&template_id];
+            regex.is_match(log_line) || head_line_bytes(log_line).is_some_and(|head| regex.is_match(head))
         })
     }
 
+    /// Match a log line and extract its variables from the template's capture groups.
+    /// If the template regex does not match (fragment-only hit), variables are empty.
+    fn match_and_extract(&self, log_line: &str) -> Option<ExtractedMatch> {
+        let template_id = self.match_log(log_line)?;
+        let variables = self.extract_variables(template_id, log_line);
+        Some(ExtractedMatch { template_id, variables })
+    }
+
+    fn extract_variables(&self, template_id: u64, log_line: &str) -> HashMap<String, String> {
+        let mut variables = HashMap::new();
+        let (Some(regex), Some(template)) = (self.patterns.get(&template_id), self.templates.get(&template_id)) else {
+            return variables;
+        };
+
+        SCRATCH.with(|scratch| {
+            let mut scratch = scratch.borrow_mut();
+            let locs = scratch
+                .capture_locations
+                .entry(template_id)
+                .or_insert_with(|| regex.capture_locations());
+
+            // Slots are cached per template_id; rebuild if the template changed shape
+            if locs.len() != regex.captures_len() {
+                *locs = regex.capture_locations();
+            }
+
+            // Multi-line records: fall back to the head line when the whole record doesn't match
+            let text = if regex.captures_read(locs, log_line).is_some() {
+                log_line
+            } else {
+                match head_line(log_line) {
+                    Some(head) if regex.captures_read(locs, head).is_some() => head,
+                    _ => return,
+                }
+            };
+
+            // Group 0 is the whole match; variables map to groups 1..n in order.
+            // Named groups take precedence over the positional variable names.
+            for (group, name) in regex.capture_names().enumerate().skip(1) {
+                let Some((start, end)) = locs.get(group) else {
+                    continue;
+                };
+                let key = name
+                    .map(|n| n.to_string())
+                    .or_else(|| template.variables.get(group - 1).cloned())
+                    .unwrap_or_else(|| format!("var{}", group));
+                variables.insert(key, text[start..end].to_string());
+            }
+        });
+
+        variables
+    }
+
+    #[inline]
+    fn match_batch_bytes(&self, log_lines: &[&[u8]]) -> Vec<Option<u64>> {
+        log_lines.iter().map(|log_line| self.match_log_bytes(log_line)).collect()
+    }
+
     #[inline]
     fn match_batch(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
         // Process in chunks for better cache locality
@@ -285,6 +943,431 @@ impl MatcherSnapshot {
     }
 }
 
+/// Precomputed per-template data for conflict checks
+struct ConflictProbe<'a> {
+    template: &'a LogTemplate,
+    /// Sorted, deduplicated fragments
+    fragments: Vec<String>,
+    normalized_pattern: String,
+}
+
+impl<'a> ConflictProbe<'a> {
+    fn new(template: &'a LogTemplate, mut fragments: Vec<String>) -> Self {
+        fragments.sort_unstable();
+        fragments.dedup();
+        Self {
+            template,
+            fragments,
+            normalized_pattern: normalize_pattern(&template.pattern),
+        }
+    }
+
+    /// Conflicts where `self` is claimed by `other`
+    fn directional_conflicts(&self, other: &ConflictProbe, other_regex: Option<&Regex>, out: &mut Vec<TemplateConflict>) {
+        let conflict = |kind| TemplateConflict {
+            template_id: self.template.template_id,
+            other_template_id: other.template.template_id,
+            kind,
+        };
+
+        if !self.template.example.is_empty() && other_regex.is_some_and(|regex| regex.is_match(&self.template.example)) {
+            out.push(conflict(ConflictKind::ExampleMatchedByOther));
+        }
+
+        // Both lists are sorted, so a binary search per fragment is enough
+        if !self.fragments.is_empty()
+            && self.fragments.len() <= other.fragments.len()
+            && self.fragments.iter().all(|f| other.fragments.binary_search(f).is_ok())
+        {
+            out.push(conflict(ConflictKind::SharedFragments));
+        }
+    }
+
+    fn equivalent_to(&self, other: &ConflictProbe) -> bool {
+        self.normalized_pattern == other.normalized_pattern
+    }
+}
+
+static NAMED_GROUP: once_cell::sync::Lazy<Regex> =
+    once_cell::sync::Lazy::new(|| Regex::new(r"\(\?P?<[A-Za-z_][A-Za-z0-9_]*>").unwrap());
+
+/// Canonical spelling of a pattern: surrounding whitespace trimmed and named
+/// groups renamed to plain groups. Only rewrites that keep the regex matching
+/// exactly the same lines, so equal canonical patterns may share a template ID.
+pub(crate) fn canonical_pattern(pattern: &str) -> String {
+    NAMED_GROUP.replace_all(pattern.trim(), "(").into_owned()
+}
+
+/// Normalize a pattern so near-identical spellings compare equal for conflict
+/// detection and log types: on top of `canonical_pattern`, outer anchors are
+/// dropped and `[0-9]` is `\d`. These change what the regex matches, so this
+/// must not decide template identity.
+pub(crate) fn normalize_pattern(pattern: &str) -> String {
+    let mut normalized = canonical_pattern(pattern).replace("[0-9]", r"\d");
+
+    if let Some(rest) = normalized.strip_prefix('^') {
+        normalized = rest.to_string();
+    }
+    if normalized.ends_with('$') && !normalized.ends_with(r"\$") {
+        normalized.pop();
+    }
+    normalized
+}
+
+/// Error unless `existing`, stored under `template_id`, is the same pattern as
+/// `pattern`. Content-addressed IDs are 53-bit hashes, so two patterns can
+/// collide; the second one must not silently replace the first.
+pub(crate) fn ensure_same_pattern(template_id: u64, existing: &str, pattern: &str) -> anyhow::Result<()> {
+    if canonical_pattern(existing) != canonical_pattern(pattern) {
+        anyhow::bail!(
+            "template ID {} is already used by pattern '{}', not '{}'",
+            template_id,
+            existing,
+            pattern
+        );
+    }
+    Ok(())
+}
+
+/// Content-addressed IDs start here; lower IDs are left to explicitly numbered
+/// templates (the built-in defaults, counter-assigned IDs from older caches)
+pub const CONTENT_ID_MIN: u64 = 1 << 32;
+/// Content-addressed IDs stay below 2^53 so JSON clients using doubles keep them exact
+const CONTENT_ID_MAX: u64 = 1 << 53;
+
+/// Deterministic template ID for `pattern` within `namespace` (the tenant, see
+/// `TenantKey::id_namespace`). Hashes the namespace and the canonical pattern
+/// with 64-bit FNV-1a, which is stable across builds and platforms, so every
+/// replica and every cache file assigns the same template the same ID.
+pub fn content_template_id(namespace: &str, pattern: &str) -> u64 {
+    content_id(namespace, &canonical_pattern(pattern))
+}
+
+/// Log type id and signature of a template, from its compiled regex and example
+fn classify_log_type(regex: Option<&Regex>, template: &LogTemplate) -> LogTypeEntry {
+    let signature = template_log_type(regex, template);
+    (log_type_id(&signature), Arc::from(signature))
+}
+
+/// 64-bit FNV-1a of `namespace` and `key`, mapped into `[CONTENT_ID_MIN, CONTENT_ID_MAX)`
+pub(crate) fn content_id(namespace: &str, key: &str) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = FNV_OFFSET;
+    // NUL separates namespace and key so ("a", "bc") and ("ab", "c") differ
+    for byte in namespace.bytes().chain([0]).chain(key.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    CONTENT_ID_MIN + hash % (CONTENT_ID_MAX - CONTENT_ID_MIN)
+}
+
+// ============================================================================
+// On-disk snapshot format
+// ============================================================================
+//
+// Layout (all integers little-endian):
+//   [0..8)   SNAPSHOT_MAGIC
+//   [8..12)  format version (u32)
+//   [12..20) length of the templates section (u64)
+//   templates section: bincode `SnapshotTemplates`
+//   tables section:    bincode `SnapshotTables` (rest of the file)
+//
+// The templates section (templates, disabled set, config) alone is enough to
+// rebuild a matcher, so a file whose tables can't be used (other format
+// version, corrupt tables) still loads, just at rebuild speed. Files without
+// the magic are the original template-only format.
+//
+// The automaton is NOT saved. aho-corasick has no serialized form, so every
+// load builds it again from the saved pattern list. That build is cheap (about
+// 1ms for 1k templates); loading is dominated by per-template work, so the
+// tables carry each template's fragments and log type, leaving only the regex
+// compile. `cargo test --release --test benchmarks snapshot_load -- --ignored`
+// compares a load with tables against a rebuild from templates.
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"LAMSNAP\0";
+const SNAPSHOT_HEADER_LEN: usize = 8 + 4 + 8;
+
+/// Current on-disk snapshot format version. Bump whenever `SnapshotTables`
+/// changes shape; `SnapshotTemplates` keeps its layout, since files of every
+/// version are rebuilt from it.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotTemplates {
+    templates: Vec<LogTemplate>,
+    disabled: Vec<u64>,
+    config: MatcherConfig,
+}
+
+/// Precomputed matcher tables, so loading skips fragment extraction, weighting
+/// and log type classification (the automaton is still built on load). They are
+/// only valid for the config stored in the templates section.
+#[derive(Serialize, Deserialize)]
+struct SnapshotTables {
+    template_fragments: Vec<(u64, Vec<u32>)>,
+    /// (fragment id, fragment, weight)
+    fragments: Vec<(u32, String, f64)>,
+    next_fragment_id: u32,
+    /// Aho-Corasick patterns as fragment ids, in pattern order
+    automaton_fragments: Vec<u32>,
+    /// Indexed by Aho-Corasick pattern: (template_id, fragment index)
+    fragment_to_template: Vec<Vec<(u64, usize)>>,
+    /// (template_id, log type id, log type signature)
+    log_types: Vec<(u64, u64, String)>,
+}
+
+/// `LogTemplate` without metadata, as stored in template-only files
+#[derive(Serialize, Deserialize)]
+struct LegacyTemplate {
+    template_id: u64,
+    pattern: String,
+    variables: Vec<String>,
+    example: String,
+}
+
+impl From<LegacyTemplate> for LogTemplate {
+    fn from(legacy: LegacyTemplate) -> Self {
+        LogTemplate {
+            template_id: legacy.template_id,
+            pattern: legacy.pattern,
+            variables: legacy.variables,
+            example: legacy.example,
+            ..Default::default()
+        }
+    }
+}
+
+/// Template-only layout written before the versioned snapshot format
+#[derive(Serialize, Deserialize)]
+struct LegacyMatcherState {
+    templates: Vec<LegacyTemplate>,
+    #[allow(dead_code)]
+    next_template_id: u64,
+}
+
+impl MatcherSnapshot {
+    fn to_tables(&self) -> SnapshotTables {
+        let mut template_fragments: Vec<(u64, Vec<u32>)> = self
+            .template_fragments
+            .iter()
+            .map(|(&tid, frags)| (tid, frags.to_vec()))
+            .collect();
+        template_fragments.sort_unstable_by_key(|(tid, _)| *tid);
+
+        let mut fragments: Vec<(u32, String, f64)> = self
+            .fragment_id_to_string
+            .iter()
+            .map(|(&id, frag)| {
+                let weight = self.fragment_weights.get(&id).copied().unwrap_or(0.0);
+                (id, frag.clone(), weight)
+            })
+            .collect();
+        fragments.sort_unstable_by_key(|(id, _, _)| *id);
+
+        let fragment_to_template = (0..self.automaton_fragments.len())
+            .map(|ac_idx| {
+                self.fragment_to_template
+                    .get(&ac_idx)
+                    .map(|list| list.to_vec())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut log_types: Vec<(u64, u64, String)> = self
+            .log_types
+            .iter()
+            .map(|(&tid, (id, signature))| (tid, *id, signature.to_string()))
+            .collect();
+        log_types.sort_unstable_by_key(|(tid, _, _)| *tid);
+
+        SnapshotTables {
+            template_fragments,
+            fragments,
+            next_fragment_id: self.next_fragment_id,
+            automaton_fragments: self.automaton_fragments.clone(),
+            fragment_to_template,
+            log_types,
+        }
+    }
+
+    /// Restore fragment tables and the automaton from saved tables.
+    /// aho-corasick has no serialized form, so the automaton is built straight
+    /// from the saved pattern list (no fragment extraction or weighting).
+    fn from_tables(config: MatcherConfig, tables: SnapshotTables) -> anyhow::Result<Self> {
+        let mut snapshot = Self::with_config(config);
+
+        for (id, frag, weight) in tables.fragments {
+            snapshot.fragment_string_to_id.insert(frag.clone(), id);
+            snapshot.fragment_id_to_string.insert(id, frag);
+            snapshot.fragment_weights.insert(id, weight);
+        }
+        snapshot.next_fragment_id = tables.next_fragment_id;
+
+        for (tid, frags) in tables.template_fragments {
+            if let Some(missing) = frags.iter().find(|id| !snapshot.fragment_id_to_string.contains_key(id)) {
+                anyhow::bail!("template {} references unknown fragment {}", tid, missing);
+            }
+            snapshot.template_fragments.insert(tid, frags.into_iter().collect());
+        }
+
+        if tables.fragment_to_template.len() != tables.automaton_fragments.len() {
+            anyhow::bail!(
+                "automaton has {} patterns but {} template lists",
+                tables.automaton_fragments.len(),
+                tables.fragment_to_template.len()
+            );
+        }
+
+        let fragment_strs = tables
+            .automaton_fragments
+            .iter()
+            .map(|id| {
+                snapshot
+                    .fragment_id_to_string
+                    .get(id)
+                    .map(|s| s.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("automaton references unknown fragment {}", id))
+            })
+            .collect::<anyhow::Result<Vec<&str>>>()?;
+
+        if !fragment_strs.is_empty() {
+            let ac = AhoCorasick::builder()
+                .match_kind(snapshot.config.to_ac_match_kind())
+                .build(&fragment_strs)?;
+            snapshot.ac = Arc::new(ac);
+        }
+
+        for (ac_idx, list) in tables.fragment_to_template.into_iter().enumerate() {
+            snapshot.fragment_to_template.insert(ac_idx, list.into_iter().collect());
+        }
+        snapshot.automaton_fragments = tables.automaton_fragments;
+
+        for (tid, id, signature) in tables.log_types {
+            snapshot.log_types.insert(tid, (id, Arc::from(signature)));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Attach templates to restored tables, compiling their regexes in parallel.
+    /// Log types come from the tables; templates missing there are classified.
+    fn attach_templates(mut self, templates: Vec<LogTemplate>, disabled: Vec<u64>) -> Self {
+        use rayon::prelude::*;
+
+        let saved_log_types = &self.log_types;
+        let compiled: Vec<(u64, Option<Arc<Regex>>, Option<LogTypeEntry>)> = templates
+            .par_iter()
+            .map(|t| {
+                let regex = Regex::new(&t.pattern).ok();
+                let log_type = (!saved_log_types.contains_key(&t.template_id))
+                    .then(|| classify_log_type(regex.as_ref(), t));
+                (t.template_id, regex.map(Arc::new), log_type)
+            })
+            .collect();
+        for (template_id, regex, log_type) in compiled {
+            if let Some(regex) = regex {
+                self.patterns.insert(template_id, regex);
+            }
+            if let Some(log_type) = log_type {
+                self.log_types.insert(template_id, log_type);
+            }
+        }
+
+        for template in templates {
+            self.counters.entry(template.template_id).or_default();
+            self.templates.insert(template.template_id, Arc::new(template));
+        }
+        self.disabled.extend(disabled);
+        self
+    }
+
+    /// Rebuild a snapshot from templates alone (fragment extraction + automaton build)
+    fn rebuild_from(config: MatcherConfig, templates: Vec<LogTemplate>, disabled: Vec<u64>) -> Self {
+        let mut snapshot = Self::with_config(config);
+        snapshot.disabled.extend(disabled);
+        snapshot.add_templates(templates)
+    }
+}
+
+/// Encode a snapshot into the versioned on-disk format
+fn encode_snapshot(snapshot: &MatcherSnapshot) -> anyhow::Result<Vec<u8>> {
+    let mut templates: Vec<LogTemplate> = snapshot.templates.values().map(|t| (**t).clone()).collect();
+    templates.sort_unstable_by_key(|t| t.template_id);
+    let mut disabled: Vec<u64> = snapshot.disabled.iter().copied().collect();
+    disabled.sort_unstable();
+
+    let templates_section = bincode::serialize(&SnapshotTemplates {
+        templates,
+        disabled,
+        config: snapshot.config.clone(),
+    })?;
+    let tables_section = bincode::serialize(&snapshot.to_tables())?;
+
+    let mut bytes = Vec::with_capacity(SNAPSHOT_HEADER_LEN + templates_section.len() + tables_section.len());
+    bytes.extend_from_slice(SNAPSHOT_MAGIC);
+    bytes.extend_from_slice(&SNAPSHOT_FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(templates_section.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&templates_section);
+    bytes.extend_from_slice(&tables_section);
+    Ok(bytes)
+}
+
+/// Decode a snapshot file, falling back to a rebuild from templates when the
+/// saved tables are from another format version or can't be used. The saved
+/// config is kept either way; only template-only files get the default one.
+/// Returns the snapshot and whether the tables were used.
+fn decode_snapshot(bytes: &[u8]) -> anyhow::Result<(MatcherSnapshot, bool)> {
+    if !bytes.starts_with(SNAPSHOT_MAGIC) {
+        let state: LegacyMatcherState = bincode::deserialize(bytes)?;
+        tracing::info!("Template-only snapshot file, rebuilding automaton with the default config");
+        let templates = state.templates.into_iter().map(LogTemplate::from).collect();
+        let snapshot = MatcherSnapshot::rebuild_from(MatcherConfig::default(), templates, Vec::new());
+        return Ok((snapshot, false));
+    }
+
+    if bytes.len() < SNAPSHOT_HEADER_LEN {
+        anyhow::bail!("snapshot file truncated: {} bytes", bytes.len());
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into()?);
+    let templates_len = usize::try_from(u64::from_le_bytes(bytes[12..20].try_into()?))?;
+    let templates_end = SNAPSHOT_HEADER_LEN
+        .checked_add(templates_len)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| anyhow::anyhow!("snapshot templates section exceeds file size"))?;
+
+    let SnapshotTemplates { templates, disabled, config } =
+        bincode::deserialize(&bytes[SNAPSHOT_HEADER_LEN..templates_end])?;
+
+    if version != SNAPSHOT_FORMAT_VERSION {
+        tracing::warn!(
+            "Snapshot format v{} (expected v{}), rebuilding automaton",
+            version,
+            SNAPSHOT_FORMAT_VERSION
+        );
+        let snapshot = MatcherSnapshot::rebuild_from(config, templates, disabled);
+        return Ok((snapshot, false));
+    }
+
+    let restored = bincode::deserialize::<SnapshotTables>(&bytes[templates_end..])
+        .map_err(anyhow::Error::from)
+        .and_then(|tables| MatcherSnapshot::from_tables(config.clone(), tables))
+        .and_then(|snapshot| match templates.iter().find(|t| !snapshot.template_fragments.contains_key(&t.template_id)) {
+            Some(t) => Err(anyhow::anyhow!("template {} has no fragment table", t.template_id)),
+            None => Ok(snapshot),
+        });
+
+    match restored {
+        Ok(snapshot) => Ok((snapshot.attach_templates(templates, disabled), true)),
+        Err(e) => {
+            tracing::warn!("Unusable snapshot tables ({}), rebuilding automaton", e);
+            let snapshot = MatcherSnapshot::rebuild_from(config, templates, disabled);
+            Ok((snapshot, false))
+        }
+    }
+}
+
 #[allow(dead_code)]
 fn tokenize(text: &str) -> Vec<&str> {
     let mut tokens = Vec::new();
@@ -304,7 +1387,7 @@ fn tokenize(text: &str) -> Vec<&str> {
     tokens
 }
 
-fn extract_fragments(pattern: &str, min_length: usize) -> Vec<String> {
+pub(crate) fn extract_fragments(pattern: &str, min_length: usize) -> Vec<String> {
     let mut fragments = Vec::new();
     let mut current_fragment = String::new();
     let mut chars = pattern.chars().peekable();
@@ -459,7 +1542,6 @@ fn has_distinctive_markers(fragment: &str) -> bool {
 
 pub struct LogMatcher {
     snapshot: ArcSwap<MatcherSnapshot>,
-    next_template_id: Arc<AtomicU64>,
     config: MatcherConfig,
 }
 
@@ -471,34 +1553,45 @@ impl LogMatcher {
     pub fn with_config(config: MatcherConfig) -> Self {
         let mut snapshot = MatcherSnapshot::with_config(config.clone());
 
-        let default_templates = vec![
+        let mut default_templates = vec![
             LogTemplate {
                 template_id: 1,
                 pattern: r"cpu_usage: (\d+\.\d+)% - (.*)".to_string(),
                 variables: vec!["percentage".to_string(), "message".to_string()],
                 example: "cpu_usage: 45.2% - Server load normal".to_string(),
+                ..Default::default()
             },
             LogTemplate {
                 template_id: 2,
                 pattern: r"memory_usage: (\d+\.\d+)GB - (.*)".to_string(),
                 variables: vec!["amount".to_string(), "message".to_string()],
                 example: "memory_usage: 2.5GB - Memory consumption stable".to_string(),
+                ..Default::default()
             },
             LogTemplate {
                 template_id: 3,
                 pattern: r"disk_io: (\d+)MB/s - (.*)".to_string(),
                 variables: vec!["throughput".to_string(), "message".to_string()],
                 example: "disk_io: 250MB/s - Disk activity moderate".to_string(),
+                ..Default::default()
             },
         ];
 
-        for template in default_templates {
-            snapshot = snapshot.add_template(template);
+        for template in &mut default_templates {
+            template.metadata.source = Some(SOURCE_BUILTIN.to_string());
         }
+        snapshot = snapshot.add_templates(default_templates);
 
         Self {
             snapshot: ArcSwap::new(Arc::new(snapshot)),
-            next_template_id: Arc::new(AtomicU64::new(4)), // Start after default templates
+            config,
+        }
+    }
+
+    /// Create a matcher without the built-in default templates
+    pub fn empty(config: MatcherConfig) -> Self {
+        Self {
+            snapshot: ArcSwap::new(Arc::new(MatcherSnapshot::with_config(config.clone()))),
             config,
         }
     }
@@ -513,29 +1606,148 @@ impl LogMatcher {
         self.config.optimal_batch_size
     }
 
-    /// Generate next template ID
-    fn next_id(&self) -> u64 {
-        self.next_template_id.fetch_add(1, Ordering::SeqCst)
-    }
-
-    /// Set the next template ID (used when loading from database to avoid collisions)
-    pub fn set_next_template_id(&self, next_id: u64) {
-        self.next_template_id.store(next_id, Ordering::SeqCst);
-    }
-
-    /// Add a new template to the matcher (thread-safe)
-    pub fn add_template(&self, mut template: LogTemplate) {
-        // Assign a unique ID if it's 0 (placeholder from LLM)
+    /// Add a new template to the matcher (thread-safe).
+    /// A template_id of 0 (placeholder from the LLM) is replaced with the
+    /// pattern's content-addressed ID in the empty namespace; `MatcherRegistry`
+    /// assigns tenant-scoped IDs before templates get here.
+    /// Fails without changing anything if the ID is already used by a different
+    /// pattern; `replace_template` is the way to change a template's pattern.
+    pub fn add_template(&self, mut template: LogTemplate) -> anyhow::Result<()> {
         if template.template_id == 0 {
-            template.template_id = self.next_id();
+            template.template_id = content_template_id("", &template.pattern);
         }
 
+        let mut collision = Ok(());
         self.snapshot.rcu(|old_snapshot| {
+            collision = old_snapshot.check_id_collisions([&template]);
+            if collision.is_err() {
+                return Arc::clone(old_snapshot);
+            }
             let new_snapshot = (**old_snapshot).clone().add_template(template.clone());
             Arc::new(new_snapshot)
         });
+        collision?;
 
         tracing::debug!("Added template: {}", template.template_id);
+        Ok(())
+    }
+
+    /// Add a batch of templates with one automaton build and one snapshot swap (thread-safe)
+    /// Much cheaper than calling `add_template` in a loop, which rebuilds per template.
+    /// Nothing is added if any ID is already used by a different pattern.
+    pub fn add_templates(&self, templates: Vec<LogTemplate>) -> anyhow::Result<()> {
+        if templates.is_empty() {
+            return Ok(());
+        }
+
+        let templates: Vec<LogTemplate> = templates
+            .into_iter()
+            .map(|mut template| {
+                if template.template_id == 0 {
+                    template.template_id = content_template_id("", &template.pattern);
+                }
+                template
+            })
+            .collect();
+
+        let count = templates.len();
+        let mut collision = Ok(());
+        self.snapshot.rcu(|old_snapshot| {
+            collision = old_snapshot.check_id_collisions(&templates);
+            if collision.is_err() {
+                return Arc::clone(old_snapshot);
+            }
+            let new_snapshot = (**old_snapshot).clone().add_templates(templates.iter().cloned());
+            Arc::new(new_snapshot)
+        });
+        collision?;
+
+        tracing::debug!("Added {} templates", count);
+        Ok(())
+    }
+
+    /// Remove a template from the matcher (thread-safe)
+    /// Returns true if the template existed
+    pub fn remove_template(&self, template_id: u64) -> bool {
+        let previous = self.snapshot.rcu(|old_snapshot| {
+            if !old_snapshot.templates.contains_key(&template_id) {
+                return Arc::clone(old_snapshot);
+            }
+            Arc::new((**old_snapshot).clone().remove_template(template_id))
+        });
+
+        let existed = previous.templates.contains_key(&template_id);
+        if existed {
+            tracing::debug!("Removed template: {}", template_id);
+        }
+        existed
+    }
+
+    /// Replace the template with the same ID, or add it if it doesn't exist yet (thread-safe)
+    /// The disabled flag of an existing template is kept.
+    /// Returns true if an existing template was replaced
+    pub fn replace_template(&self, template: LogTemplate) -> bool {
+        let template_id = template.template_id;
+        let previous = self.snapshot.rcu(|old_snapshot| {
+            Arc::new((**old_snapshot).clone().replace_template(template_id, template.clone()))
+        });
+
+        tracing::debug!("Replaced template: {}", template_id);
+        previous.templates.contains_key(&template_id)
+    }
+
+    /// Replace template `template_id` with `template`, which carries its own ID:
+    /// a changed pattern has a different content-addressed ID, and the template
+    /// moves there with its disabled flag and hit counts (thread-safe).
+    /// Fails without changing anything if the new ID is already another template.
+    /// Returns true if `template_id` existed.
+    pub fn replace_template_as(&self, template_id: u64, template: LogTemplate) -> anyhow::Result<bool> {
+        let new_id = template.template_id;
+        let mut taken = false;
+        let previous = self.snapshot.rcu(|old_snapshot| {
+            taken = new_id != template_id && old_snapshot.templates.contains_key(&new_id);
+            if taken {
+                return Arc::clone(old_snapshot);
+            }
+            Arc::new((**old_snapshot).clone().replace_template(template_id, template.clone()))
+        });
+        if taken {
+            anyhow::bail!("template ID {} is already used by another template", new_id);
+        }
+
+        tracing::debug!("Replaced template {} with {}", template_id, new_id);
+        Ok(previous.templates.contains_key(&template_id))
+    }
+
+    /// Enable or disable a template without removing it (thread-safe)
+    /// Disabled templates are kept but never matched.
+    /// Returns false if the template doesn't exist
+    pub fn set_template_disabled(&self, template_id: u64, disabled: bool) -> bool {
+        let previous = self.snapshot.rcu(|old_snapshot| {
+            if !old_snapshot.templates.contains_key(&template_id) {
+                return Arc::clone(old_snapshot);
+            }
+            Arc::new((**old_snapshot).clone().set_disabled(template_id, disabled))
+        });
+
+        let exists = previous.templates.contains_key(&template_id);
+        if exists {
+            tracing::debug!("Template {} disabled: {}", template_id, disabled);
+        }
+        exists
+    }
+
+    /// Check whether a template is disabled
+    pub fn is_template_disabled(&self, template_id: u64) -> bool {
+        self.snapshot.load().disabled.contains(&template_id)
+    }
+
+    /// Get the IDs of all disabled templates
+    pub fn disabled_templates(&self) -> Vec<u64> {
+        let snapshot = self.snapshot.load();
+        let mut ids: Vec<u64> = snapshot.disabled.iter().copied().collect();
+        ids.sort_unstable();
+        ids
     }
 
     /// Match log and return template ID (Pure Aho-Corasick DFA)
@@ -549,107 +1761,230 @@ impl LogMatcher {
         } else {
             tracing::debug!("No template match found for log: {}", log_line);
         }
+        snapshot.record_hits(result, now_millis());
+
+        result
+    }
+
+    /// Match a raw byte log line (no UTF-8 validation required)
+    /// Returns Some(template_id) if matched, None otherwise
+    pub fn match_log_bytes(&self, log_line: &[u8]) -> Option<u64> {
+        let snapshot = self.snapshot.load();
+        let result = snapshot.match_log_bytes(log_line);
+        snapshot.record_hits(result, now_millis());
+        result
+    }
+
+    /// Byte variant of `match_batch` for raw input that may not be UTF-8
+    pub fn match_batch_bytes(&self, log_lines: &[&[u8]]) -> Vec<Option<u64>> {
+        let snapshot = self.snapshot.load();
+        let results = snapshot.match_batch_bytes(log_lines);
+        snapshot.record_hits(results.iter().flatten().copied(), now_millis());
+        results
+    }
+
+    /// Match multiple logs at once (batch processing for higher throughput)
+    /// Amortizes Arc load overhead across all logs in the batch
+    pub fn match_batch(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
+        let snapshot = self.snapshot.load();
+        let results = snapshot.match_batch(log_lines);
+        snapshot.record_hits(results.iter().flatten().copied(), now_millis());
+        results
+    }
+
+    /// Parallel batch matching with chunked processing for SIMD-style optimization
+    /// Uses rayon for parallel processing across chunks for better cache locality
+    pub fn match_batch_parallel(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
+        use rayon::prelude::*;
+
+        const CHUNK_SIZE: usize = 256; // Larger chunks for parallel processing
+        let snapshot = self.snapshot.load();
+        let now_ms = now_millis();
+
+        // Process chunks in parallel
+        let results: Vec<Vec<Option<u64>>> = log_lines
+            .par_chunks(CHUNK_SIZE)
+            .map(|chunk| {
+                // Each thread processes its chunk sequentially for cache efficiency
+                let chunk_results: Vec<Option<u64>> = chunk.iter()
+                    .map(|log_line| snapshot.match_log(log_line))
+                    .collect();
+                // Counted on the worker thread so hits spread over counter shards
+                snapshot.record_hits(chunk_results.iter().flatten().copied(), now_ms);
+                chunk_results
+            })
+            .collect();
+
+        // Flatten results
+        results.into_iter().flatten().collect()
+    }
+
+    /// Match log and extract named variable values from the template's capture groups
+    /// Returns Some(ExtractedMatch) if matched, None otherwise
+    pub fn match_and_extract(&self, log_line: &str) -> Option<ExtractedMatch> {
+        let snapshot = self.snapshot.load();
+        let result = snapshot.match_and_extract(log_line);
+        snapshot.record_hits(result.as_ref().map(|m| m.template_id), now_millis());
+        result
+    }
+
+    /// Values of a known template's variables in a log line it matched.
+    /// None when the template is not in this matcher.
+    pub fn extract_variables(&self, template_id: u64, log_line: &str) -> Option<HashMap<String, String>> {
+        let snapshot = self.snapshot.load();
+        if !snapshot.templates.contains_key(&template_id) {
+            return None;
+        }
+        Some(snapshot.extract_variables(template_id, log_line))
+    }
 
-        result
+    /// Explain how a log line was scored: every candidate template with its
+    /// weighted score, matched and missing fragments, and fragment weights
+    pub fn explain_match(&self, log_line: &str) -> MatchExplanation {
+        let snapshot = self.snapshot.load();
+        snapshot.explain_match(log_line)
     }
 
-    /// Match multiple logs at once (batch processing for higher throughput)
-    /// Amortizes Arc load overhead across all logs in the batch
-    pub fn match_batch(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
+    /// Find templates that can claim the same log lines: examples matched by
+    /// another template, fragments fully shared with another template, and
+    /// equivalent regexes. Disabled templates are left out.
+    pub fn find_conflicts(&self) -> Vec<TemplateConflict> {
         let snapshot = self.snapshot.load();
-        snapshot.match_batch(log_lines)
+        snapshot.find_conflicts()
     }
 
-    /// Parallel batch matching with chunked processing for SIMD-style optimization
-    /// Uses rayon for parallel processing across chunks for better cache locality
-    pub fn match_batch_parallel(&self, log_lines: &[&str]) -> Vec<Option<u64>> {
+    /// Check a template that is about to be added against the current set.
+    /// Conflicts are reported in both directions, with the candidate's own
+    /// `template_id` (0 for a not yet assigned id).
+    pub fn check_template_conflicts(&self, candidate: &LogTemplate) -> Vec<TemplateConflict> {
+        let snapshot = self.snapshot.load();
+        snapshot.check_template(candidate)
+    }
+
+    /// Batch variant of `match_and_extract` (single snapshot load for the whole batch)
+    pub fn match_and_extract_batch(&self, log_lines: &[&str]) -> Vec<Option<ExtractedMatch>> {
+        let snapshot = self.snapshot.load();
+        let results: Vec<Option<ExtractedMatch>> = log_lines
+            .iter()
+            .map(|log_line| snapshot.match_and_extract(log_line))
+            .collect();
+        snapshot.record_hits(results.iter().flatten().map(|m| m.template_id), now_millis());
+        results
+    }
+
+    /// Parallel variant of `match_and_extract` using per-thread scratch buffers
+    pub fn match_and_extract_parallel(&self, log_lines: &[&str]) -> Vec<Option<ExtractedMatch>> {
         use rayon::prelude::*;
 
-        const CHUNK_SIZE: usize = 256; // Larger chunks for parallel processing
+        const CHUNK_SIZE: usize = 256;
         let snapshot = self.snapshot.load();
+        let now_ms = now_millis();
 
-        // Process chunks in parallel
-        let results: Vec<Vec<Option<u64>>> = log_lines
+        let results: Vec<Vec<Option<ExtractedMatch>>> = log_lines
             .par_chunks(CHUNK_SIZE)
             .map(|chunk| {
-                // Each thread processes its chunk sequentially for cache efficiency
-                chunk.iter()
-                    .map(|log_line| snapshot.match_log(log_line))
-                    .collect()
+                let chunk_results: Vec<Option<ExtractedMatch>> = chunk.iter()
+                    .map(|log_line| snapshot.match_and_extract(log_line))
+                    .collect();
+                snapshot.record_hits(chunk_results.iter().flatten().map(|m| m.template_id), now_ms);
+                chunk_results
             })
             .collect();
 
-        // Flatten results
         results.into_iter().flatten().collect()
     }
 
+    /// Per-template hit counts and first/last match times, sorted by template id.
+    /// Counts live in memory only and start at zero when the matcher is created
+    /// or loaded; `explain_match` and conflict checks don't count as hits.
+    pub fn template_stats(&self) -> Vec<TemplateStats> {
+        self.snapshot.load().template_stats()
+    }
+
+    /// Log type of a template (see `LogType`)
+    pub fn log_type_id(&self, template_id: u64) -> Option<u64> {
+        self.snapshot.load().log_types.get(&template_id).map(|(log_type_id, _)| *log_type_id)
+    }
+
+    /// Log types for a batch of match results, in order; None where unmatched
+    pub fn log_type_ids(&self, template_ids: &[Option<u64>]) -> Vec<Option<u64>> {
+        let snapshot = self.snapshot.load();
+        template_ids
+            .iter()
+            .map(|id| id.and_then(|id| snapshot.log_types.get(&id)).map(|(log_type_id, _)| *log_type_id))
+            .collect()
+    }
+
+    /// All log types with the templates grouped under them
+    pub fn log_types(&self) -> Vec<LogType> {
+        self.snapshot.load().log_types()
+    }
+
     /// Get all templates for inspection
     pub fn get_all_templates(&self) -> Vec<LogTemplate> {
         let snapshot = self.snapshot.load();
         snapshot.templates.values().map(|t| (**t).clone()).collect()
     }
 
+    /// Get one template by ID
+    pub fn get_template(&self, template_id: u64) -> Option<LogTemplate> {
+        self.snapshot.load().templates.get(&template_id).map(|t| (**t).clone())
+    }
+
+    /// Get the number of templates (including disabled ones)
+    pub fn template_count(&self) -> usize {
+        self.snapshot.load().templates.len()
+    }
+
     /// Save the matcher state to a file
-    /// This serializes all templates; the Aho-Corasick DFA will be rebuilt on load
+    /// Writes the versioned snapshot format: templates, config, the fragment tables,
+    /// weights and the automaton's pattern list, so loading skips fragment
+    /// extraction and weighting. The automaton itself is not serialized.
+    /// The file is written next to `path` and renamed into place.
     pub fn save_to_file(&self, path: &str) -> anyhow::Result<()> {
-        use std::fs::File;
         use std::io::Write;
 
         let snapshot = self.snapshot.load();
-        let templates: Vec<LogTemplate> = snapshot.templates.values().map(|t| (**t).clone()).collect();
-        let next_id = self.next_template_id.load(Ordering::SeqCst);
-
-        #[derive(Serialize, Deserialize)]
-        struct MatcherState {
-            templates: Vec<LogTemplate>,
-            next_template_id: u64,
-        }
-
-        let state = MatcherState {
-            templates,
-            next_template_id: next_id,
-        };
+        let encoded = encode_snapshot(&snapshot)?;
 
-        let encoded = bincode::serialize(&state)?;
-        let mut file = File::create(path)?;
+        let tmp_path = format!("{}.tmp", path);
+        let mut file = std::fs::File::create(&tmp_path)?;
         file.write_all(&encoded)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
 
-        tracing::info!("Saved {} templates to {}", state.templates.len(), path);
+        tracing::info!(
+            "Saved {} templates to {} (snapshot v{})",
+            snapshot.templates.len(),
+            path,
+            SNAPSHOT_FORMAT_VERSION
+        );
         Ok(())
     }
 
     /// Load the matcher state from a file
-    /// Rebuilds the Aho-Corasick DFA from saved templates
+    /// The file is memory-mapped and the saved config is restored. When the format
+    /// version matches, the saved tables are used as-is and the Aho-Corasick automaton
+    /// is built from their pattern list; otherwise everything is rebuilt from the
+    /// templates. Template-only files from older versions are still accepted.
     pub fn load_from_file(path: &str) -> anyhow::Result<Self> {
-        use std::fs::File;
-        use std::io::Read;
-
-        let mut file = File::open(path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-
-        #[derive(Serialize, Deserialize)]
-        struct MatcherState {
-            templates: Vec<LogTemplate>,
-            next_template_id: u64,
-        }
-
-        let state: MatcherState = bincode::deserialize(&buffer)?;
+        let file = std::fs::File::open(path)?;
+        // SAFETY: read-only mapping of a file that save_to_file only ever
+        // replaces by rename, never rewrites in place
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
 
-        // Create new matcher without default templates
-        let mut snapshot = MatcherSnapshot::new();
-
-        // Add all loaded templates
-        for template in &state.templates {
-            snapshot = snapshot.add_template(template.clone());
-        }
+        let (snapshot, from_tables) = decode_snapshot(&mmap)?;
 
-        tracing::info!("Loaded {} templates from {}", state.templates.len(), path);
+        tracing::info!(
+            "Loaded {} templates from {} ({})",
+            snapshot.templates.len(),
+            path,
+            if from_tables { "saved tables" } else { "rebuilt" }
+        );
 
         Ok(Self {
+            config: snapshot.config.clone(),
             snapshot: ArcSwap::new(Arc::new(snapshot)),
-            next_template_id: Arc::new(AtomicU64::new(state.next_template_id)),
-            config: MatcherConfig::default(),
         })
     }
 
@@ -659,17 +1994,20 @@ impl LogMatcher {
 
         let snapshot = self.snapshot.load();
         let templates: Vec<LogTemplate> = snapshot.templates.values().map(|t| (**t).clone()).collect();
-        let next_id = self.next_template_id.load(Ordering::SeqCst);
+        let mut disabled: Vec<u64> = snapshot.disabled.iter().copied().collect();
+        disabled.sort_unstable();
 
         #[derive(Serialize, Deserialize)]
         struct MatcherState {
             templates: Vec<LogTemplate>,
-            next_template_id: u64,
+            disabled: Vec<u64>,
+            config: MatcherConfig,
         }
 
         let state = MatcherState {
             templates,
-            next_template_id: next_id,
+            disabled,
+            config: snapshot.config.clone(),
         };
 
         let file = File::create(path)?;
@@ -684,37 +2022,37 @@ impl LogMatcher {
     }
 
     /// Load from JSON
+    /// Files written before the config was saved get the default config.
     pub fn load_from_json(path: &str) -> anyhow::Result<Self> {
         use std::fs::File;
 
         let file = File::open(path)?;
 
+        // Files from older versions also carry a next_template_id, which is ignored
         #[derive(Serialize, Deserialize)]
         struct MatcherState {
             templates: Vec<LogTemplate>,
-            next_template_id: u64,
+            #[serde(default)]
+            disabled: Vec<u64>,
+            #[serde(default)]
+            config: MatcherConfig,
         }
 
         let state: MatcherState = serde_json::from_reader(file)?;
 
-        // Create new matcher without default templates
-        let mut snapshot = MatcherSnapshot::new();
-
-        // Add all loaded templates
-        for template in &state.templates {
-            snapshot = snapshot.add_template(template.clone());
-        }
+        // Create new matcher without default templates (single automaton build)
+        let template_count = state.templates.len();
+        let snapshot = MatcherSnapshot::rebuild_from(state.config, state.templates, state.disabled);
 
         tracing::info!(
             "Loaded {} templates from {} (JSON)",
-            state.templates.len(),
+            template_count,
             path
         );
 
         Ok(Self {
+            config: snapshot.config.clone(),
             snapshot: ArcSwap::new(Arc::new(snapshot)),
-            next_template_id: Arc::new(AtomicU64::new(state.next_template_id)),
-            config: MatcherConfig::default(),
         })
     }
 }
@@ -729,9 +2067,6 @@ impl Clone for LogMatcher {
     fn clone(&self) -> Self {
         Self {
             snapshot: ArcSwap::new(self.snapshot.load_full()),
-            next_template_id: Arc::new(AtomicU64::new(
-                self.next_template_id.load(Ordering::SeqCst),
-            )),
             config: self.config.clone(),
         }
     }
@@ -838,6 +2173,738 @@ mod tests {
         assert_eq!(matcher.match_log("memory_usage: 2.5GB - test"), Some(2));
     }
 
+    #[test]
+    fn test_regex_verification() {
+        let config = MatcherConfig::default().with_regex_verification(true);
+        let matcher = LogMatcher::with_config(config);
+
+        // Well-formed line still matches
+        assert_eq!(matcher.match_log("cpu_usage: 67.8% - Normal format"), Some(1));
+
+        // Fragments present but variables malformed - rejected by the regex check
+        assert_eq!(matcher.match_log("cpu_usage: INVALID FORMAT HERE"), None);
+
+        // Right literals in the wrong order - rejected by the regex check
+        assert_eq!(matcher.match_log("% - cpu_usage: 67.8"), None);
+    }
+
+    #[test]
+    fn test_regex_verification_falls_through() {
+        let config = MatcherConfig::default().with_regex_verification(true);
+        let matcher = LogMatcher::with_config(config);
+
+        // Both templates share all fragments; the first one scores higher on
+        // fragments but only the second one accepts a non-numeric id
+        matcher.add_template(LogTemplate {
+            template_id: 40,
+            pattern: r"job (\d+) finished with state (\w+) and long trailing description".to_string(),
+            variables: vec!["job_id".to_string(), "state".to_string()],
+            example: "job 17 finished with state ok and long trailing description".to_string(),
+            ..Default::default()
+        }).unwrap();
+        matcher.add_template(LogTemplate {
+            template_id: 41,
+            pattern: r"job ([a-z]+) finished with state (\w+)".to_string(),
+            variables: vec!["job_name".to_string(), "state".to_string()],
+            example: "job backup finished with state ok".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(
+            matcher.match_log("job 17 finished with state ok and long trailing description"),
+            Some(40)
+        );
+        assert_eq!(
+            matcher.match_log("job backup finished with state ok and long trailing description"),
+            Some(41)
+        );
+    }
+
+    #[test]
+    fn test_explain_match() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        matcher.add_templates(vec![
+            LogTemplate {
+                template_id: 1,
+                pattern: r"disk (\w+) usage at (\d+) percent on host (\S+)".to_string(),
+                variables: vec!["disk".to_string(), "pct".to_string(), "host".to_string()],
+                example: "disk sda1 usage at 91 percent on host web-1".to_string(),
+                ..Default::default()
+            },
+            LogTemplate {
+                template_id: 2,
+                pattern: r"disk (\w+) unmounted by (\w+)".to_string(),
+                variables: vec!["disk".to_string(), "user".to_string()],
+                example: "disk sda1 unmounted by root".to_string(),
+                ..Default::default()
+            },
+        ]).unwrap();
+
+        let line = "disk sda1 usage at 91 percent on host web-1";
+        let explanation = matcher.explain_match(line);
+
+        assert_eq!(explanation.matched_template_id, Some(1));
+        assert_eq!(explanation.matched_template_id, matcher.match_log(line));
+        assert!(!explanation.verify_with_regex);
+
+        let best = &explanation.candidates[0];
+        assert_eq!(best.template_id, 1);
+        assert!(best.above_threshold);
+        assert!((best.score - 1.0).abs() < f64::EPSILON);
+        assert!(best.missing_fragments.is_empty());
+        assert!(best.matched_fragments.iter().all(|f| f.weight > 0.0));
+
+        // The runner-up shares "disk " but is missing the rest of its fragments
+        let runner_up = explanation
+            .candidates
+            .iter()
+            .find(|c| c.template_id == 2)
+            .expect("runner-up candidate");
+        assert!(runner_up.score < best.score);
+        assert!(!runner_up.missing_fragments.is_empty());
+        assert_eq!(runner_up.regex_verified, None);
+
+        // No shared fragments at all -> no candidates
+        let explanation = matcher.explain_match("completely unrelated text");
+        assert_eq!(explanation.matched_template_id, None);
+        assert!(explanation.candidates.is_empty());
+    }
+
+    #[test]
+    fn test_explain_match_reports_regex_verification() {
+        let config = MatcherConfig::default().with_regex_verification(true);
+        let matcher = LogMatcher::empty(config);
+        matcher.add_template(LogTemplate {
+            template_id: 1,
+            pattern: r"job (\d+) finished with state (\w+)".to_string(),
+            variables: vec!["job_id".to_string(), "state".to_string()],
+            example: "job 17 finished with state ok".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let explanation = matcher.explain_match("job backup finished with state ok");
+        assert_eq!(explanation.matched_template_id, None);
+        assert_eq!(explanation.candidates[0].regex_verified, Some(false));
+
+        let explanation = matcher.explain_match("job 17 finished with state ok");
+        assert_eq!(explanation.matched_template_id, Some(1));
+        assert_eq!(explanation.candidates[0].regex_verified, Some(true));
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        matcher.add_templates(vec![
+            LogTemplate {
+                template_id: 1,
+                pattern: r"user (\w+) logged in".to_string(),
+                variables: vec!["user".to_string()],
+                example: "user alice logged in".to_string(),
+                ..Default::default()
+            },
+            // Broader duplicate: claims template 1's example and has the same fragments
+            LogTemplate {
+                template_id: 2,
+                pattern: r"user (\S+) logged in( via (\w+))?".to_string(),
+                variables: vec!["user".to_string(), "suffix".to_string(), "method".to_string()],
+                example: "user bob logged in via ssh".to_string(),
+                ..Default::default()
+            },
+            // Same regex as 1, spelled differently
+            LogTemplate {
+                template_id: 3,
+                pattern: r"^user (?P<user>\w+) logged in$".to_string(),
+                variables: vec!["user".to_string()],
+                example: "user carol logged in".to_string(),
+                ..Default::default()
+            },
+            LogTemplate {
+                template_id: 4,
+                pattern: r"disk (\w+) full".to_string(),
+                variables: vec!["disk".to_string()],
+                example: "disk sda1 full".to_string(),
+                ..Default::default()
+            },
+        ]).unwrap();
+
+        let conflicts = matcher.find_conflicts();
+        let has = |template_id, other_template_id, kind| {
+            conflicts.contains(&TemplateConflict { template_id, other_template_id, kind })
+        };
+
+        assert!(has(1, 3, ConflictKind::EquivalentRegex));
+        assert!(!has(3, 1, ConflictKind::EquivalentRegex));
+        assert!(has(1, 3, ConflictKind::ExampleMatchedByOther));
+        assert!(has(3, 1, ConflictKind::ExampleMatchedByOther));
+        assert!(has(1, 2, ConflictKind::ExampleMatchedByOther));
+        assert!(has(1, 2, ConflictKind::SharedFragments));
+        assert!(has(2, 1, ConflictKind::SharedFragments));
+        assert!(conflicts.iter().all(|c| c.template_id != 4 && c.other_template_id != 4));
+
+        // Disabled templates can't claim lines, so they don't conflict
+        matcher.set_template_disabled(3, true);
+        assert!(matcher
+            .find_conflicts()
+            .iter()
+            .all(|c| c.template_id != 3 && c.other_template_id != 3));
+    }
+
+    #[test]
+    fn test_check_template_conflicts() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        matcher.add_template(LogTemplate {
+            template_id: 1,
+            pattern: r"job (\d+) finished".to_string(),
+            variables: vec!["job_id".to_string()],
+            example: "job 17 finished".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        // Candidate whose example is already covered by template 1
+        let duplicate = LogTemplate {
+            template_id: 0,
+            pattern: r"job ([0-9]+) finished".to_string(),
+            variables: vec!["id".to_string()],
+            example: "job 18 finished".to_string(),
+            ..Default::default()
+        };
+        let conflicts = matcher.check_template_conflicts(&duplicate);
+        assert!(conflicts.contains(&TemplateConflict {
+            template_id: 0,
+            other_template_id: 1,
+            kind: ConflictKind::ExampleMatchedByOther,
+        }));
+        assert!(conflicts.contains(&TemplateConflict {
+            template_id: 0,
+            other_template_id: 1,
+            kind: ConflictKind::EquivalentRegex,
+        }));
+
+        let distinct = LogTemplate {
+            template_id: 0,
+            pattern: r"disk (\w+) full".to_string(),
+            variables: vec!["disk".to_string()],
+            example: "disk sda1 full".to_string(),
+            ..Default::default()
+        };
+        assert!(matcher.check_template_conflicts(&distinct).is_empty());
+    }
+
+    #[test]
+    fn test_content_template_id() {
+        let id = content_template_id("acme/api", r"user (\w+) logged in");
+        // Fixed value: IDs must not change between builds, or stored templates get renumbered
+        assert_eq!(id, 5_328_467_166_139_783);
+        assert!((CONTENT_ID_MIN..CONTENT_ID_MAX).contains(&id));
+
+        // Renaming groups keeps the ID; anchors and character classes change it
+        assert_eq!(id, content_template_id("acme/api", r" user (?P<name>\w+) logged in"));
+        assert_ne!(id, content_template_id("acme/api", r"^user (\w+) logged in$"));
+        assert_ne!(
+            content_template_id("acme/api", r"job (\d+) done"),
+            content_template_id("acme/api", r"job ([0-9]+) done")
+        );
+        assert_ne!(id, content_template_id("acme/web", r"user (\w+) logged in"));
+        assert_ne!(id, content_template_id("acme/api", r"user (\w+) logged out"));
+
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        matcher.add_template(LogTemplate {
+            template_id: 0,
+            pattern: r"user (\w+) logged in".to_string(),
+            variables: vec!["user".to_string()],
+            example: "user bob logged in".to_string(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(matcher.match_log("user alice logged in"), Some(content_template_id("", r"user (\w+) logged in")));
+    }
+
+    #[test]
+    fn test_template_id_collision() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        let template = |pattern: &str, example: &str| LogTemplate {
+            template_id: CONTENT_ID_MIN + 7,
+            pattern: pattern.to_string(),
+            example: example.to_string(),
+            ..Default::default()
+        };
+        matcher.add_template(template(r"user (\w+) logged in", "user bob logged in")).unwrap();
+
+        // Renamed groups are the same regex, so not a collision
+        matcher.add_template(template(r"user (?P<name>\w+) logged in", "user bob logged in")).unwrap();
+
+        // Anchors and `[0-9]` vs `\d` change what matches: different patterns
+        let err = matcher.add_template(template(r"^user (?P<name>\w+) logged in$", "user bob logged in")).unwrap_err();
+        assert!(err.to_string().contains("already used"));
+        assert!(matcher.add_template(template(r"user ([0-9]+) logged in", "user 7 logged in")).is_err());
+        assert!(matcher.match_log("prefix user alice logged in").is_some());
+
+        // A different pattern under a taken ID is rejected and the first one kept
+        let err = matcher.add_template(template(r"disk (\w+) full", "disk sda full")).unwrap_err();
+        assert!(err.to_string().contains("already used"));
+        assert_eq!(matcher.match_log("user alice logged in"), Some(CONTENT_ID_MIN + 7));
+        assert_eq!(matcher.match_log("disk sda full"), None);
+
+        // Batches are all or nothing, including collisions inside the batch
+        let mut other = template(r"cache (\w+) evicted", "cache users evicted");
+        other.template_id = 1;
+        assert!(matcher.add_templates(vec![other.clone(), template(r"disk (\w+) full", "disk sda full")]).is_err());
+        assert!(matcher.add_templates(vec![other.clone(), LogTemplate { pattern: r"x (\d+)".to_string(), ..other.clone() }]).is_err());
+        assert_eq!(matcher.template_count(), 1);
+
+        // Changing a pattern goes through replace_template
+        assert!(matcher.replace_template(template(r"disk (\w+) full", "disk sda full")));
+        assert_eq!(matcher.match_log("disk sda full"), Some(CONTENT_ID_MIN + 7));
+    }
+
+    #[test]
+    fn test_log_types() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        let template = |id: u64, pattern: &str, example: &str| LogTemplate {
+            template_id: id,
+            pattern: pattern.to_string(),
+            example: example.to_string(),
+            ..Default::default()
+        };
+        matcher.add_templates(vec![
+            template(1, r"session opened for user root by (\S+)", "session opened for user root by cron"),
+            template(2, r"session opened for user admin by (\S+)", "session opened for user admin by sshd"),
+            template(3, r"session closed for user (\w+)", "session closed for user root"),
+        ]).unwrap();
+
+        let log_types = matcher.log_types();
+        assert_eq!(log_types.len(), 2);
+        let opened = log_types.iter().find(|t| t.template_ids == [1, 2]).unwrap();
+        assert_eq!(opened.signature, "session opened for user by");
+
+        let ids = matcher.match_batch(&["session opened for user admin by cron", "session closed for user bob", "noise"]);
+        let log_type_ids = matcher.log_type_ids(&ids);
+        assert_eq!(log_type_ids[0], Some(opened.log_type_id));
+        assert_eq!(log_type_ids[1], matcher.log_type_id(3));
+        assert_ne!(log_type_ids[1], log_type_ids[0]);
+        assert_eq!(log_type_ids[2], None);
+
+        matcher.remove_template(3);
+        assert_eq!(matcher.log_types().len(), 1);
+    }
+
+    #[test]
+    fn test_normalize_pattern() {
+        assert_eq!(canonical_pattern(r" ^user (?P<name>\w+) id ([0-9]+)$ "), r"^user (\w+) id ([0-9]+)$");
+        assert_eq!(normalize_pattern(r"^user (?P<name>\w+) id ([0-9]+)$"), r"user (\w+) id (\d+)");
+        assert_eq!(normalize_pattern(r"cost (?<amount>\d+)\$"), r"cost (\d+)\$");
+    }
+
+    #[test]
+    fn test_match_bytes() {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        matcher.add_template(LogTemplate {
+            template_id: 1,
+            pattern: r"file (\S+) opened by (\w+)".to_string(),
+            variables: vec!["path".to_string(), "user".to_string()],
+            example: "file /tmp/a opened by root".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        // Latin-1 "é" (0xE9) is not valid UTF-8
+        let latin1: &[u8] = b"file /tmp/caf\xe9 opened by root";
+
+        assert_eq!(matcher.match_log_bytes(latin1), Some(1));
+        assert_eq!(matcher.match_log_bytes(b"file /tmp/a opened by bob"), matcher.match_log("file /tmp/a opened by bob"));
+        assert_eq!(matcher.match_log_bytes(b"\xff\xfe binary junk"), None);
+        assert_eq!(
+            matcher.match_batch_bytes(&[latin1, b"unrelated", b"file x opened by y"]),
+            vec![Some(1), None, Some(1)]
+        );
+    }
+
+    #[test]
+    fn test_match_bytes_with_regex_verification() {
+        let config = MatcherConfig::default().with_regex_verification(true);
+        let matcher = LogMatcher::empty(config);
+        matcher.add_template(LogTemplate {
+            template_id: 1,
+            pattern: r"file (\S+) opened by (\w+)".to_string(),
+            variables: vec!["path".to_string(), "user".to_string()],
+            example: "file /tmp/a opened by root".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        // Invalid UTF-8 inside a \S+ group still verifies on the byte path
+        assert_eq!(matcher.match_log_bytes(b"file /tmp/caf\xe9 opened by root"), Some(1));
+        // ...but the regex is still enforced
+        assert_eq!(matcher.match_log_bytes(b"file /tmp/caf\xe9 opened by !!"), None);
+
+        // Replacing the template invalidates the cached byte regex
+        matcher.replace_template(LogTemplate {
+            template_id: 1,
+            pattern: r"file (\S+) opened by (\d+)".to_string(),
+            variables: vec!["path".to_string(), "uid".to_string()],
+            example: "file /tmp/a opened by 0".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(matcher.match_log_bytes(b"file /tmp/caf\xe9 opened by root"), None);
+        assert_eq!(matcher.match_log_bytes(b"file /tmp/caf\xe9 opened by 1000"), Some(1));
+    }
+
+    #[test]
+    fn test_masked_matching() {
+        use crate::masking::MaskingConfig;
+
+        let config = MatcherConfig::default()
+            .with_masking(MaskingConfig::drain_defaults().with_case_folding(true));
+        let matcher = LogMatcher::empty(config.clone());
+        matcher.add_template(LogTemplate {
+            template_id: 1,
+            pattern: r"^GET /api/v1/users/(\d+) returned (\d+)$".to_string(),
+            variables: vec!["user_id".to_string(), "status".to_string()],
+            example: "GET /api/v1/users/42 returned 200".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        // Case and whitespace differences only matter before masking
+        assert_eq!(matcher.match_log("get  /api/v1/users/42\treturned 200"), Some(1));
+        // Variables still come from the raw line
+        let extracted = matcher.match_and_extract("GET /api/v1/users/7 returned 404").unwrap();
+        assert_eq!(extracted.variables["user_id"], "7");
+        assert_eq!(extracted.variables["status"], "404");
+
+        let explanation = matcher.explain_match("GET /api/v1/users/7 returned 404");
+        assert_eq!(explanation.masked_line.as_deref(), Some("get /api/v1/users/<num> returned <num>"));
+
+        // Numbers in template literals are masked too, so they index like any other
+        // fragment; the raw line is what regex verification checks
+        let verified = LogMatcher::empty(config.with_regex_verification(true));
+        verified.add_template(LogTemplate {
+            template_id: 2,
+            pattern: r"^connected to port 8080 after (\d+) retries$".to_string(),
+            variables: vec!["retries".to_string()],
+            example: "connected to port 8080 after 3 retries".to_string(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(verified.match_log("connected to port 8080 after 3 retries"), Some(2));
+        assert_eq!(verified.match_log_bytes(b"connected to port 8080 after 3 retries"), Some(2));
+        assert_eq!(verified.match_log("connected to port 9090 after 3 retries"), None);
+    }
+
+    #[test]
+    fn test_multiline_records() {
+        let record = "ERROR Request 42 failed\njava.io.IOException: closed\n\tat com.example.Conn.write(Conn.java:7)";
+
+        for verify in [false, true] {
+            let matcher = LogMatcher::empty(MatcherConfig::default().with_regex_verification(verify));
+            matcher.add_template(LogTemplate {
+                template_id: 1,
+                pattern: r"^ERROR Request (\d+) failed$".to_string(),
+                variables: vec!["request_id".to_string()],
+                example: "ERROR Request 7 failed".to_string(),
+                ..Default::default()
+            }).unwrap();
+
+            // Single-line templates match the record through its first line
+            assert_eq!(matcher.match_log(record), Some(1));
+            assert_eq!(matcher.match_log_bytes(record.as_bytes()), Some(1));
+            let extracted = matcher.match_and_extract(record).unwrap();
+            assert_eq!(extracted.variables["request_id"], "42");
+        }
+
+        // Templates spanning lines match the whole record
+        let matcher = LogMatcher::empty(MatcherConfig::default().with_regex_verification(true));
+        matcher.add_template(LogTemplate {
+            template_id: 2,
+            pattern: r"(?s)^ERROR Request (\d+) failed\n(\S+): (.*)$".to_string(),
+            variables: vec!["request_id".to_string(), "exception".to_string(), "trace".to_string()],
+            example: "ERROR Request 7 failed\njava.lang.Error: x".to_string(),
+            ..Default::default()
+        }).unwrap();
+        let extracted = matcher.match_and_extract(record).unwrap();
+        assert_eq!(extracted.template_id, 2);
+        assert_eq!(extracted.variables["exception"], "java.io.IOException");
+    }
+
+    #[test]
+    fn test_match_and_extract() {
+        let matcher = LogMatcher::new();
+
+        matcher.add_template(LogTemplate {
+            template_id: 50,
+            pattern: r"user (\w+) logged in from (\d+\.\d+\.\d+\.\d+)".to_string(),
+            variables: vec!["user".to_string(), "ip".to_string()],
+            example: "user alice logged in from 10.0.0.1".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let result = matcher
+            .match_and_extract("user bob logged in from 192.168.1.20")
+            .expect("should match");
+        assert_eq!(result.template_id, 50);
+        assert_eq!(result.variables.get("user").map(String::as_str), Some("bob"));
+        assert_eq!(result.variables.get("ip").map(String::as_str), Some("192.168.1.20"));
+
+        // Fragment-only hit: template id is returned, but nothing is extracted
+        let result = matcher.match_and_extract("cpu_usage: INVALID FORMAT HERE").unwrap();
+        assert_eq!(result.template_id, 1);
+        assert!(result.variables.is_empty());
+
+        assert_eq!(matcher.match_and_extract("unknown log format"), None);
+    }
+
+    #[test]
+    fn test_match_and_extract_named_groups_and_batch() {
+        let matcher = LogMatcher::new();
+
+        matcher.add_template(LogTemplate {
+            template_id: 51,
+            pattern: r"instance (?P<instance_id>[0-9a-f-]+) rebooted after (\d+)s".to_string(),
+            variables: vec!["ignored".to_string(), "seconds".to_string()],
+            example: "instance 3edec1e4-9678 rebooted after 12s".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let logs = vec![
+            "instance 3edec1e4-9678 rebooted after 12s",
+            "disk_io: 250MB/s - Disk activity moderate",
+            "unknown log format",
+        ];
+
+        let sequential = matcher.match_and_extract_batch(&logs);
+        let parallel = matcher.match_and_extract_parallel(&logs);
+        assert_eq!(sequential, parallel);
+
+        let first = sequential[0].as_ref().unwrap();
+        assert_eq!(first.template_id, 51);
+        assert_eq!(first.variables.get("instance_id").map(String::as_str), Some("3edec1e4-9678"));
+        assert_eq!(first.variables.get("seconds").map(String::as_str), Some("12"));
+
+        let second = sequential[1].as_ref().unwrap();
+        assert_eq!(second.template_id, 3);
+        assert_eq!(second.variables.get("throughput").map(String::as_str), Some("250"));
+        assert_eq!(second.variables.get("message").map(String::as_str), Some("Disk activity moderate"));
+
+        assert!(sequential[2].is_none());
+    }
+
+    #[test]
+    fn test_add_templates_bulk() {
+        let matcher = LogMatcher::new();
+
+        let templates: Vec<LogTemplate> = (0..50)
+            .map(|i| LogTemplate {
+                template_id: 100 + i,
+                pattern: format!(r"service_{} started on port (\d+)", i),
+                variables: vec!["port".to_string()],
+                example: format!("service_{} started on port 8080", i),
+                ..Default::default()
+            })
+            .collect();
+
+        matcher.add_templates(templates).unwrap();
+
+        assert_eq!(matcher.get_all_templates().len(), 53);
+        assert_eq!(matcher.match_log("service_7 started on port 9000"), Some(107));
+        assert_eq!(matcher.match_log("service_42 started on port 80"), Some(142));
+        // Default templates are still matched after the bulk rebuild
+        assert_eq!(matcher.match_log("cpu_usage: 50.0% - test"), Some(1));
+    }
+
+    #[test]
+    fn test_add_templates_assigns_ids() {
+        let matcher = LogMatcher::new();
+
+        matcher.add_templates(vec![
+            LogTemplate {
+                template_id: 0,
+                pattern: r"queue (\w+) is full".to_string(),
+                variables: vec!["queue".to_string()],
+                example: "queue jobs is full".to_string(),
+                ..Default::default()
+            },
+            LogTemplate {
+                template_id: 0,
+                pattern: r"queue (\w+) drained in (\d+)ms".to_string(),
+                variables: vec!["queue".to_string(), "duration".to_string()],
+                example: "queue jobs drained in 12ms".to_string(),
+                ..Default::default()
+            },
+        ]).unwrap();
+
+        assert_eq!(matcher.match_log("queue jobs is full"), Some(content_template_id("", r"queue (\w+) is full")));
+        assert_eq!(
+            matcher.match_log("queue jobs drained in 12ms"),
+            Some(content_template_id("", r"queue (\w+) drained in (\d+)ms"))
+        );
+    }
+
+    #[test]
+    fn test_remove_template() {
+        let matcher = LogMatcher::new();
+
+        assert_eq!(matcher.match_log("disk_io: 100MB/s - test"), Some(3));
+        assert!(matcher.remove_template(3));
+        assert_eq!(matcher.match_log("disk_io: 100MB/s - test"), None);
+        // Removing an unknown ID keeps the snapshot as it is
+        let before = matcher.snapshot.load_full();
+        assert!(!matcher.remove_template(3));
+        assert!(Arc::ptr_eq(&before, &matcher.snapshot.load_full()));
+
+        // Other templates are unaffected by the rebuild
+        assert_eq!(matcher.match_log("cpu_usage: 50.0% - test"), Some(1));
+        assert_eq!(matcher.get_all_templates().len(), 2);
+
+        // Removing everything leaves an empty, non-matching automaton
+        assert!(matcher.remove_template(1));
+        assert!(matcher.remove_template(2));
+        assert_eq!(matcher.match_log("cpu_usage: 50.0% - test"), None);
+    }
+
+    #[test]
+    fn test_replace_template() {
+        let matcher = LogMatcher::new();
+
+        let replaced = matcher.replace_template(LogTemplate {
+            template_id: 3,
+            pattern: r"disk_write: (\d+)MB/s on (\w+)".to_string(),
+            variables: vec!["throughput".to_string(), "device".to_string()],
+            example: "disk_write: 80MB/s on sda".to_string(),
+            ..Default::default()
+        });
+        assert!(replaced);
+
+        assert_eq!(matcher.match_log("disk_write: 80MB/s on sda"), Some(3));
+        assert_eq!(matcher.match_log("disk_io: 100MB/s - test"), None);
+
+        // Replacing an unknown ID adds it
+        assert!(!matcher.replace_template(LogTemplate {
+            template_id: 60,
+            pattern: r"backup completed in (\d+)s".to_string(),
+            variables: vec!["seconds".to_string()],
+            example: "backup completed in 42s".to_string(),
+            ..Default::default()
+        }));
+        assert_eq!(matcher.match_log("backup completed in 42s"), Some(60));
+    }
+
+    #[test]
+    fn test_replace_template_as() {
+        let matcher = LogMatcher::new();
+        matcher.set_template_disabled(3, true);
+        let template = |template_id: u64, pattern: &str| LogTemplate {
+            template_id,
+            pattern: pattern.to_string(),
+            example: "disk_write: 80MB/s".to_string(),
+            ..Default::default()
+        };
+
+        // The template moves to its new ID, still disabled
+        assert!(matcher.replace_template_as(3, template(30, r"disk_write: (\d+)MB/s")).unwrap());
+        assert!(matcher.get_template(3).is_none());
+        assert!(matcher.is_template_disabled(30));
+        assert_eq!(matcher.get_all_templates().len(), 3);
+
+        // Moving onto another template's ID is rejected
+        assert!(matcher.replace_template_as(30, template(1, r"disk_read: (\d+)MB/s")).is_err());
+        assert_eq!(matcher.get_template(30).unwrap().pattern, r"disk_write: (\d+)MB/s");
+    }
+
+    #[test]
+    fn test_fragments_pruned_on_remove_and_replace() {
+        let matcher = LogMatcher::new();
+        let fragments = |matcher: &LogMatcher| {
+            let snapshot = matcher.snapshot.load();
+            let mut strings: Vec<String> = snapshot.fragment_string_to_id.keys().cloned().collect();
+            strings.sort();
+            assert_eq!(snapshot.fragment_id_to_string.len(), strings.len());
+            assert_eq!(snapshot.fragment_weights.len(), strings.len());
+            strings
+        };
+        assert!(fragments(&matcher).iter().any(|f| f.contains("disk_io")));
+
+        matcher.replace_template(LogTemplate {
+            template_id: 3,
+            pattern: r"disk_write: (\d+)MB/s on (\w+)".to_string(),
+            variables: vec!["throughput".to_string(), "device".to_string()],
+            example: "disk_write: 80MB/s on sda".to_string(),
+            ..Default::default()
+        });
+        assert!(!fragments(&matcher).iter().any(|f| f.contains("disk_io")));
+        assert!(fragments(&matcher).iter().any(|f| f.contains("disk_write")));
+
+        // Disabled templates keep their fragments
+        matcher.set_template_disabled(2, true);
+        assert!(fragments(&matcher).iter().any(|f| f.contains("memory_usage")));
+
+        for template_id in [1, 2, 3] {
+            assert!(matcher.remove_template(template_id));
+        }
+        assert!(fragments(&matcher).is_empty());
+    }
+
+    #[test]
+    fn test_disable_template() {
+        let matcher = LogMatcher::new();
+
+        assert!(matcher.set_template_disabled(2, true));
+        assert!(matcher.is_template_disabled(2));
+        assert_eq!(matcher.disabled_templates(), vec![2]);
+        assert_eq!(matcher.match_log("memory_usage: 2.5GB - test"), None);
+
+        // Disabled templates are still listed
+        assert_eq!(matcher.get_all_templates().len(), 3);
+
+        // The flag survives a replace
+        matcher.replace_template(LogTemplate {
+            template_id: 2,
+            pattern: r"memory_usage: (\d+\.\d+)GB".to_string(),
+            variables: vec!["amount".to_string()],
+            example: "memory_usage: 2.5GB".to_string(),
+            ..Default::default()
+        });
+        assert!(matcher.is_template_disabled(2));
+        assert_eq!(matcher.match_log("memory_usage: 2.5GB"), None);
+
+        assert!(matcher.set_template_disabled(2, false));
+        assert_eq!(matcher.match_log("memory_usage: 2.5GB - test"), Some(2));
+
+        assert!(!matcher.set_template_disabled(999, true));
+        assert!(!matcher.is_template_disabled(999));
+    }
+
+    #[test]
+    fn test_template_stats() {
+        let matcher = LogMatcher::new();
+
+        // Every template is listed, including ones that never matched
+        let stats = matcher.template_stats();
+        assert_eq!(stats.iter().map(|s| s.template_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(stats.iter().all(|s| s.hits == 0 && s.first_seen.is_none() && s.last_seen.is_none()));
+
+        let before = Utc::now();
+        matcher.match_log("cpu_usage: 50.0% - test");
+        matcher.match_batch(&["cpu_usage: 1.0% - a", "disk_io: 100MB/s - b", "nothing here"]);
+        matcher.match_batch_parallel(&vec!["cpu_usage: 2.0% - c"; 600]);
+        matcher.match_and_extract("disk_io: 5MB/s - d");
+        matcher.match_log_bytes(b"memory_usage: 2.5GB - e");
+        // Explaining a line is not a hit
+        matcher.explain_match("memory_usage: 2.5GB - e");
+
+        let stats = matcher.template_stats();
+        assert_eq!(stats.iter().map(|s| s.hits).collect::<Vec<_>>(), vec![602, 1, 2]);
+        let cpu = &stats[0];
+        assert!(cpu.first_seen.unwrap() >= before - chrono::Duration::milliseconds(1));
+        assert!(cpu.first_seen <= cpu.last_seen);
+
+        // Counts survive a replace, are dropped on remove
+        matcher.replace_template(LogTemplate {
+            template_id: 1,
+            pattern: r"cpu_usage: (\d+\.\d+)%".to_string(),
+            variables: vec!["percentage".to_string()],
+            example: "cpu_usage: 1.0%".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(matcher.template_stats()[0].hits, 602);
+        matcher.remove_template(3);
+        assert_eq!(matcher.template_stats().len(), 2);
+    }
+
     #[test]
     fn test_multiple_templates_same_prefix() {
         let mut matcher = LogMatcher::new();
@@ -848,21 +2915,24 @@ mod tests {
             pattern: r"error: connection timeout after (\d+)ms".to_string(),
             variables: vec!["duration".to_string()],
             example: "error: connection timeout after 5000ms".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         matcher.add_template(LogTemplate {
             template_id: 11,
             pattern: r"error: invalid user id (\d+)".to_string(),
             variables: vec!["user_id".to_string()],
             example: "error: invalid user id 12345".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         matcher.add_template(LogTemplate {
             template_id: 12,
             pattern: r"error: file not found: (.*)".to_string(),
             variables: vec!["filename".to_string()],
             example: "error: file not found: config.json".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         // Each should match the correct template despite sharing "error: " prefix
         assert_eq!(
@@ -914,21 +2984,24 @@ mod tests {
                 .to_string(),
             variables: vec!["txn_id".to_string(), "amount".to_string()],
             example: "Transaction txn_001 completed successfully with amount 100".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         matcher.add_template(LogTemplate {
             template_id: 31,
             pattern: r"Transaction ([a-zA-Z0-9_]+) completed with warnings: (.*)".to_string(),
             variables: vec!["txn_id".to_string(), "warnings".to_string()],
             example: "Transaction txn_002 completed with warnings: low balance".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         matcher.add_template(LogTemplate {
             template_id: 32,
             pattern: r"Transaction ([a-zA-Z0-9_]+) failed due to (.*)".to_string(),
             variables: vec!["txn_id".to_string(), "reason".to_string()],
             example: "Transaction txn_003 failed due to insufficient funds".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         // Each should match the correct template based on distinctive fragments
         assert_eq!(
@@ -957,7 +3030,8 @@ mod tests {
             pattern: r"^([A-Z][a-z]{2} \d{1,2} \d{2}:\d{2}:\d{2}) ([\w-]+) sshd\(pam_unix\)\[(\d+)\]: authentication failure; logname=(.*?) uid=(\d+) euid=(\d+) tty=([\w]+) ruser=(.*?) rhost=([\d.]+)\s*$".to_string(),
             variables: vec!["timestamp".to_string(), "hostname".to_string(), "pid".to_string()],
             example: "Jun 14 15:16:01 combo sshd(pam_unix)[19939]: authentication failure; logname= uid=0 euid=0 tty=NODEVssh ruser= rhost=218.188.2.4".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         // Add a competing pattern with similar generic fragments
         matcher.add_template(LogTemplate {
@@ -965,7 +3039,8 @@ mod tests {
             pattern: r"generic log with uid=(\d+) and tty=(\w+) somewhere".to_string(),
             variables: vec!["uid".to_string(), "tty".to_string()],
             example: "generic log with uid=123 and tty=tty1 somewhere".to_string(),
-        });
+            ..Default::default()
+        }).unwrap();
 
         // Real Linux syslog line
         let test_log = "Jun 14 15:16:01 combo sshd(pam_unix)[19939]: authentication failure; logname= uid=0 euid=0 tty=NODEVssh ruser= rhost=218.188.2.4";
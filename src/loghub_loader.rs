@@ -1,11 +1,14 @@
 /// LogHub dataset loader
 ///
 /// Loads datasets from LogHub format with pre-generated templates
+use crate::log_format_detector::HeaderFormat;
+use crate::multiline::{assemble_lines, MultilineAssembler, MultilineConfig};
 use crate::traits::{DatasetLoader, GroundTruthEntry};
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
+use std::io::{BufRead, BufReader};
 
 #[derive(Debug, Deserialize)]
 struct LogHubTemplate {
@@ -15,6 +18,46 @@ struct LogHubTemplate {
     event_template: String,
 }
 
+/// Read a log file as raw byte lines, without UTF-8 validation.
+/// Lines are split on `\n` with a trailing `\r` removed, like `str::lines`.
+pub fn read_raw_lines(path: &str) -> Result<Vec<Vec<u8>>> {
+    let file = fs::File::open(path).with_context(|| format!("Failed to open log file: {}", path))?;
+    let mut reader = BufReader::new(file);
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// Join byte lines into multi-line records. Continuation is decided on a
+/// lossy UTF-8 view; the records keep the original bytes.
+fn assemble_raw_lines(config: &MultilineConfig, lines: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+    let mut assembler = MultilineAssembler::new(config.clone())?;
+    let mut spans = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        spans.extend(assembler.push(&String::from_utf8_lossy(line), idx).map(|record| (record.meta, record.lines)));
+    }
+    spans.extend(assembler.flush().map(|record| (record.meta, record.lines)));
+
+    Ok(spans
+        .into_iter()
+        .map(|(start, count)| lines[start..start + count].join(&b'\n'))
+        .collect())
+}
+
 /// Convert LogHub template format (<*>) to regex
 fn loghub_template_to_regex(template: &str) -> String {
     // Escape regex special characters except <*>
@@ -49,10 +92,19 @@ fn loghub_template_to_regex(template: &str) -> String {
     result
 }
 
+/// A log line split into header fields and content
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedLog {
+    pub headers: HashMap<String, String>,
+    pub content: String,
+}
+
 pub struct LogHubDatasetLoader {
     dataset_name: String,
     log_file: String,
     template_file: String,
+    header_format: Option<HeaderFormat>,
+    multiline: Option<MultilineConfig>,
 }
 
 impl LogHubDatasetLoader {
@@ -67,9 +119,85 @@ impl LogHubDatasetLoader {
             dataset_name: dataset_name.to_string(),
             log_file,
             template_file,
+            header_format: None,
+            multiline: None,
+        }
+    }
+
+    /// Strip headers with this format: raw logs and ground truth then hold only
+    /// the `<Content>` field, which is what LogHub templates describe.
+    /// Lines that don't follow the format are kept whole.
+    pub fn with_header_format(mut self, format: HeaderFormat) -> Self {
+        self.header_format = Some(format);
+        self
+    }
+
+    /// Use the dataset's LogHub header format, if one is known
+    pub fn with_loghub_header_format(mut self) -> Self {
+        self.header_format = HeaderFormat::loghub(&self.dataset_name);
+        self
+    }
+
+    pub fn header_format(&self) -> Option<&HeaderFormat> {
+        self.header_format.as_ref()
+    }
+
+    /// Join continuation lines (stack traces and the like) into one record
+    /// before raw and parsed logs are returned. Ground truth stays per line,
+    /// since LogHub labels physical lines.
+    pub fn with_multiline(mut self, config: MultilineConfig) -> Self {
+        self.multiline = Some(config);
+        self
+    }
+
+    pub fn multiline(&self) -> Option<&MultilineConfig> {
+        self.multiline.as_ref()
+    }
+
+    /// Physical lines of the log file
+    fn read_lines(&self) -> Result<Vec<String>> {
+        let content = fs::read_to_string(&self.log_file)
+            .with_context(|| format!("Failed to read log file: {}", self.log_file))?;
+        Ok(content.lines().map(str::to_string).collect())
+    }
+
+    /// Physical lines, or assembled records with `with_multiline`
+    fn read_records(&self) -> Result<Vec<String>> {
+        let lines = self.read_lines()?;
+        match &self.multiline {
+            Some(config) => assemble_lines(config, lines.iter().map(String::as_str)),
+            None => Ok(lines),
         }
     }
 
+    /// The content of a line or record, or all of it without a header format
+    fn content(&self, line: &str) -> String {
+        match &self.header_format {
+            Some(format) => format.content(line).to_string(),
+            None => line.to_string(),
+        }
+    }
+
+    /// Load log lines with their header fields as structured metadata.
+    /// Without a header format (or for lines that don't follow it) the
+    /// whole line is the content and the headers are empty.
+    pub fn load_parsed_logs(&self) -> Result<Vec<ParsedLog>> {
+        Ok(self
+            .read_records()?
+            .iter()
+            .map(|line| match self.header_format.as_ref().and_then(|f| f.parse_line(line)) {
+                Some(parsed) => ParsedLog {
+                    headers: parsed.headers,
+                    content: parsed.content.to_string(),
+                },
+                None => ParsedLog {
+                    headers: HashMap::new(),
+                    content: line.to_string(),
+                },
+            })
+            .collect())
+    }
+
     /// Load templates from LogHub CSV format
     pub fn load_templates(&self) -> Result<HashMap<String, String>> {
         let content = fs::read_to_string(&self.template_file)
@@ -90,15 +218,31 @@ impl LogHubDatasetLoader {
 
 impl DatasetLoader for LogHubDatasetLoader {
     fn load_raw_logs(&self) -> Result<Vec<String>> {
-        let content = fs::read_to_string(&self.log_file)
-            .with_context(|| format!("Failed to read log file: {}", self.log_file))?;
+        Ok(self.read_records()?.iter().map(|record| self.content(record)).collect())
+    }
+
+    fn load_raw_log_bytes(&self) -> Result<Vec<Vec<u8>>> {
+        let mut lines = read_raw_lines(&self.log_file)?;
+        if let Some(config) = &self.multiline {
+            lines = assemble_raw_lines(config, lines)?;
+        }
+        let Some(format) = &self.header_format else {
+            return Ok(lines);
+        };
 
-        Ok(content.lines().map(|s| s.to_string()).collect())
+        // Only valid UTF-8 lines can be split; others are kept whole
+        Ok(lines
+            .into_iter()
+            .map(|line| match std::str::from_utf8(&line) {
+                Ok(text) => format.content(text).as_bytes().to_vec(),
+                Err(_) => line,
+            })
+            .collect())
     }
 
     fn load_ground_truth(&self) -> Result<Vec<GroundTruthEntry>> {
-        // Load raw logs (one line per log)
-        let raw_logs = self.load_raw_logs()?;
+        // Load raw logs (one line per log, even with multi-line assembly)
+        let raw_logs: Vec<String> = self.read_lines()?.iter().map(|line| self.content(line)).collect();
 
         // Load from structured CSV file to get event IDs
         let structured_file = format!(
@@ -145,6 +289,79 @@ impl DatasetLoader for LogHubDatasetLoader {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_read_raw_lines_keeps_invalid_utf8() {
+        let path = std::env::temp_dir().join(format!("raw_lines_{}.log", std::process::id()));
+        fs::write(&path, b"caf\xe9 opened\r\nplain line\n\nlast").unwrap();
+
+        let lines = read_raw_lines(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                b"caf\xe9 opened".to_vec(),
+                b"plain line".to_vec(),
+                Vec::new(),
+                b"last".to_vec(),
+            ]
+        );
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_header_format_strips_headers() {
+        let base = std::env::temp_dir().join(format!("loghub_headers_{}", std::process::id()));
+        let dir = base.join("HDFS");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("HDFS_2k.log"),
+            "081109 203518 143 INFO dfs.DataNode$DataXceiver: Receiving block blk_-1608999687919862906\nnot a header line\n",
+        )
+        .unwrap();
+
+        let base_path = base.to_str().unwrap();
+        let plain = LogHubDatasetLoader::new("HDFS", base_path);
+        assert!(plain.load_raw_logs().unwrap()[0].starts_with("081109"));
+
+        let loader = LogHubDatasetLoader::new("HDFS", base_path).with_loghub_header_format();
+        assert!(loader.header_format().is_some());
+        assert_eq!(
+            loader.load_raw_logs().unwrap(),
+            vec!["Receiving block blk_-1608999687919862906", "not a header line"]
+        );
+        assert_eq!(loader.load_raw_log_bytes().unwrap()[0], b"Receiving block blk_-1608999687919862906");
+
+        let parsed = loader.load_parsed_logs().unwrap();
+        assert_eq!(parsed[0].headers["Pid"], "143");
+        assert_eq!(parsed[0].headers["Component"], "dfs.DataNode$DataXceiver");
+        assert!(parsed[1].headers.is_empty());
+
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn test_multiline_records() {
+        let base = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/loghub");
+        let plain = LogHubDatasetLoader::new("Spark", base).with_loghub_header_format();
+        assert_eq!(plain.load_raw_logs().unwrap().len(), 9);
+
+        let loader = plain.with_multiline(MultilineConfig::timestamp_prefix());
+        let logs = loader.load_raw_logs().unwrap();
+        assert_eq!(logs.len(), 4);
+        assert!(logs[1].starts_with("Exception in task 0.0 in stage 1.0 (TID 4)\njava.io.IOException"));
+        assert!(logs[1].ends_with("\t... 12 more"));
+        assert_eq!(logs[1].lines().count(), 6);
+        assert_eq!(logs[2], "Found block rdd_2_0 locally");
+
+        let bytes = loader.load_raw_log_bytes().unwrap();
+        assert_eq!(bytes, logs.iter().map(|log| log.as_bytes().to_vec()).collect::<Vec<_>>());
+
+        let parsed = loader.load_parsed_logs().unwrap();
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed[1].headers["Level"], "ERROR");
+        assert_eq!(parsed[1].content, logs[1]);
+    }
+
     #[test]
     fn test_loghub_template_to_regex() {
         assert_eq!(
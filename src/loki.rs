@@ -0,0 +1,275 @@
+/// Loki push API decoding
+///
+/// Decodes `/loki/api/v1/push` bodies into streams of labeled entries: JSON,
+/// or snappy-compressed protobuf (`logproto.PushRequest`) as sent by Promtail,
+/// Grafana Agent and Fluent Bit.
+use crate::bulk_ingest::MAX_DECODED_BYTES;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use prost::Message;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LokiStream {
+    pub labels: Vec<(String, String)>,
+    pub entries: Vec<LokiEntry>,
+}
+
+impl LokiStream {
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LokiEntry {
+    pub timestamp: DateTime<Utc>,
+    pub line: String,
+    /// Structured metadata (Loki 2.9+)
+    pub metadata: Vec<(String, String)>,
+}
+
+// Subset of Loki's push.proto
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StreamAdapter {
+    /// Label set in selector syntax: `{job="varlogs", host="web-1"}`
+    #[prost(string, tag = "1")]
+    pub labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<EntryAdapter>,
+    #[prost(uint64, tag = "3")]
+    pub hash: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub line: String,
+    #[prost(message, repeated, tag = "3")]
+    pub structured_metadata: Vec<LabelPairAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+/// `google.protobuf.Timestamp`
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+/// Decode a snappy-compressed protobuf push request
+pub fn decode_protobuf(body: &[u8]) -> Result<Vec<LokiStream>> {
+    let len = snap::raw::decompress_len(body).context("invalid snappy block")?;
+    if len > MAX_DECODED_BYTES {
+        bail!("snappy block decompresses to {} bytes, over the {} byte limit", len, MAX_DECODED_BYTES);
+    }
+    let decompressed = snap::raw::Decoder::new().decompress_vec(body).context("invalid snappy block")?;
+    let request = PushRequest::decode(decompressed.as_slice()).context("invalid Loki protobuf")?;
+
+    request
+        .streams
+        .into_iter()
+        .map(|stream| {
+            let entries = stream
+                .entries
+                .into_iter()
+                .map(|entry| {
+                    let ts = entry.timestamp.unwrap_or_default();
+                    Ok(LokiEntry {
+                        timestamp: DateTime::from_timestamp(ts.seconds, ts.nanos.max(0) as u32)
+                            .context("entry timestamp out of range")?,
+                        line: entry.line,
+                        metadata: entry.structured_metadata.into_iter().map(|pair| (pair.name, pair.value)).collect(),
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Ok(LokiStream {
+                labels: parse_labels(&stream.labels)?,
+                entries,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct JsonPushRequest {
+    streams: Vec<JsonStream>,
+}
+
+#[derive(Deserialize)]
+struct JsonStream {
+    #[serde(default)]
+    stream: BTreeMap<String, String>,
+    #[serde(default)]
+    values: Vec<JsonEntry>,
+}
+
+/// `["<unix ns>", "<line>"]`, optionally with a structured metadata object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Line(String, String),
+    WithMetadata(String, String, BTreeMap<String, String>),
+}
+
+/// Decode a JSON push request
+pub fn decode_json(body: &[u8]) -> Result<Vec<LokiStream>> {
+    let request: JsonPushRequest = serde_json::from_slice(body).context("invalid Loki JSON")?;
+
+    request
+        .streams
+        .into_iter()
+        .map(|stream| {
+            let entries = stream
+                .values
+                .into_iter()
+                .map(|entry| {
+                    let (ts, line, metadata) = match entry {
+                        JsonEntry::Line(ts, line) => (ts, line, BTreeMap::new()),
+                        JsonEntry::WithMetadata(ts, line, metadata) => (ts, line, metadata),
+                    };
+                    let nanos: i64 = ts.parse().with_context(|| format!("invalid timestamp '{}'", ts))?;
+                    Ok(LokiEntry {
+                        timestamp: DateTime::from_timestamp_nanos(nanos),
+                        line,
+                        metadata: metadata.into_iter().collect(),
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Ok(LokiStream {
+                labels: stream.stream.into_iter().collect(),
+                entries,
+            })
+        })
+        .collect()
+}
+
+/// Parse a label set in selector syntax: `{name="value", ...}`
+pub fn parse_labels(selector: &str) -> Result<Vec<(String, String)>> {
+    let inner = selector
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .with_context(|| format!("invalid label set '{}'", selector))?;
+
+    let mut labels = Vec::new();
+    let mut rest = inner.trim_start();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=').with_context(|| format!("invalid label set '{}'", selector))?;
+        let mut chars = after.strip_prefix('"').context("label value must be quoted")?.char_indices();
+
+        let mut value = String::new();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c)) => value.push(c),
+                    None => bail!("unterminated label value in '{}'", selector),
+                },
+                Some((_, c)) => value.push(c),
+                None => bail!("unterminated label value in '{}'", selector),
+            }
+        };
+        labels.push((name.trim().to_string(), value));
+
+        rest = after[end + 2..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_labels() {
+        let labels = parse_labels(r#"{job="varlogs", filename="/var/log/a \"b\".log",host="web-1"}"#).unwrap();
+        assert_eq!(
+            labels,
+            vec![
+                ("job".to_string(), "varlogs".to_string()),
+                ("filename".to_string(), "/var/log/a \"b\".log".to_string()),
+                ("host".to_string(), "web-1".to_string()),
+            ]
+        );
+        assert!(parse_labels("{}").unwrap().is_empty());
+        assert!(parse_labels(r#"{job=varlogs}"#).is_err());
+        assert!(parse_labels(r#"job="x""#).is_err());
+        assert!(parse_labels(r#"{job="x}"#).is_err());
+    }
+
+    #[test]
+    fn test_decode_json() {
+        let body = br#"{"streams": [{
+            "stream": {"job": "api", "region": "eu-1"},
+            "values": [
+                ["1700000000000000001", "GET /health 200"],
+                ["1700000001000000000", "GET /users 500", {"trace_id": "abc"}]
+            ]
+        }]}"#;
+        let streams = decode_json(body).unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].label("job"), Some("api"));
+        assert_eq!(streams[0].entries.len(), 2);
+        assert_eq!(streams[0].entries[0].timestamp.timestamp_nanos_opt(), Some(1_700_000_000_000_000_001));
+        assert_eq!(streams[0].entries[1].metadata, vec![("trace_id".to_string(), "abc".to_string())]);
+
+        assert!(decode_json(br#"{"streams": [{"stream": {}, "values": [["soon", "x"]]}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_decode_protobuf() {
+        let request = PushRequest {
+            streams: vec![StreamAdapter {
+                labels: r#"{job="varlogs", filename="/var/log/syslog"}"#.to_string(),
+                entries: vec![EntryAdapter {
+                    timestamp: Some(Timestamp {
+                        seconds: 1_700_000_000,
+                        nanos: 5,
+                    }),
+                    line: "disk quota exceeded for user 42".to_string(),
+                    structured_metadata: vec![LabelPairAdapter {
+                        name: "pod".to_string(),
+                        value: "api-7f9".to_string(),
+                    }],
+                }],
+                hash: 0,
+            }],
+        };
+        let body = snap::raw::Encoder::new().compress_vec(&request.encode_to_vec()).unwrap();
+
+        let streams = decode_protobuf(&body).unwrap();
+        assert_eq!(streams[0].label("filename"), Some("/var/log/syslog"));
+        let entry = &streams[0].entries[0];
+        assert_eq!(entry.line, "disk quota exceeded for user 42");
+        assert_eq!(entry.timestamp.timestamp_nanos_opt(), Some(1_700_000_000_000_000_005));
+        assert_eq!(entry.metadata, vec![("pod".to_string(), "api-7f9".to_string())]);
+
+        // Uncompressed protobuf is rejected
+        assert!(decode_protobuf(&request.encode_to_vec()).is_err());
+        // Declared length over the limit, checked before allocating
+        assert!(decode_protobuf(&[0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+    }
+}
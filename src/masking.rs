@@ -0,0 +1,300 @@
+/// Input masking and normalization
+///
+/// Rewrites variable-looking tokens (UUIDs, IPs, hex, numbers, ...) to fixed
+/// placeholders and optionally folds case and collapses whitespace, so log
+/// lines that only differ in those values look the same. The matcher runs it
+/// on log lines before the fragment search and on template fragments when they
+/// are indexed; regex verification and variable extraction still see the raw line.
+use crate::pattern_learner::VariableType;
+use crate::token_classifier::is_ephemeral;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// Non-space runs, the tokens the ephemeral masker looks at
+static TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\S+").unwrap());
+/// Whitespace other than a single plain space
+static WHITESPACE_RUN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s{2,}|[^\S ]").unwrap());
+
+/// Built-in maskers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinMask {
+    /// Lowercase UUIDs -> `<UUID>`
+    Uuid,
+    /// IPv4 addresses -> `<IP>`
+    Ipv4,
+    /// 0x-prefixed hex and hex runs longer than 8 digits -> `<HEX>`
+    Hex,
+    /// Standalone decimal numbers -> `<NUM>`
+    Number,
+    /// Whitespace tokens the token classifier considers ephemeral
+    /// (timestamps, dates, month names, IPv6, ...) -> `<*>`
+    Ephemeral,
+}
+
+impl BuiltinMask {
+    pub fn placeholder(&self) -> &'static str {
+        match self {
+            BuiltinMask::Uuid => "<UUID>",
+            BuiltinMask::Ipv4 => "<IP>",
+            BuiltinMask::Hex => "<HEX>",
+            BuiltinMask::Number => "<NUM>",
+            BuiltinMask::Ephemeral => "<*>",
+        }
+    }
+
+    /// Word-bounded regex for the regex-based masks (None for token masks)
+    fn regex(&self) -> Option<Regex> {
+        let value = match self {
+            BuiltinMask::Uuid => VariableType::UUID.value_pattern(),
+            BuiltinMask::Ipv4 => VariableType::IPAddress.value_pattern(),
+            BuiltinMask::Number => VariableType::Number.value_pattern(),
+            // Same rule as the token classifier: bare short hex is too often a word
+            BuiltinMask::Hex => r"0x[0-9a-fA-F]+|[0-9a-fA-F]{9,}",
+            BuiltinMask::Ephemeral => return None,
+        };
+        Some(Regex::new(&format!(r"\b(?:{})\b", value)).unwrap())
+    }
+}
+
+impl FromStr for BuiltinMask {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "uuid" => Ok(BuiltinMask::Uuid),
+            "ip" | "ipv4" => Ok(BuiltinMask::Ipv4),
+            "hex" => Ok(BuiltinMask::Hex),
+            "number" | "num" => Ok(BuiltinMask::Number),
+            "ephemeral" => Ok(BuiltinMask::Ephemeral),
+            other => anyhow::bail!("unknown mask '{}' (expected uuid, ipv4, hex, number or ephemeral)", other),
+        }
+    }
+}
+
+/// User-supplied mask: every match of `pattern` is replaced with `replacement`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomMask {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaskingConfig {
+    /// Built-in masks, applied in order after the custom masks
+    pub builtin: Vec<BuiltinMask>,
+    /// User regex masks, applied first and in order
+    pub custom: Vec<CustomMask>,
+    /// Lowercase the line (after masking)
+    pub case_fold: bool,
+    /// Replace whitespace runs, tabs and newlines with a single space
+    pub collapse_whitespace: bool,
+}
+
+impl MaskingConfig {
+    /// Masking off (the default)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drain-style defaults: UUID, IPv4, hex and number masks plus whitespace collapsing
+    pub fn drain_defaults() -> Self {
+        Self {
+            builtin: vec![BuiltinMask::Uuid, BuiltinMask::Ipv4, BuiltinMask::Hex, BuiltinMask::Number],
+            collapse_whitespace: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_builtin(mut self, mask: BuiltinMask) -> Self {
+        if !self.builtin.contains(&mask) {
+            self.builtin.push(mask);
+        }
+        self
+    }
+
+    pub fn with_custom_mask(mut self, pattern: &str, replacement: &str) -> Self {
+        self.custom.push(CustomMask {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        });
+        self
+    }
+
+    pub fn with_case_folding(mut self, enabled: bool) -> Self {
+        self.case_fold = enabled;
+        self
+    }
+
+    pub fn with_whitespace_collapsing(mut self, enabled: bool) -> Self {
+        self.collapse_whitespace = enabled;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.builtin.is_empty() || !self.custom.is_empty() || self.case_fold || self.collapse_whitespace
+    }
+
+    /// Check that every custom mask compiles
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for mask in &self.custom {
+            Regex::new(&mask.pattern)
+                .map_err(|e| anyhow::anyhow!("invalid mask regex '{}': {}", mask.pattern, e))?;
+        }
+        Ok(())
+    }
+}
+
+enum Rule {
+    Replace(Regex, String),
+    EphemeralTokens,
+}
+
+impl Rule {
+    fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            // NoExpand: replacements are literal text like <IP>, not $group references
+            Rule::Replace(regex, replacement) => regex.replace_all(text, regex::NoExpand(replacement)),
+            Rule::EphemeralTokens => TOKEN.replace_all(text, |caps: &regex::Captures| {
+                let token = &caps[0];
+                // Keep surrounding punctuation such as "[1234]:" or "value,"
+                let core = token.trim_matches(|c: char| "[](){},;\"'".contains(c));
+                if !core.is_empty() && is_ephemeral(core) {
+                    token.replacen(core, BuiltinMask::Ephemeral.placeholder(), 1)
+                } else {
+                    token.to_string()
+                }
+            }),
+        }
+    }
+}
+
+/// Compiled `MaskingConfig`
+pub struct Masker {
+    rules: Vec<Rule>,
+    case_fold: bool,
+    collapse_whitespace: bool,
+}
+
+impl Masker {
+    /// Compile a masking config. Custom masks that fail to compile are skipped
+    /// with a warning; use `MaskingConfig::validate` to reject them up front.
+    pub fn new(config: &MaskingConfig) -> Self {
+        let mut rules = Vec::new();
+
+        for mask in &config.custom {
+            match Regex::new(&mask.pattern) {
+                Ok(regex) => rules.push(Rule::Replace(regex, mask.replacement.clone())),
+                Err(e) => tracing::warn!("Skipping invalid mask regex '{}': {}", mask.pattern, e),
+            }
+        }
+
+        for mask in &config.builtin {
+            rules.push(match mask.regex() {
+                Some(regex) => Rule::Replace(regex, mask.placeholder().to_string()),
+                None => Rule::EphemeralTokens,
+            });
+        }
+
+        Self {
+            rules,
+            case_fold: config.case_fold,
+            collapse_whitespace: config.collapse_whitespace,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty() || self.case_fold || self.collapse_whitespace
+    }
+
+    /// Mask and normalize a line. Borrows the input when nothing changes.
+    pub fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(line);
+
+        for rule in &self.rules {
+            let replaced = match rule.apply(&text) {
+                Cow::Owned(replaced) => Some(replaced),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(replaced) = replaced {
+                text = Cow::Owned(replaced);
+            }
+        }
+
+        if self.collapse_whitespace {
+            let collapsed = match WHITESPACE_RUN.replace_all(&text, " ") {
+                Cow::Owned(collapsed) => Some(collapsed),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(collapsed) = collapsed {
+                text = Cow::Owned(collapsed);
+            }
+        }
+
+        if self.case_fold && text.chars().any(char::is_uppercase) {
+            text = Cow::Owned(text.to_lowercase());
+        }
+
+        text
+    }
+}
+
+impl Default for Masker {
+    fn default() -> Self {
+        Self::new(&MaskingConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_masks() {
+        let masker = Masker::new(&MaskingConfig::drain_defaults());
+
+        assert_eq!(
+            masker.apply("req 550e8400-e29b-41d4-a716-446655440000 from 10.0.0.12 took 35 ms"),
+            "req <UUID> from <IP> took <NUM> ms"
+        );
+        assert_eq!(masker.apply("ptr=0x7ffe12 hash deadbeef00 add bad"), "ptr=<HEX> hash <HEX> add bad");
+        // Digits inside words are not numbers
+        assert_eq!(masker.apply("mounted sda1 on  /mnt\tok"), "mounted sda1 on /mnt ok");
+
+        // Nothing to mask: no allocation
+        assert!(matches!(masker.apply("plain text"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_ephemeral_and_custom_masks() {
+        let config = MaskingConfig::new()
+            .with_custom_mask(r"user=\w+", "user=<USER>")
+            .with_builtin(BuiltinMask::Ephemeral)
+            .with_case_folding(true);
+        let masker = Masker::new(&config);
+
+        assert_eq!(
+            masker.apply("Jun 14 15:16:01 sshd[19939]: Failed login user=root"),
+            "<*> <*> <*> sshd[19939]: failed login user=<user>"
+        );
+        assert_eq!(masker.apply("pid [4242] exited"), "pid [<*>] exited");
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(!MaskingConfig::default().is_enabled());
+        assert!(!Masker::default().is_enabled());
+        assert!(MaskingConfig::new().with_whitespace_collapsing(true).is_enabled());
+
+        assert_eq!("IPv4".parse::<BuiltinMask>().unwrap(), BuiltinMask::Ipv4);
+        assert!("mac".parse::<BuiltinMask>().is_err());
+
+        let bad = MaskingConfig::new().with_custom_mask(r"(unclosed", "x");
+        assert!(bad.validate().is_err());
+        // Invalid custom masks are skipped rather than failing the matcher
+        assert!(!Masker::new(&bad).is_enabled());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::masking::MaskingConfig;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,6 +8,19 @@ pub struct MatcherConfig {
     pub cache_regex: bool,
     pub optimal_batch_size: usize,
     pub fragment_match_threshold: f64,
+    /// Confirm fragment-scored candidates against their compiled regex
+    #[serde(default)]
+    pub verify_with_regex: bool,
+    /// Maximum number of top-scoring candidates to verify before giving up
+    #[serde(default = "default_max_verify_candidates")]
+    pub max_verify_candidates: usize,
+    /// Masking applied to log lines and template fragments before fragment search
+    #[serde(default)]
+    pub masking: MaskingConfig,
+}
+
+fn default_max_verify_candidates() -> usize {
+    8
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -24,6 +38,9 @@ impl Default for MatcherConfig {
             cache_regex: true,
             optimal_batch_size: 10_000,
             fragment_match_threshold: 0.3,
+            verify_with_regex: false,
+            max_verify_candidates: default_max_verify_candidates(),
+            masking: MaskingConfig::default(),
         }
     }
 }
@@ -79,6 +96,21 @@ impl MatcherConfig {
         self
     }
 
+    pub fn with_regex_verification(mut self, enabled: bool) -> Self {
+        self.verify_with_regex = enabled;
+        self
+    }
+
+    pub fn with_max_verify_candidates(mut self, count: usize) -> Self {
+        self.max_verify_candidates = count.max(1);
+        self
+    }
+
+    pub fn with_masking(mut self, masking: MaskingConfig) -> Self {
+        self.masking = masking;
+        self
+    }
+
     pub(crate) fn to_ac_match_kind(&self) -> aho_corasick::MatchKind {
         match self.match_kind {
             MatchKind::LeftmostLongest => aho_corasick::MatchKind::LeftmostLongest,
@@ -99,6 +131,9 @@ mod tests {
         assert_eq!(config.optimal_batch_size, 10_000);
         assert_eq!(config.fragment_match_threshold, 0.3);
         assert!(config.cache_regex);
+        assert!(!config.verify_with_regex);
+        assert_eq!(config.max_verify_candidates, 8);
+        assert!(!config.masking.is_enabled());
     }
 
     #[test]
@@ -123,4 +158,29 @@ mod tests {
         assert_eq!(config.min_fragment_length, 3);
         assert_eq!(config.optimal_batch_size, 5_000);
     }
+
+    #[test]
+    fn test_verification_builder() {
+        let config = MatcherConfig::new()
+            .with_regex_verification(true)
+            .with_max_verify_candidates(0);
+
+        assert!(config.verify_with_regex);
+        assert_eq!(config.max_verify_candidates, 1);
+    }
+
+    #[test]
+    fn test_deserialize_config_without_verification_fields() {
+        let json = r#"{
+            "match_kind": "LeftmostLongest",
+            "min_fragment_length": 2,
+            "cache_regex": true,
+            "optimal_batch_size": 1000,
+            "fragment_match_threshold": 0.5
+        }"#;
+        let config: MatcherConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.min_fragment_length, 2);
+        assert!(!config.verify_with_regex);
+        assert_eq!(config.max_verify_candidates, 8);
+    }
 }
@@ -0,0 +1,527 @@
+/// Per-tenant matcher registry
+///
+/// Keeps a separate `LogMatcher` (and therefore a separate template set and
+/// ID space) per (org_id, log_stream_id), plus an optional shared global
+/// matcher that tenants fall back to when none of their own templates match.
+///
+/// The tenant map is swapped with RCU like the matcher snapshots, so lookups
+/// on the ingest path are lock-free; only the first template for a new
+/// tenant clones the map.
+///
+/// Tenants can also have a header format: templates of such a stream only
+/// see the `<Content>` part of each line.
+use crate::clickhouse_client::TemplateRow;
+use crate::log_format_detector::HeaderFormat;
+use crate::log_matcher::{content_template_id, LogMatcher, LogTemplate, LogType, TemplateStats};
+use crate::matcher_config::MatcherConfig;
+use arc_swap::ArcSwap;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Reserved org_id / log_stream_id for the shared global template set
+pub const GLOBAL_ORG_ID: &str = "_global";
+pub const GLOBAL_LOG_STREAM_ID: &str = "_global";
+
+/// Template set a line matched in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchSource {
+    Tenant,
+    /// The shared global set, directly or as the tenant's fallback
+    Global,
+}
+
+/// Matched template of a line. Tenant and global IDs are separate ID spaces
+/// (explicit IDs such as the built-in defaults can exist in both), so the
+/// source says which matcher the ID belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemplateMatch {
+    pub template_id: u64,
+    pub source: MatchSource,
+}
+
+fn tag_matches(template_ids: Vec<Option<u64>>, source: MatchSource) -> Vec<Option<TemplateMatch>> {
+    template_ids
+        .into_iter()
+        .map(|template_id| template_id.map(|template_id| TemplateMatch { template_id, source }))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TenantKey {
+    pub org_id: String,
+    pub log_stream_id: String,
+}
+
+impl TenantKey {
+    pub fn new(org_id: &str, log_stream_id: &str) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            log_stream_id: log_stream_id.to_string(),
+        }
+    }
+
+    /// Key of the shared global template set
+    pub fn global() -> Self {
+        Self::new(GLOBAL_ORG_ID, GLOBAL_LOG_STREAM_ID)
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.org_id == GLOBAL_ORG_ID && self.log_stream_id == GLOBAL_LOG_STREAM_ID
+    }
+
+    /// Namespace for content-addressed template IDs: `<org_id length>:<org_id>/<log_stream_id>`.
+    /// Both IDs may contain `/`, so the length prefix keeps org `a/b` + stream `c`
+    /// apart from org `a` + stream `b/c`. The global set uses the empty
+    /// namespace, the same one a standalone `LogMatcher` uses.
+    pub fn id_namespace(&self) -> String {
+        if self.is_global() {
+            String::new()
+        } else {
+            format!("{}:{}/{}", self.org_id.len(), self.org_id, self.log_stream_id)
+        }
+    }
+
+    /// Content-addressed ID of `pattern` in this tenant
+    pub fn template_id(&self, pattern: &str) -> u64 {
+        content_template_id(&self.id_namespace(), pattern)
+    }
+}
+
+pub struct MatcherRegistry {
+    tenants: ArcSwap<FxHashMap<TenantKey, Arc<LogMatcher>>>,
+    header_formats: ArcSwap<FxHashMap<TenantKey, Arc<HeaderFormat>>>,
+    global: Arc<LogMatcher>,
+    config: MatcherConfig,
+    fallback_to_global: bool,
+}
+
+impl MatcherRegistry {
+    /// Create a registry with an empty global set and global fallback enabled
+    pub fn new(config: MatcherConfig) -> Self {
+        Self::with_global(LogMatcher::empty(config.clone()), config)
+    }
+
+    /// Create a registry around an existing global matcher
+    pub fn with_global(global: LogMatcher, config: MatcherConfig) -> Self {
+        Self {
+            tenants: ArcSwap::new(Arc::new(FxHashMap::default())),
+            header_formats: ArcSwap::new(Arc::new(FxHashMap::default())),
+            global: Arc::new(global),
+            config,
+            fallback_to_global: true,
+        }
+    }
+
+    /// Enable or disable falling back to the global set for unmatched tenant logs
+    pub fn with_global_fallback(mut self, enabled: bool) -> Self {
+        self.fallback_to_global = enabled;
+        self
+    }
+
+    pub fn config(&self) -> &MatcherConfig {
+        &self.config
+    }
+
+    pub fn global_fallback(&self) -> bool {
+        self.fallback_to_global
+    }
+
+    pub fn global(&self) -> &Arc<LogMatcher> {
+        &self.global
+    }
+
+    /// Get the matcher for a tenant (the global matcher for the global key)
+    pub fn get(&self, tenant: &TenantKey) -> Option<Arc<LogMatcher>> {
+        if tenant.is_global() {
+            return Some(self.global.clone());
+        }
+        self.tenants.load().get(tenant).cloned()
+    }
+
+    /// Get the matcher for a tenant, creating an empty one if needed
+    pub fn get_or_create(&self, tenant: &TenantKey) -> Arc<LogMatcher> {
+        if let Some(matcher) = self.get(tenant) {
+            return matcher;
+        }
+
+        let config = self.config.clone();
+        self.tenants.rcu(|old| {
+            if old.contains_key(tenant) {
+                return Arc::clone(old);
+            }
+            let mut tenants = (**old).clone();
+            tenants.insert(tenant.clone(), Arc::new(LogMatcher::empty(config.clone())));
+            Arc::new(tenants)
+        });
+
+        // The entry exists now (inserted by us or by a concurrent caller)
+        self.tenants.load().get(tenant).cloned().expect("tenant matcher was just inserted")
+    }
+
+    /// Add a template to a tenant's set. A template_id of 0 gets the tenant's
+    /// content-addressed ID for the pattern. Fails if the ID is already used
+    /// by a different pattern.
+    pub fn add_template(&self, tenant: &TenantKey, mut template: LogTemplate) -> anyhow::Result<()> {
+        if template.template_id == 0 {
+            template.template_id = tenant.template_id(&template.pattern);
+        }
+        self.get_or_create(tenant).add_template(template)
+    }
+
+    /// Add a batch of templates to a tenant's set with a single automaton build
+    pub fn add_templates(&self, tenant: &TenantKey, mut templates: Vec<LogTemplate>) -> anyhow::Result<()> {
+        for template in templates.iter_mut().filter(|t| t.template_id == 0) {
+            template.template_id = tenant.template_id(&template.pattern);
+        }
+        self.get_or_create(tenant).add_templates(templates)
+    }
+
+    /// Load stored templates, routing each row to its tenant's matcher
+    pub fn load_rows(&self, rows: Vec<TemplateRow>) {
+        let mut grouped: FxHashMap<TenantKey, (Vec<LogTemplate>, Vec<u64>)> = FxHashMap::default();
+
+        for row in rows {
+            let (templates, disabled) = grouped
+                .entry(TenantKey::new(&row.org_id, &row.log_stream_id))
+                .or_default();
+            if row.disabled {
+                disabled.push(row.template_id);
+            }
+            templates.push(row.to_template());
+        }
+
+        for (tenant, (templates, disabled)) in grouped {
+            let matcher = self.get_or_create(&tenant);
+            // On an ID collision, load one by one so only the colliding rows are skipped
+            if let Err(e) = matcher.add_templates(templates.clone()) {
+                tracing::warn!("Templates of {}/{}: {}", tenant.org_id, tenant.log_stream_id, e);
+                for template in templates {
+                    if let Err(e) = matcher.add_template(template) {
+                        tracing::warn!("Skipping template of {}/{}: {}", tenant.org_id, tenant.log_stream_id, e);
+                    }
+                }
+            }
+            for template_id in disabled {
+                matcher.set_template_disabled(template_id, true);
+            }
+        }
+    }
+
+    /// Match a batch of logs for one tenant, falling back to the global set
+    pub fn match_batch(&self, tenant: &TenantKey, log_lines: &[&str]) -> Vec<Option<TemplateMatch>> {
+        self.match_with(tenant, log_lines, |matcher, lines| matcher.match_batch(lines))
+    }
+
+    /// Parallel variant of `match_batch` for large batches
+    pub fn match_batch_parallel(&self, tenant: &TenantKey, log_lines: &[&str]) -> Vec<Option<TemplateMatch>> {
+        self.match_with(tenant, log_lines, |matcher, lines| matcher.match_batch_parallel(lines))
+    }
+
+    fn match_with<F>(&self, tenant: &TenantKey, log_lines: &[&str], match_fn: F) -> Vec<Option<TemplateMatch>>
+    where
+        F: Fn(&LogMatcher, &[&str]) -> Vec<Option<u64>>,
+    {
+        if tenant.is_global() {
+            return tag_matches(match_fn(&self.global, log_lines), MatchSource::Global);
+        }
+
+        let mut results = match self.tenants.load().get(tenant) {
+            Some(matcher) => tag_matches(match_fn(matcher, log_lines), MatchSource::Tenant),
+            None => vec![None; log_lines.len()],
+        };
+
+        if !self.fallback_to_global {
+            return results;
+        }
+
+        // Second pass: only the lines the tenant set didn't match
+        let unmatched: Vec<usize> = (0..log_lines.len()).filter(|&i| results[i].is_none()).collect();
+        if unmatched.is_empty() {
+            return results;
+        }
+
+        let unmatched_lines: Vec<&str> = unmatched.iter().map(|&i| log_lines[i]).collect();
+        let global_results = match_fn(&self.global, &unmatched_lines);
+        for (idx, result) in unmatched.into_iter().zip(tag_matches(global_results, MatchSource::Global)) {
+            results[idx] = result;
+        }
+
+        results
+    }
+
+    /// Matcher a match came from
+    fn source_matcher(&self, tenant: &TenantKey, source: MatchSource) -> Option<Arc<LogMatcher>> {
+        match source {
+            MatchSource::Global => Some(self.global.clone()),
+            MatchSource::Tenant if tenant.is_global() => None,
+            MatchSource::Tenant => self.tenants.load().get(tenant).cloned(),
+        }
+    }
+
+    /// Log types for the results of `match_batch`, each looked up in the
+    /// template set that matched
+    pub fn log_type_ids(&self, tenant: &TenantKey, matches: &[Option<TemplateMatch>]) -> Vec<Option<u64>> {
+        let mut log_type_ids = vec![None; matches.len()];
+        for source in [MatchSource::Tenant, MatchSource::Global] {
+            let Some(matcher) = self.source_matcher(tenant, source) else {
+                continue;
+            };
+            let template_ids: Vec<Option<u64>> = matches
+                .iter()
+                .map(|m| m.filter(|m| m.source == source).map(|m| m.template_id))
+                .collect();
+            for (log_type_id, found) in log_type_ids.iter_mut().zip(matcher.log_type_ids(&template_ids)) {
+                *log_type_id = log_type_id.or(found);
+            }
+        }
+        log_type_ids
+    }
+
+    /// Variable values of a matched line, from the template that matched it
+    pub fn extract_variables(&self, tenant: &TenantKey, matched: TemplateMatch, log_line: &str) -> Option<HashMap<String, String>> {
+        self.source_matcher(tenant, matched.source)?.extract_variables(matched.template_id, log_line)
+    }
+
+    /// Log types of every tenant and the global set (listed first)
+    pub fn log_types(&self) -> Vec<(TenantKey, Vec<LogType>)> {
+        let mut log_types = vec![(TenantKey::global(), self.global.log_types())];
+        let mut tenants: Vec<(TenantKey, Vec<LogType>)> = self
+            .tenants
+            .load()
+            .iter()
+            .map(|(tenant, matcher)| (tenant.clone(), matcher.log_types()))
+            .collect();
+        tenants.sort_by(|a, b| (&a.0.org_id, &a.0.log_stream_id).cmp(&(&b.0.org_id, &b.0.log_stream_id)));
+        log_types.extend(tenants);
+        log_types
+    }
+
+    /// Set or clear the header format of a tenant's log lines
+    pub fn set_header_format(&self, tenant: &TenantKey, format: Option<HeaderFormat>) {
+        let format = format.map(Arc::new);
+        self.header_formats.rcu(|old| {
+            let mut formats = (**old).clone();
+            match &format {
+                Some(format) => formats.insert(tenant.clone(), format.clone()),
+                None => formats.remove(tenant),
+            };
+            Arc::new(formats)
+        });
+    }
+
+    pub fn header_format(&self, tenant: &TenantKey) -> Option<Arc<HeaderFormat>> {
+        self.header_formats.load().get(tenant).cloned()
+    }
+
+    /// All tenants with their own template set
+    pub fn tenants(&self) -> Vec<TenantKey> {
+        self.tenants.load().keys().cloned().collect()
+    }
+
+    pub fn tenant_count(&self) -> usize {
+        self.tenants.load().len()
+    }
+
+    /// Per-template hit counts for every tenant and the global set (listed first)
+    pub fn template_stats(&self) -> Vec<(TenantKey, Vec<TemplateStats>)> {
+        let mut stats = vec![(TenantKey::global(), self.global.template_stats())];
+        let mut tenants: Vec<(TenantKey, Vec<TemplateStats>)> = self
+            .tenants
+            .load()
+            .iter()
+            .map(|(tenant, matcher)| (tenant.clone(), matcher.template_stats()))
+            .collect();
+        tenants.sort_by(|a, b| (&a.0.org_id, &a.0.log_stream_id).cmp(&(&b.0.org_id, &b.0.log_stream_id)));
+        stats.extend(tenants);
+        stats
+    }
+
+    /// Total number of templates across all tenants and the global set
+    pub fn template_count(&self) -> usize {
+        self.global.template_count()
+            + self
+                .tenants
+                .load()
+                .values()
+                .map(|matcher| matcher.template_count())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(matches: Vec<Option<TemplateMatch>>) -> Vec<Option<u64>> {
+        matches.into_iter().map(|m| m.map(|m| m.template_id)).collect()
+    }
+
+    fn template(template_id: u64, pattern: &str, example: &str) -> LogTemplate {
+        LogTemplate {
+            template_id,
+            pattern: pattern.to_string(),
+            variables: vec!["value".to_string()],
+            example: example.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let registry = MatcherRegistry::new(MatcherConfig::default()).with_global_fallback(false);
+        let acme = TenantKey::new("acme", "api");
+        let globex = TenantKey::new("globex", "api");
+
+        // Same template_id in two tenants must not collide
+        registry.add_template(&acme, template(1, r"order (\d+) shipped", "order 7 shipped")).unwrap();
+        registry.add_template(&globex, template(1, r"payment (\d+) declined", "payment 9 declined")).unwrap();
+
+        assert_eq!(ids(registry.match_batch(&acme, &["order 12 shipped"])), vec![Some(1)]);
+        assert_eq!(ids(registry.match_batch(&acme, &["payment 12 declined"])), vec![None]);
+        assert_eq!(ids(registry.match_batch(&globex, &["payment 12 declined"])), vec![Some(1)]);
+        assert_eq!(ids(registry.match_batch(&globex, &["order 12 shipped"])), vec![None]);
+
+        // Without fallback, the global set is only used for the global key itself
+        registry.add_template(&TenantKey::global(), template(100, r"heartbeat from (\w+)", "heartbeat from node1")).unwrap();
+        assert_eq!(ids(registry.match_batch(&acme, &["heartbeat from node3"])), vec![None]);
+        assert_eq!(ids(registry.match_batch(&TenantKey::global(), &["heartbeat from node3"])), vec![Some(100)]);
+
+        assert_eq!(registry.tenant_count(), 2);
+        assert_eq!(registry.template_count(), 3);
+    }
+
+    #[test]
+    fn test_global_fallback() {
+        let registry = MatcherRegistry::new(MatcherConfig::default());
+        let acme = TenantKey::new("acme", "api");
+
+        registry.add_template(&TenantKey::global(), template(100, r"heartbeat from (\w+)", "heartbeat from node1")).unwrap();
+        registry.add_template(&acme, template(1, r"order (\d+) shipped", "order 7 shipped")).unwrap();
+
+        let logs = ["order 12 shipped", "heartbeat from node3", "something else"];
+        assert_eq!(ids(registry.match_batch(&acme, &logs)), vec![Some(1), Some(100), None]);
+        assert_eq!(ids(registry.match_batch_parallel(&acme, &logs)), vec![Some(1), Some(100), None]);
+
+        // Unknown tenants only see the global set
+        let unknown = TenantKey::new("initech", "web");
+        assert_eq!(ids(registry.match_batch(&unknown, &logs)), vec![None, Some(100), None]);
+        assert!(registry.get(&unknown).is_none());
+
+        // Global set is not registered as a tenant
+        assert_eq!(registry.tenant_count(), 1);
+
+        // Fallback hits are counted against the global template
+        let stats = registry.template_stats();
+        assert_eq!(stats[0].0, TenantKey::global());
+        assert_eq!(stats[0].1[0].hits, 3);
+        assert_eq!(stats[1].0, acme);
+        assert_eq!(stats[1].1[0].hits, 2);
+
+        // Fallback matches get the global template's log type
+        let log_type_ids = registry.log_type_ids(&acme, &registry.match_batch(&acme, &logs));
+        assert_eq!(log_type_ids[0], registry.get(&acme).unwrap().log_type_id(1));
+        assert_eq!(log_type_ids[1], registry.global().log_type_id(100));
+        assert_eq!(log_type_ids[2], None);
+        assert!(log_type_ids[0].is_some() && log_type_ids[1].is_some());
+    }
+
+    #[test]
+    fn test_fallback_uses_global_template() {
+        let registry = MatcherRegistry::new(MatcherConfig::default());
+        let acme = TenantKey::new("acme", "api");
+
+        // Explicit IDs: the same ID means different templates in each set
+        registry.add_template(&TenantKey::global(), template(1, r"heartbeat from (\w+)", "heartbeat from node1")).unwrap();
+        registry.add_template(&acme, template(1, r"order (\d+) shipped", "order 7 shipped")).unwrap();
+
+        let logs = ["order 12 shipped", "heartbeat from node3"];
+        let matches = registry.match_batch(&acme, &logs);
+        assert_eq!(matches[0], Some(TemplateMatch { template_id: 1, source: MatchSource::Tenant }));
+        assert_eq!(matches[1], Some(TemplateMatch { template_id: 1, source: MatchSource::Global }));
+
+        let log_type_ids = registry.log_type_ids(&acme, &matches);
+        assert_eq!(log_type_ids[0], registry.get(&acme).unwrap().log_type_id(1));
+        assert_eq!(log_type_ids[1], registry.global().log_type_id(1));
+        assert_ne!(log_type_ids[0], log_type_ids[1]);
+
+        let heartbeat = registry.extract_variables(&acme, matches[1].unwrap(), logs[1]).unwrap();
+        assert_eq!(heartbeat.values().collect::<Vec<_>>(), vec!["node3"]);
+        let order = registry.extract_variables(&acme, matches[0].unwrap(), logs[0]).unwrap();
+        assert_eq!(order.values().collect::<Vec<_>>(), vec!["12"]);
+
+        // The global tenant only has global matches
+        let matches = registry.match_batch(&TenantKey::global(), &logs);
+        assert_eq!(matches[1].map(|m| m.source), Some(MatchSource::Global));
+        assert_eq!(registry.log_type_ids(&TenantKey::global(), &matches)[1], registry.global().log_type_id(1));
+    }
+
+    #[test]
+    fn test_header_formats() {
+        let registry = MatcherRegistry::new(MatcherConfig::default());
+        let acme = TenantKey::new("acme", "api");
+        let format = HeaderFormat::parse("<Date> <Time> <Level>: <Content>").unwrap();
+
+        assert!(registry.header_format(&acme).is_none());
+        registry.set_header_format(&acme, Some(format.clone()));
+        assert_eq!(*registry.header_format(&acme).unwrap(), format);
+        assert!(registry.header_format(&TenantKey::new("acme", "worker")).is_none());
+
+        registry.set_header_format(&acme, None);
+        assert!(registry.header_format(&acme).is_none());
+    }
+
+    #[test]
+    fn test_load_rows_groups_by_tenant() {
+        let registry = MatcherRegistry::new(MatcherConfig::default());
+
+        let row = |org: &str, stream: &str, id: u64, pattern: &str, disabled: bool| TemplateRow {
+            disabled,
+            ..TemplateRow::from_template(org, stream, &LogTemplate {
+                template_id: id,
+                pattern: pattern.to_string(),
+                ..Default::default()
+            })
+        };
+
+        registry.load_rows(vec![
+            row("acme", "api", 1, r"order (\d+) shipped", false),
+            row("acme", "api", 2, r"refund (\d+) cancelled", true),
+            row("acme", "worker", 1, r"job (\d+) done", false),
+        ]);
+
+        let api = TenantKey::new("acme", "api");
+        let worker = TenantKey::new("acme", "worker");
+
+        assert_eq!(ids(registry.match_batch(&api, &["order 3 shipped", "refund 3 cancelled"])), vec![Some(1), None]);
+        assert_eq!(ids(registry.match_batch(&worker, &["job 4 done"])), vec![Some(1)]);
+        assert!(registry.get(&api).unwrap().is_template_disabled(2));
+        assert_eq!(registry.template_count(), 3);
+    }
+
+    #[test]
+    fn test_content_addressed_ids() {
+        let acme = TenantKey::new("acme", "api");
+        let pattern = r"order (\d+) shipped";
+
+        // Two replicas assign the same ID to the same template
+        let first = MatcherRegistry::new(MatcherConfig::default());
+        let second = MatcherRegistry::new(MatcherConfig::default());
+        first.add_template(&acme, template(0, pattern, "order 7 shipped")).unwrap();
+        second.add_templates(&acme, vec![template(0, r"order (?P<id>\d+) shipped", "order 7 shipped")]).unwrap();
+
+        let id = acme.template_id(pattern);
+        assert_eq!(ids(first.match_batch(&acme, &["order 1 shipped"])), vec![Some(id)]);
+        assert_eq!(ids(second.match_batch(&acme, &["order 1 shipped"])), vec![Some(id)]);
+
+        // Scoped by tenant; the global set shares the standalone matcher's namespace
+        assert_ne!(TenantKey::new("acme", "worker").template_id(pattern), id);
+        assert_eq!(TenantKey::global().id_namespace(), "");
+
+        // A `/` inside an ID can't make two tenants share a namespace
+        let (slash_org, slash_stream) = (TenantKey::new("a/b", "c"), TenantKey::new("a", "b/c"));
+        assert_ne!(slash_org.id_namespace(), slash_stream.id_namespace());
+        assert_ne!(slash_org.template_id(pattern), slash_stream.template_id(pattern));
+    }
+}
@@ -0,0 +1,351 @@
+/// Multi-line event assembly
+///
+/// Stack traces and other continuation lines arrive as separate physical
+/// lines. The assembler joins them with `\n` into one logical record, using
+/// start-of-record rules (timestamp prefix, indentation, markers such as
+/// `Caused by:`), a line cap and an idle timeout for open records on streams.
+/// The matcher verifies multi-line records against their first line when the
+/// whole record doesn't match, so single-line templates keep working.
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Common timestamp prefixes: ISO 8601, syslog, LogHub-style `yyMMdd HHmmss` and `yy/MM/dd HH:mm:ss`
+pub const TIMESTAMP_PREFIX: &str = r"^\[?(?:\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}|[A-Z][a-z]{2}\s+\d{1,2}\s+\d{2}:\d{2}:\d{2}|\d{6} \d{6}|\d{2}/\d{2}/\d{2} \d{2}:\d{2}:\d{2})";
+
+/// Exception header lines that follow the log message: `java.io.IOException: closed`, `ValueError: bad`
+const EXCEPTION_LINE: &str = r"^(?:[A-Za-z_$][\w$]*\.)*[A-Z][\w$]*(?:Exception|Error)(?::|$)";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MultilineConfig {
+    /// Regex a record's first line matches, typically a timestamp prefix.
+    /// When set, every other line continues the current record and the
+    /// indentation and marker rules are not used.
+    pub start_pattern: Option<String>,
+    /// Regex for lines that always continue the current record. Checked first,
+    /// so it also covers sources that repeat their header on every traceback line.
+    pub continuation_pattern: Option<String>,
+    /// Lines starting with a space or tab continue the current record
+    pub indented_continuation: bool,
+    /// Lines starting with one of these (after leading whitespace) continue the current record
+    pub continuation_markers: Vec<String>,
+    /// A record is cut after this many lines
+    pub max_lines: usize,
+    /// Stream ingestion flushes an open record after this long without a new line
+    pub flush_timeout_ms: u64,
+}
+
+impl Default for MultilineConfig {
+    fn default() -> Self {
+        Self {
+            start_pattern: None,
+            continuation_pattern: Some(EXCEPTION_LINE.to_string()),
+            indented_continuation: true,
+            continuation_markers: vec![
+                "Caused by:".to_string(),
+                "Traceback (most recent call last):".to_string(),
+                "During handling of the above exception".to_string(),
+                "The above exception was the direct cause".to_string(),
+                "... ".to_string(),
+            ],
+            max_lines: 500,
+            flush_timeout_ms: 1000,
+        }
+    }
+}
+
+impl MultilineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records start at lines with a common timestamp prefix (`TIMESTAMP_PREFIX`)
+    pub fn timestamp_prefix() -> Self {
+        Self::default().with_start_pattern(TIMESTAMP_PREFIX)
+    }
+
+    pub fn with_start_pattern(mut self, pattern: &str) -> Self {
+        self.start_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn with_continuation_pattern(mut self, pattern: &str) -> Self {
+        self.continuation_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn with_indented_continuation(mut self, enabled: bool) -> Self {
+        self.indented_continuation = enabled;
+        self
+    }
+
+    pub fn with_continuation_marker(mut self, marker: &str) -> Self {
+        if !self.continuation_markers.iter().any(|m| m == marker) {
+            self.continuation_markers.push(marker.to_string());
+        }
+        self
+    }
+
+    pub fn with_max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = lines.max(1);
+        self
+    }
+
+    pub fn with_flush_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.flush_timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn flush_timeout(&self) -> Duration {
+        Duration::from_millis(self.flush_timeout_ms)
+    }
+
+    /// Check that the start and continuation patterns compile
+    pub fn validate(&self) -> Result<()> {
+        compile(&self.start_pattern)?;
+        compile(&self.continuation_pattern)?;
+        Ok(())
+    }
+}
+
+fn compile(pattern: &Option<String>) -> Result<Option<Regex>> {
+    pattern
+        .as_deref()
+        .map(|p| Regex::new(p).map_err(|e| anyhow::anyhow!("invalid multiline regex '{}': {}", p, e)))
+        .transpose()
+}
+
+/// One assembled event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<M> {
+    /// Physical lines joined with `\n`
+    pub text: String,
+    /// Number of physical lines
+    pub lines: usize,
+    /// Metadata passed with the record's first line
+    pub meta: M,
+}
+
+/// Groups physical lines into records. `M` is per-record metadata taken from
+/// the first line (the request fields on streams, a line number for files).
+pub struct MultilineAssembler<M = ()> {
+    config: MultilineConfig,
+    start: Option<Regex>,
+    continuation: Option<Regex>,
+    pending: Option<Record<M>>,
+    last_line_at: Instant,
+}
+
+impl<M> MultilineAssembler<M> {
+    pub fn new(config: MultilineConfig) -> Result<Self> {
+        Ok(Self {
+            start: compile(&config.start_pattern)?,
+            continuation: compile(&config.continuation_pattern)?,
+            config,
+            pending: None,
+            last_line_at: Instant::now(),
+        })
+    }
+
+    pub fn config(&self) -> &MultilineConfig {
+        &self.config
+    }
+
+    /// Whether `line` continues the previous record rather than starting one
+    pub fn is_continuation(&self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            return true;
+        }
+        if self.continuation.as_ref().is_some_and(|re| re.is_match(line)) {
+            return true;
+        }
+        if let Some(start) = &self.start {
+            return !start.is_match(line);
+        }
+        if self.config.indented_continuation && line.starts_with([' ', '\t']) {
+            return true;
+        }
+        let trimmed = line.trim_start();
+        self.config
+            .continuation_markers
+            .iter()
+            .any(|marker| trimmed.starts_with(marker.as_str()))
+    }
+
+    /// Add a line. Returns the previous record once this line starts a new one
+    /// (or the current record hits `max_lines`).
+    pub fn push(&mut self, line: &str, meta: M) -> Option<Record<M>> {
+        self.push_at(line, meta, Instant::now())
+    }
+
+    /// `push` with an explicit arrival time, for idle tracking
+    pub fn push_at(&mut self, line: &str, meta: M, now: Instant) -> Option<Record<M>> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        self.last_line_at = now;
+
+        let continues = self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.lines < self.config.max_lines)
+            && self.is_continuation(line);
+        if continues {
+            let pending = self.pending.as_mut().unwrap();
+            pending.text.push('\n');
+            pending.text.push_str(line);
+            pending.lines += 1;
+            return None;
+        }
+
+        self.pending.replace(Record {
+            text: line.to_string(),
+            lines: 1,
+            meta,
+        })
+    }
+
+    /// Flush the open record if no line arrived for `flush_timeout_ms`
+    pub fn flush_idle(&mut self, now: Instant) -> Option<Record<M>> {
+        if now.saturating_duration_since(self.last_line_at) >= self.config.flush_timeout() {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+
+    /// Flush the open record unconditionally (end of file or shutdown)
+    pub fn flush(&mut self) -> Option<Record<M>> {
+        self.pending.take()
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// When the open record becomes idle, if there is one
+    pub fn idle_deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|_| self.last_line_at + self.config.flush_timeout())
+    }
+}
+
+/// Assemble a complete sequence of lines (file ingestion)
+pub fn assemble_lines<'a, I>(config: &MultilineConfig, lines: I) -> Result<Vec<String>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut assembler = MultilineAssembler::new(config.clone())?;
+    let mut records = Vec::new();
+    for line in lines {
+        records.extend(assembler.push(line, ()).map(|record| record.text));
+    }
+    records.extend(assembler.flush().map(|record| record.text));
+    Ok(records)
+}
+
+/// Read a log file as assembled records
+pub fn read_records(path: &Path, config: &MultilineConfig) -> Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut assembler = MultilineAssembler::new(config.clone())?;
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        records.extend(assembler.push(&line?, ()).map(|record| record.text));
+    }
+    records.extend(assembler.flush().map(|record| record.text));
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_traces() {
+        let java = [
+            "ERROR Request failed",
+            "java.lang.IllegalStateException: pool closed",
+            "\tat com.example.Pool.get(Pool.java:42)",
+            "\t... 12 more",
+            "Caused by: java.io.IOException: broken pipe",
+            "\tat com.example.Conn.write(Conn.java:7)",
+            "INFO Retrying request",
+        ];
+        let records = assemble_lines(&MultilineConfig::default(), java).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].starts_with("ERROR Request failed\njava.lang.IllegalStateException"));
+        assert!(records[0].ends_with("(Conn.java:7)"));
+        assert_eq!(records[1], "INFO Retrying request");
+
+        let python = [
+            "ERROR:root:job failed",
+            "Traceback (most recent call last):",
+            "  File \"job.py\", line 3, in <module>",
+            "    run()",
+            "ValueError: bad input",
+            "",
+            "INFO:root:next job",
+        ];
+        let records = assemble_lines(&MultilineConfig::default(), python).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].ends_with("ValueError: bad input\n"));
+        assert_eq!(records[1], "INFO:root:next job");
+    }
+
+    #[test]
+    fn test_start_and_continuation_patterns() {
+        // Timestamp prefix: anything without one continues the record
+        let lines = [
+            "2024-01-15 10:00:00 ERROR dump follows",
+            "state=broken",
+            "2024-01-15 10:00:01 INFO recovered",
+        ];
+        let records = assemble_lines(&MultilineConfig::timestamp_prefix(), lines).unwrap();
+        assert_eq!(records, ["2024-01-15 10:00:00 ERROR dump follows\nstate=broken", "2024-01-15 10:00:01 INFO recovered"]);
+
+        // OpenStack repeats the header on every traceback line
+        let config = MultilineConfig::new()
+            .with_start_pattern(r"^\S+ \d{4}-\d{2}-\d{2} ")
+            .with_continuation_pattern(r"^\S+ \S+ \S+ \d+ (?:ERROR|TRACE) \S+ (?:\[[^\]]*\] )?(?:Traceback|\s|[\w.]+(?:Error|Exception)\b)");
+        let lines = [
+            "nova-compute.log 2017-05-16 00:00:04.500 2931 ERROR nova.compute.manager [instance: b9000564] Instance failed to spawn",
+            "nova-compute.log 2017-05-16 00:00:04.500 2931 ERROR nova.compute.manager [instance: b9000564] Traceback (most recent call last):",
+            "nova-compute.log 2017-05-16 00:00:04.500 2931 ERROR nova.compute.manager [instance: b9000564]   File \"manager.py\", line 2078, in _build",
+            "nova-compute.log 2017-05-16 00:00:04.500 2931 ERROR nova.compute.manager [instance: b9000564] RescheduledException: boom",
+            "nova-compute.log 2017-05-16 00:00:05.100 2931 INFO nova.compute.manager [instance: b9000564] Terminating instance",
+        ];
+        let records = assemble_lines(&config, lines).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].lines().count(), 4);
+
+        assert!(MultilineConfig::new().with_start_pattern("(").validate().is_err());
+        assert!(MultilineAssembler::<()>::new(MultilineConfig::new().with_continuation_pattern("[")).is_err());
+    }
+
+    #[test]
+    fn test_idle_flush_and_line_cap() {
+        let config = MultilineConfig::default().with_flush_timeout_ms(100).with_max_lines(3);
+        let mut assembler = MultilineAssembler::new(config).unwrap();
+        let t0 = Instant::now();
+
+        assert!(assembler.push_at("ERROR first", 1, t0).is_none());
+        assert!(assembler.push_at("  at a", 2, t0).is_none());
+        assert_eq!(assembler.idle_deadline(), Some(t0 + Duration::from_millis(100)));
+        assert!(assembler.flush_idle(t0 + Duration::from_millis(50)).is_none());
+
+        let record = assembler.flush_idle(t0 + Duration::from_millis(100)).unwrap();
+        assert_eq!(record.text, "ERROR first\n  at a");
+        assert_eq!((record.lines, record.meta), (2, 1));
+        assert!(!assembler.has_pending());
+        assert!(assembler.idle_deadline().is_none());
+
+        // Continuations past max_lines start a new record
+        for (i, line) in ["ERROR second", "  at a", "  at b", "  at c"].iter().enumerate() {
+            let flushed = assembler.push_at(line, i, t0);
+            assert_eq!(flushed.is_some(), i == 3);
+        }
+        assert_eq!(assembler.flush().unwrap().text, "  at c");
+    }
+}
@@ -0,0 +1,519 @@
+/// OTLP log ingestion
+///
+/// Turns OpenTelemetry `ExportLogsServiceRequest`s (protobuf over gRPC or
+/// HTTP, or OTLP/JSON) into flat log records. Resource, scope and record
+/// attributes are mapped to the tenant and stream fields by `OtlpMapping`;
+/// the log body becomes the message.
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, ArrayValue, InstrumentationScope, KeyValue, KeyValueList};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// `service.name` of telemetry that doesn't set one (OpenTelemetry convention)
+pub const UNKNOWN_SERVICE: &str = "unknown_service";
+
+/// Attribute keys each log field is read from, tried in order. Record
+/// attributes are looked up first, then scope attributes, then resource
+/// attributes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtlpMapping {
+    pub org_id: Vec<String>,
+    /// Falls back to the service
+    pub log_stream_id: Vec<String>,
+    /// Falls back to `unknown_service`
+    pub service: Vec<String>,
+    pub region: Vec<String>,
+    /// Falls back to the instrumentation scope name, then the service
+    pub log_stream_name: Vec<String>,
+    /// Org of records without an org attribute
+    pub default_org_id: String,
+}
+
+impl Default for OtlpMapping {
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect();
+        Self {
+            org_id: keys(&["org_id", "org.id", "tenant.id"]),
+            log_stream_id: keys(&["log_stream_id", "log_stream.id"]),
+            service: keys(&["service.name"]),
+            region: keys(&["cloud.region"]),
+            log_stream_name: keys(&["log_stream_name", "log.file.path", "log.file.name", "k8s.container.name"]),
+            default_org_id: "default".to_string(),
+        }
+    }
+}
+
+impl OtlpMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default_org_id(mut self, org_id: &str) -> Self {
+        self.default_org_id = org_id.to_string();
+        self
+    }
+}
+
+/// One OTLP log record flattened into the ingest fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtlpLog {
+    /// Record time, else observed time; None when neither is set
+    pub timestamp: Option<DateTime<Utc>>,
+    pub org_id: String,
+    pub log_stream_id: String,
+    pub service: String,
+    pub region: String,
+    pub log_stream_name: String,
+    pub message: String,
+    /// `severity_text` as sent (may be empty)
+    pub severity_text: String,
+}
+
+/// Flatten an export request. Records with an empty body are skipped; the
+/// second value is their count, for the OTLP partial success response.
+pub fn logs_from_request(request: &ExportLogsServiceRequest, mapping: &OtlpMapping) -> (Vec<OtlpLog>, usize) {
+    let mut logs = Vec::new();
+    let mut rejected = 0;
+
+    for resource_logs in &request.resource_logs {
+        let resource_attributes = resource_logs.resource.as_ref().map(|r| r.attributes.as_slice()).unwrap_or(&[]);
+
+        for scope_logs in &resource_logs.scope_logs {
+            let scope = scope_logs.scope.as_ref();
+            let scope_attributes = scope.map(|s| s.attributes.as_slice()).unwrap_or(&[]);
+
+            for record in &scope_logs.log_records {
+                let message = record.body.as_ref().map(any_value_to_string).unwrap_or_default();
+                if message.is_empty() {
+                    rejected += 1;
+                    continue;
+                }
+
+                let levels = [record.attributes.as_slice(), scope_attributes, resource_attributes];
+                let lookup = |keys: &[String]| {
+                    keys.iter().find_map(|key| {
+                        levels
+                            .iter()
+                            .find_map(|attributes| attribute(attributes, key))
+                            .filter(|value| !value.is_empty())
+                    })
+                };
+
+                let service = lookup(&mapping.service).unwrap_or_else(|| UNKNOWN_SERVICE.to_string());
+                let log_stream_name = lookup(&mapping.log_stream_name)
+                    .or_else(|| scope.map(|s| s.name.clone()).filter(|name| !name.is_empty()))
+                    .unwrap_or_else(|| service.clone());
+
+                logs.push(OtlpLog {
+                    timestamp: record_timestamp(record),
+                    org_id: lookup(&mapping.org_id).unwrap_or_else(|| mapping.default_org_id.clone()),
+                    log_stream_id: lookup(&mapping.log_stream_id).unwrap_or_else(|| service.clone()),
+                    region: lookup(&mapping.region).unwrap_or_default(),
+                    log_stream_name,
+                    service,
+                    message,
+                    severity_text: record.severity_text.clone(),
+                });
+            }
+        }
+    }
+
+    (logs, rejected)
+}
+
+fn record_timestamp(record: &LogRecord) -> Option<DateTime<Utc>> {
+    [record.time_unix_nano, record.observed_time_unix_nano]
+        .into_iter()
+        .find(|&nanos| nanos > 0)
+        .and_then(|nanos| i64::try_from(nanos).ok())
+        .map(DateTime::from_timestamp_nanos)
+}
+
+/// Value of an attribute, rendered as a string
+fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.as_ref())
+        .map(any_value_to_string)
+}
+
+/// Strings as they are, scalars in their usual form, bytes as hex, and
+/// arrays and maps as JSON
+pub fn any_value_to_string(value: &AnyValue) -> String {
+    match &value.value {
+        Some(any_value::Value::StringValue(s)) => s.clone(),
+        Some(any_value::Value::BytesValue(bytes)) => bytes.iter().fold(String::new(), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        }),
+        Some(_) => match any_value_to_json(value) {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        },
+        None => String::new(),
+    }
+}
+
+fn any_value_to_json(value: &AnyValue) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match &value.value {
+        Some(any_value::Value::StringValue(s)) => Json::String(s.clone()),
+        Some(any_value::Value::BoolValue(b)) => Json::Bool(*b),
+        Some(any_value::Value::IntValue(i)) => Json::from(*i),
+        Some(any_value::Value::DoubleValue(d)) => serde_json::Number::from_f64(*d).map(Json::Number).unwrap_or(Json::Null),
+        Some(any_value::Value::ArrayValue(array)) => Json::Array(array.values.iter().map(any_value_to_json).collect()),
+        Some(any_value::Value::KvlistValue(list)) => Json::Object(
+            list.values
+                .iter()
+                .map(|kv| (kv.key.clone(), kv.value.as_ref().map(any_value_to_json).unwrap_or(Json::Null)))
+                .collect(),
+        ),
+        Some(any_value::Value::BytesValue(_)) => Json::String(any_value_to_string(value)),
+        None => Json::Null,
+    }
+}
+
+// OTLP/JSON: protobuf JSON mapping with lowerCamelCase names, 64-bit
+// integers as strings or numbers, and AnyValue as a one-key object
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonExportRequest {
+    #[serde(default)]
+    resource_logs: Vec<JsonResourceLogs>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonResourceLogs {
+    #[serde(default)]
+    resource: Option<JsonAttributes>,
+    #[serde(default)]
+    scope_logs: Vec<JsonScopeLogs>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonScopeLogs {
+    #[serde(default)]
+    scope: Option<JsonScope>,
+    #[serde(default)]
+    log_records: Vec<JsonLogRecord>,
+}
+
+#[derive(Deserialize, Default)]
+struct JsonAttributes {
+    #[serde(default)]
+    attributes: Vec<JsonKeyValue>,
+}
+
+#[derive(Deserialize)]
+struct JsonScope {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    attributes: Vec<JsonKeyValue>,
+}
+
+#[derive(Deserialize)]
+struct JsonKeyValue {
+    key: String,
+    #[serde(default)]
+    value: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLogRecord {
+    #[serde(default)]
+    time_unix_nano: Option<serde_json::Value>,
+    #[serde(default)]
+    observed_time_unix_nano: Option<serde_json::Value>,
+    #[serde(default)]
+    severity_number: Option<serde_json::Value>,
+    #[serde(default)]
+    severity_text: String,
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+    #[serde(default)]
+    attributes: Vec<JsonKeyValue>,
+}
+
+/// Integer fields may be JSON numbers or decimal strings
+fn json_u64(value: &Option<serde_json::Value>) -> Result<u64> {
+    match value {
+        None | Some(serde_json::Value::Null) => Ok(0),
+        Some(serde_json::Value::Number(n)) => n.as_u64().ok_or_else(|| anyhow::anyhow!("invalid integer {}", n)),
+        Some(serde_json::Value::String(s)) => Ok(s.parse()?),
+        Some(other) => anyhow::bail!("invalid integer {}", other),
+    }
+}
+
+fn json_any_value(value: &serde_json::Value) -> Result<AnyValue> {
+    let object = value.as_object().ok_or_else(|| anyhow::anyhow!("AnyValue must be an object"))?;
+    let Some((kind, inner)) = object.iter().next() else {
+        return Ok(AnyValue { value: None });
+    };
+
+    let value = match kind.as_str() {
+        "stringValue" => any_value::Value::StringValue(inner.as_str().unwrap_or_default().to_string()),
+        "boolValue" => any_value::Value::BoolValue(inner.as_bool().unwrap_or_default()),
+        "intValue" => any_value::Value::IntValue(match inner {
+            serde_json::Value::String(s) => s.parse()?,
+            other => other.as_i64().ok_or_else(|| anyhow::anyhow!("invalid intValue {}", other))?,
+        }),
+        "doubleValue" => any_value::Value::DoubleValue(match inner {
+            serde_json::Value::String(s) => s.parse()?,
+            other => other.as_f64().ok_or_else(|| anyhow::anyhow!("invalid doubleValue {}", other))?,
+        }),
+        "arrayValue" => any_value::Value::ArrayValue(ArrayValue {
+            values: inner
+                .get("values")
+                .and_then(|v| v.as_array())
+                .map(|values| values.iter().map(json_any_value).collect::<Result<_>>())
+                .transpose()?
+                .unwrap_or_default(),
+        }),
+        "kvlistValue" => {
+            let pairs: Vec<JsonKeyValue> = match inner.get("values") {
+                Some(values) => serde_json::from_value(values.clone())?,
+                None => Vec::new(),
+            };
+            any_value::Value::KvlistValue(KeyValueList { values: json_attributes(&pairs)? })
+        }
+        // Base64 in OTLP/JSON; kept as text
+        "bytesValue" => any_value::Value::StringValue(inner.as_str().unwrap_or_default().to_string()),
+        other => anyhow::bail!("unknown AnyValue kind '{}'", other),
+    };
+    Ok(AnyValue { value: Some(value) })
+}
+
+fn json_attributes(attributes: &[JsonKeyValue]) -> Result<Vec<KeyValue>> {
+    attributes
+        .iter()
+        .map(|kv| {
+            Ok(KeyValue {
+                key: kv.key.clone(),
+                value: kv.value.as_ref().map(json_any_value).transpose()?,
+            })
+        })
+        .collect()
+}
+
+/// Parse an OTLP/JSON export request body
+pub fn decode_json_request(body: &[u8]) -> Result<ExportLogsServiceRequest> {
+    let request: JsonExportRequest = serde_json::from_slice(body)?;
+
+    let resource_logs = request
+        .resource_logs
+        .into_iter()
+        .map(|resource_logs| {
+            let scope_logs = resource_logs
+                .scope_logs
+                .into_iter()
+                .map(|scope_logs| {
+                    let log_records = scope_logs
+                        .log_records
+                        .iter()
+                        .map(|record| {
+                            Ok(LogRecord {
+                                time_unix_nano: json_u64(&record.time_unix_nano)?,
+                                observed_time_unix_nano: json_u64(&record.observed_time_unix_nano)?,
+                                severity_number: json_u64(&record.severity_number)? as i32,
+                                severity_text: record.severity_text.clone(),
+                                body: record.body.as_ref().map(json_any_value).transpose()?,
+                                attributes: json_attributes(&record.attributes)?,
+                                ..Default::default()
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let scope = scope_logs
+                        .scope
+                        .map(|scope| {
+                            Ok::<_, anyhow::Error>(InstrumentationScope {
+                                name: scope.name,
+                                version: scope.version,
+                                attributes: json_attributes(&scope.attributes)?,
+                                ..Default::default()
+                            })
+                        })
+                        .transpose()?;
+                    Ok(ScopeLogs {
+                        scope,
+                        log_records,
+                        ..Default::default()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let resource = resource_logs
+                .resource
+                .map(|resource| {
+                    Ok::<_, anyhow::Error>(Resource {
+                        attributes: json_attributes(&resource.attributes)?,
+                        ..Default::default()
+                    })
+                })
+                .transpose()?;
+            Ok(ResourceLogs {
+                resource,
+                scope_logs,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ExportLogsServiceRequest { resource_logs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_value(s: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(any_value::Value::StringValue(s.to_string())),
+        })
+    }
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: string_value(value),
+        }
+    }
+
+    #[test]
+    fn test_logs_from_request() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![kv("service.name", "checkout"), kv("cloud.region", "eu-west-1"), kv("org.id", "acme")],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: "com.acme.checkout".to_string(),
+                        ..Default::default()
+                    }),
+                    log_records: vec![
+                        LogRecord {
+                            time_unix_nano: 1_700_000_000_123_000_000,
+                            severity_text: "ERROR".to_string(),
+                            body: string_value("payment declined for order 42"),
+                            attributes: vec![kv("log.file.path", "/var/log/checkout.log")],
+                            ..Default::default()
+                        },
+                        LogRecord {
+                            observed_time_unix_nano: 1_700_000_001_000_000_000,
+                            body: string_value("cart updated"),
+                            // Record attributes win over resource attributes
+                            attributes: vec![kv("org.id", "other")],
+                            ..Default::default()
+                        },
+                        LogRecord::default(),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let (logs, rejected) = logs_from_request(&request, &OtlpMapping::new());
+        assert_eq!(rejected, 1);
+        assert_eq!(logs.len(), 2);
+
+        let first = &logs[0];
+        assert_eq!(first.message, "payment declined for order 42");
+        assert_eq!((first.org_id.as_str(), first.log_stream_id.as_str()), ("acme", "checkout"));
+        assert_eq!((first.service.as_str(), first.region.as_str()), ("checkout", "eu-west-1"));
+        assert_eq!(first.log_stream_name, "/var/log/checkout.log");
+        assert_eq!(first.severity_text, "ERROR");
+        assert_eq!(first.timestamp.unwrap().timestamp_millis(), 1_700_000_000_123);
+
+        let second = &logs[1];
+        assert_eq!(second.org_id, "other");
+        assert_eq!(second.log_stream_name, "com.acme.checkout");
+        assert_eq!(second.timestamp.unwrap().timestamp(), 1_700_000_001);
+
+        // Nothing to map from: defaults
+        let bare = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                scope_logs: vec![ScopeLogs {
+                    log_records: vec![LogRecord {
+                        body: string_value("hello"),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let (logs, _) = logs_from_request(&bare, &OtlpMapping::new().with_default_org_id("1"));
+        assert_eq!((logs[0].org_id.as_str(), logs[0].log_stream_id.as_str()), ("1", UNKNOWN_SERVICE));
+        assert_eq!(logs[0].log_stream_name, UNKNOWN_SERVICE);
+        assert_eq!(logs[0].timestamp, None);
+    }
+
+    #[test]
+    fn test_decode_json_request() {
+        let body = r#"{
+            "resourceLogs": [{
+                "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "api"}}]},
+                "scopeLogs": [{
+                    "scope": {"name": "api.http"},
+                    "logRecords": [
+                        {
+                            "timeUnixNano": "1700000000000000000",
+                            "severityNumber": 17,
+                            "severityText": "ERROR",
+                            "body": {"stringValue": "GET /users 500"},
+                            "attributes": [{"key": "http.status", "value": {"intValue": "500"}}],
+                            "traceId": "5b8efff798038103d269b633813fc60c"
+                        },
+                        {
+                            "body": {"kvlistValue": {"values": [
+                                {"key": "event", "value": {"stringValue": "login"}},
+                                {"key": "ok", "value": {"boolValue": true}}
+                            ]}}
+                        }
+                    ]
+                }]
+            }]
+        }"#;
+
+        let request = decode_json_request(body.as_bytes()).unwrap();
+        let (logs, rejected) = logs_from_request(&request, &OtlpMapping::new());
+        assert_eq!(rejected, 0);
+        assert_eq!(logs[0].message, "GET /users 500");
+        assert_eq!(logs[0].timestamp.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!((logs[0].service.as_str(), logs[0].log_stream_name.as_str()), ("api", "api.http"));
+        assert_eq!(logs[1].message, r#"{"event":"login","ok":true}"#);
+
+        let record = &request.resource_logs[0].scope_logs[0].log_records[0];
+        assert_eq!(record.severity_number, 17);
+        assert_eq!(attribute(&record.attributes, "http.status").as_deref(), Some("500"));
+
+        assert!(decode_json_request(b"{\"resourceLogs\": 1}").is_err());
+        assert!(decode_json_request(br#"{"resourceLogs": [{"scopeLogs": [{"logRecords": [{"body": {"fooValue": 1}}]}]}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_any_value_to_string() {
+        let value = |v| AnyValue { value: Some(v) };
+        assert_eq!(any_value_to_string(&value(any_value::Value::IntValue(-3))), "-3");
+        assert_eq!(any_value_to_string(&value(any_value::Value::BytesValue(vec![0xde, 0xad]))), "dead");
+        let array = any_value::Value::ArrayValue(ArrayValue {
+            values: vec![value(any_value::Value::StringValue("a".into())), value(any_value::Value::DoubleValue(1.5))],
+        });
+        assert_eq!(any_value_to_string(&value(array)), r#"["a",1.5]"#);
+        assert_eq!(any_value_to_string(&AnyValue { value: None }), "");
+    }
+}
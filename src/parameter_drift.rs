@@ -0,0 +1,520 @@
+/// Parameter distribution drift detection
+///
+/// Keeps a histogram of the values of every template variable per time window
+/// (the top-k values plus an "other" bucket) and compares the current window
+/// with a baseline made of the previous windows, using Jensen-Shannon and
+/// Kullback-Leibler divergence. A variable whose value mix shifted, such as a
+/// new user or a new error code taking a share of the traffic, is flagged.
+/// High-cardinality variables (request IDs, durations) mostly land in the
+/// "other" bucket and are left out of the comparison.
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Values tracked per window before new ones go to the "other" bucket, as a multiple of `top_k`
+const TRACKED_VALUES_FACTOR: usize = 4;
+
+/// Pseudo-count added to every bucket for KL divergence, which is undefined for empty buckets
+const KL_SMOOTHING: f64 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DriftConfig {
+    /// Values kept per closed window; the rest are folded into "other"
+    pub top_k: usize,
+    /// Length of one window
+    pub window_secs: u64,
+    /// Closed windows merged into the baseline
+    pub baseline_windows: usize,
+    /// Jensen-Shannon divergence (bits, 0..=1) at which a variable counts as drifted
+    pub threshold: f64,
+    /// Samples needed in both the current window and the baseline
+    pub min_samples: u64,
+    /// A value missing from the baseline is reported as new from this share of the current window
+    pub new_value_share: f64,
+    /// Variables whose baseline has a larger "other" share are not compared
+    pub max_other_share: f64,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        Self {
+            top_k: 20,
+            window_secs: 300,
+            baseline_windows: 12,
+            threshold: 0.2,
+            min_samples: 30,
+            new_value_share: 0.05,
+            max_other_share: 0.5,
+        }
+    }
+}
+
+impl DriftConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_window_secs(mut self, window_secs: u64) -> Self {
+        self.window_secs = window_secs;
+        self
+    }
+
+    pub fn with_baseline_windows(mut self, windows: usize) -> Self {
+        self.baseline_windows = windows;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_min_samples(mut self, samples: u64) -> Self {
+        self.min_samples = samples;
+        self
+    }
+
+    pub fn with_new_value_share(mut self, share: f64) -> Self {
+        self.new_value_share = share;
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::seconds(self.window_secs as i64)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.top_k == 0 {
+            anyhow::bail!("drift top_k must be at least 1");
+        }
+        if self.window_secs == 0 || self.window_secs > i64::MAX as u64 {
+            anyhow::bail!("drift window must be at least one second");
+        }
+        if self.baseline_windows == 0 {
+            anyhow::bail!("drift baseline needs at least one window");
+        }
+        if !(self.threshold > 0.0 && self.threshold <= 1.0) {
+            anyhow::bail!("drift threshold must be in (0, 1], got {}", self.threshold);
+        }
+        for (name, share) in [("new_value_share", self.new_value_share), ("max_other_share", self.max_other_share)] {
+            if !(0.0..=1.0).contains(&share) {
+                anyhow::bail!("drift {} must be in [0, 1], got {}", name, share);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts of a variable's values: tracked values plus an "other" bucket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValueHistogram {
+    pub counts: FxHashMap<String, u64>,
+    pub other: u64,
+}
+
+impl ValueHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a value; once `max_values` values are tracked, new ones count as "other"
+    pub fn add(&mut self, value: &str, max_values: usize) {
+        if let Some(count) = self.counts.get_mut(value) {
+            *count += 1;
+        } else if self.counts.len() < max_values {
+            self.counts.insert(value.to_string(), 1);
+        } else {
+            self.other += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum::<u64>() + self.other
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Share of the total in the "other" bucket
+    pub fn other_share(&self) -> f64 {
+        share(self.other, self.total())
+    }
+
+    /// The `n` most common values, ties by value
+    pub fn top(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut values: Vec<(&str, u64)> = self.counts.iter().map(|(v, &c)| (v.as_str(), c)).collect();
+        values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        values.truncate(n);
+        values
+    }
+
+    /// Keep the `top_k` most common values and fold the rest into "other"
+    pub fn truncate(&mut self, top_k: usize) {
+        if self.counts.len() <= top_k {
+            return;
+        }
+        let keep: FxHashMap<String, u64> = self.top(top_k).into_iter().map(|(v, c)| (v.to_string(), c)).collect();
+        let kept: u64 = keep.values().sum();
+        let total = self.total();
+        self.counts = keep;
+        self.other = total - kept;
+    }
+
+    pub fn merge(&mut self, other: &ValueHistogram) {
+        for (value, count) in &other.counts {
+            *self.counts.entry(value.clone()).or_default() += count;
+        }
+        self.other += other.other;
+    }
+}
+
+/// Aligned bucket counts of two histograms: every tracked value of either, then "other"
+fn aligned_counts(p: &ValueHistogram, q: &ValueHistogram) -> (Vec<f64>, Vec<f64>) {
+    let mut values: Vec<&String> = p.counts.keys().chain(q.counts.keys()).collect();
+    values.sort_unstable();
+    values.dedup();
+
+    let count = |h: &ValueHistogram, value: &String| h.counts.get(value).copied().unwrap_or(0) as f64;
+    let mut p_counts: Vec<f64> = values.iter().map(|v| count(p, v)).collect();
+    let mut q_counts: Vec<f64> = values.iter().map(|v| count(q, v)).collect();
+    p_counts.push(p.other as f64);
+    q_counts.push(q.other as f64);
+    (p_counts, q_counts)
+}
+
+fn normalize(counts: &[f64]) -> Vec<f64> {
+    let total: f64 = counts.iter().sum();
+    if total == 0.0 {
+        return vec![0.0; counts.len()];
+    }
+    counts.iter().map(|c| c / total).collect()
+}
+
+fn kl(p: &[f64], q: &[f64]) -> f64 {
+    p.iter()
+        .zip(q)
+        .filter(|(&pi, _)| pi > 0.0)
+        .map(|(&pi, &qi)| pi * (pi / qi).log2())
+        .sum()
+}
+
+/// Jensen-Shannon divergence in bits: symmetric, 0 for equal distributions, at most 1
+pub fn js_divergence(p: &ValueHistogram, q: &ValueHistogram) -> f64 {
+    let (p_counts, q_counts) = aligned_counts(p, q);
+    let (p, q) = (normalize(&p_counts), normalize(&q_counts));
+    let m: Vec<f64> = p.iter().zip(&q).map(|(a, b)| (a + b) / 2.0).collect();
+    ((kl(&p, &m) + kl(&q, &m)) / 2.0).clamp(0.0, 1.0)
+}
+
+/// Kullback-Leibler divergence KL(p || q) in bits, with a pseudo-count on every
+/// bucket so values missing from `q` give a large but finite result
+pub fn kl_divergence(p: &ValueHistogram, q: &ValueHistogram) -> f64 {
+    let (p_counts, q_counts) = aligned_counts(p, q);
+    let smooth = |counts: Vec<f64>| normalize(&counts.into_iter().map(|c| c + KL_SMOOTHING).collect::<Vec<_>>());
+    kl(&smooth(p_counts), &smooth(q_counts)).max(0.0)
+}
+
+fn share(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueShare {
+    pub value: String,
+    pub count: u64,
+    /// Share of the current window
+    pub share: f64,
+}
+
+/// Comparison of one template variable's current window with its baseline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterDriftReport {
+    pub template_id: u64,
+    pub variable: String,
+    pub js_divergence: f64,
+    pub kl_divergence: f64,
+    /// Samples in the current window
+    pub samples: u64,
+    pub baseline_samples: u64,
+    /// JS divergence at or above the threshold, or a new value
+    pub drifted: bool,
+    /// Values missing from the baseline, with at least `new_value_share` of the window
+    pub new_values: Vec<ValueShare>,
+    /// Most common values of the current window
+    pub top_values: Vec<ValueShare>,
+    pub window_start: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct VariableWindows {
+    current: ValueHistogram,
+    /// Closed windows, oldest first
+    closed: VecDeque<ValueHistogram>,
+}
+
+/// Per-variable value histograms of one template set, in fixed time windows
+#[derive(Debug)]
+pub struct ParameterDrift {
+    config: DriftConfig,
+    window_start: Option<DateTime<Utc>>,
+    variables: FxHashMap<(u64, String), VariableWindows>,
+}
+
+impl ParameterDrift {
+    pub fn new(config: DriftConfig) -> Self {
+        Self {
+            config,
+            window_start: None,
+            variables: FxHashMap::default(),
+        }
+    }
+
+    pub fn config(&self) -> &DriftConfig {
+        &self.config
+    }
+
+    /// Count the variable values of one matched log line
+    pub fn record(&mut self, template_id: u64, variables: &HashMap<String, String>, now: DateTime<Utc>) {
+        self.roll(now);
+        let max_values = self.config.top_k * TRACKED_VALUES_FACTOR;
+        for (name, value) in variables {
+            self.variables
+                .entry((template_id, name.clone()))
+                .or_default()
+                .current
+                .add(value, max_values);
+        }
+    }
+
+    /// Forget a template's histograms (after it was replaced or deleted)
+    pub fn remove_template(&mut self, template_id: u64) {
+        self.variables.retain(|(id, _), _| *id != template_id);
+    }
+
+    /// Number of (template, variable) pairs being tracked
+    pub fn tracked_variables(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Compare every variable's current window with its baseline. Variables
+    /// without enough samples or with a mostly "other" baseline are left out.
+    /// Drifted variables come first, by decreasing JS divergence.
+    pub fn report(&mut self, now: DateTime<Utc>) -> Vec<ParameterDriftReport> {
+        self.roll(now);
+        let Some(window_start) = self.window_start else {
+            return Vec::new();
+        };
+
+        let mut reports: Vec<ParameterDriftReport> = self
+            .variables
+            .iter()
+            .filter_map(|((template_id, variable), windows)| self.compare(*template_id, variable, windows, window_start))
+            .collect();
+
+        reports.sort_by(|a, b| {
+            b.drifted
+                .cmp(&a.drifted)
+                .then_with(|| b.js_divergence.total_cmp(&a.js_divergence))
+                .then_with(|| (a.template_id, &a.variable).cmp(&(b.template_id, &b.variable)))
+        });
+        reports
+    }
+
+    /// Only the drifted variables of `report`
+    pub fn drifted(&mut self, now: DateTime<Utc>) -> Vec<ParameterDriftReport> {
+        let mut reports = self.report(now);
+        reports.retain(|r| r.drifted);
+        reports
+    }
+
+    fn compare(
+        &self,
+        template_id: u64,
+        variable: &str,
+        windows: &VariableWindows,
+        window_start: DateTime<Utc>,
+    ) -> Option<ParameterDriftReport> {
+        let current = &windows.current;
+        let mut baseline = ValueHistogram::new();
+        for closed in &windows.closed {
+            baseline.merge(closed);
+        }
+
+        let (samples, baseline_samples) = (current.total(), baseline.total());
+        if samples < self.config.min_samples || baseline_samples < self.config.min_samples {
+            return None;
+        }
+        if baseline.other_share() > self.config.max_other_share {
+            return None;
+        }
+
+        let value_share = |(value, count): (&str, u64)| ValueShare {
+            value: value.to_string(),
+            count,
+            share: share(count, samples),
+        };
+        let new_values: Vec<ValueShare> = current
+            .top(current.counts.len())
+            .into_iter()
+            .filter(|(value, _)| !baseline.counts.contains_key(*value))
+            .map(value_share)
+            .filter(|v| v.share >= self.config.new_value_share)
+            .collect();
+
+        let js = js_divergence(current, &baseline);
+        Some(ParameterDriftReport {
+            template_id,
+            variable: variable.to_string(),
+            js_divergence: js,
+            kl_divergence: kl_divergence(current, &baseline),
+            samples,
+            baseline_samples,
+            drifted: js >= self.config.threshold || !new_values.is_empty(),
+            new_values,
+            top_values: current.top(5).into_iter().map(value_share).collect(),
+            window_start,
+        })
+    }
+
+    /// Close every window that ended before `now`
+    fn roll(&mut self, now: DateTime<Utc>) {
+        let window = self.config.window();
+        let Some(start) = self.window_start else {
+            self.window_start = Some(align(now, self.config.window_secs));
+            return;
+        };
+        if now < start + window {
+            return;
+        }
+
+        // Windows without traffic are closed empty; after more than a full
+        // baseline of them, nothing of the old data is left
+        let elapsed = ((now - start).num_seconds() as u64 / self.config.window_secs) as usize;
+        let closes = elapsed.min(self.config.baseline_windows + 1);
+        for windows in self.variables.values_mut() {
+            for i in 0..closes {
+                let mut closed = if i == 0 { std::mem::take(&mut windows.current) } else { ValueHistogram::new() };
+                closed.truncate(self.config.top_k);
+                windows.closed.push_back(closed);
+                if windows.closed.len() > self.config.baseline_windows {
+                    windows.closed.pop_front();
+                }
+            }
+            windows.current = ValueHistogram::new();
+        }
+        self.variables.retain(|_, w| !w.current.is_empty() || w.closed.iter().any(|h| !h.is_empty()));
+        self.window_start = Some(align(now, self.config.window_secs));
+    }
+}
+
+/// Start of the window containing `now`
+fn align(now: DateTime<Utc>, window_secs: u64) -> DateTime<Utc> {
+    let secs = now.timestamp();
+    let aligned = secs - secs.rem_euclid(window_secs as i64);
+    DateTime::from_timestamp(aligned, 0).unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(values: &[(&str, u64)]) -> ValueHistogram {
+        let mut h = ValueHistogram::new();
+        for &(value, count) in values {
+            for _ in 0..count {
+                h.add(value, 100);
+            }
+        }
+        h
+    }
+
+    #[test]
+    fn test_divergence() {
+        let p = histogram(&[("root", 50), ("admin", 50)]);
+        assert!(js_divergence(&p, &p) < 1e-9);
+        assert!(kl_divergence(&p, &p) < 1e-9);
+
+        let q = histogram(&[("guest", 100)]);
+        assert!((js_divergence(&p, &q) - 1.0).abs() < 1e-9);
+        assert!(kl_divergence(&p, &q) > 1.0);
+
+        let r = histogram(&[("root", 60), ("admin", 40)]);
+        assert!(js_divergence(&p, &r) < 0.05);
+        assert!((js_divergence(&p, &r) - js_divergence(&r, &p)).abs() < 1e-12);
+
+        // Top-k plus other
+        let mut h = histogram(&[("a", 5), ("b", 3), ("c", 1), ("d", 1)]);
+        h.truncate(2);
+        assert_eq!(h.top(5), [("a", 5), ("b", 3)]);
+        assert_eq!((h.other, h.total()), (2, 10));
+    }
+
+    #[test]
+    fn test_drift_detection() {
+        let config = DriftConfig::new().with_window_secs(60).with_baseline_windows(3).with_min_samples(20);
+        let mut drift = ParameterDrift::new(config);
+        let start = DateTime::from_timestamp(1_700_000_040, 0).unwrap();
+        let vars = |user: &str, code: &str| {
+            HashMap::from([("user".to_string(), user.to_string()), ("code".to_string(), code.to_string())])
+        };
+
+        // Three baseline windows: alice and bob, always 200
+        for window in 0..3 {
+            let at = start + Duration::seconds(60 * window);
+            for i in 0..40 {
+                drift.record(7, &vars(if i % 2 == 0 { "alice" } else { "bob" }, "200"), at);
+            }
+        }
+        // Current window: same users, but a new error code takes over
+        let now = start + Duration::seconds(180);
+        for i in 0..40 {
+            drift.record(7, &vars(if i % 2 == 0 { "alice" } else { "bob" }, if i < 30 { "503" } else { "200" }), now);
+        }
+
+        let reports = drift.report(now);
+        assert_eq!(reports.len(), 2);
+        let code = &reports[0];
+        assert_eq!((code.variable.as_str(), code.drifted), ("code", true));
+        assert_eq!(code.new_values[0].value, "503");
+        assert!((code.new_values[0].share - 0.75).abs() < 1e-9);
+        assert_eq!((code.samples, code.baseline_samples), (40, 120));
+
+        let user = &reports[1];
+        assert_eq!((user.variable.as_str(), user.drifted), ("user", false));
+        assert!(user.js_divergence < 1e-9);
+        assert_eq!(drift.drifted(now).len(), 1);
+
+        // Idle for longer than the baseline: everything ages out
+        let later = now + Duration::seconds(60 * 10);
+        assert!(drift.report(later).is_empty());
+        assert_eq!(drift.tracked_variables(), 0);
+    }
+
+    #[test]
+    fn test_high_cardinality_variables_are_skipped() {
+        let config = DriftConfig::new().with_window_secs(60).with_top_k(2).with_min_samples(10);
+        let mut drift = ParameterDrift::new(config);
+        let start = DateTime::from_timestamp(1_700_000_040, 0).unwrap();
+        for window in 0..2 {
+            for i in 0..50 {
+                let request_id = format!("req-{}-{}", window, i);
+                drift.record(1, &HashMap::from([("request_id".to_string(), request_id)]), start + Duration::seconds(60 * window));
+            }
+        }
+        assert!(drift.report(start + Duration::seconds(60)).is_empty());
+        assert!(DriftConfig::new().with_threshold(0.0).validate().is_err());
+    }
+}
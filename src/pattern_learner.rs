@@ -168,7 +168,7 @@ impl PatternLearner {
                 }
                 PatternToken::Variable(var_type) => {
                     let (regex_pattern, var_name_base) = var_type.to_regex_and_name();
-                    pattern.push_str(regex_pattern);
+                    pattern.push_str(&regex_pattern);
 
                     // Generate unique variable name
                     let count = var_count.entry(var_name_base.clone()).or_insert(0);
@@ -238,7 +238,7 @@ enum PatternToken {
 }
 
 #[derive(Debug, Clone)]
-enum VariableType {
+pub(crate) enum VariableType {
     Number,
     IPAddress,
     HexNumber,
@@ -248,16 +248,29 @@ enum VariableType {
 }
 
 impl VariableType {
-    fn to_regex_and_name(&self) -> (&'static str, String) {
+    /// Regex for a value of this type, without a capture group
+    pub(crate) fn value_pattern(&self) -> &'static str {
         match self {
-            VariableType::Number => (r"(\d+)", "number".to_string()),
-            VariableType::IPAddress => (r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})", "ip_address".to_string()),
-            VariableType::HexNumber => (r"(0x[0-9a-fA-F]+|[0-9a-fA-F]+)", "hex_number".to_string()),
-            VariableType::UUID => (r"([0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})", "uuid".to_string()),
-            VariableType::UnixTimestamp => (r"(\d{10,})", "timestamp".to_string()),
-            VariableType::String => (r"(\S+)", "value".to_string()),
+            VariableType::Number => r"\d+",
+            VariableType::IPAddress => r"\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}",
+            VariableType::HexNumber => r"0x[0-9a-fA-F]+|[0-9a-fA-F]+",
+            VariableType::UUID => r"[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}",
+            VariableType::UnixTimestamp => r"\d{10,}",
+            VariableType::String => r"\S+",
         }
     }
+
+    fn to_regex_and_name(&self) -> (String, String) {
+        let name = match self {
+            VariableType::Number => "number",
+            VariableType::IPAddress => "ip_address",
+            VariableType::HexNumber => "hex_number",
+            VariableType::UUID => "uuid",
+            VariableType::UnixTimestamp => "timestamp",
+            VariableType::String => "value",
+        };
+        (format!("({})", self.value_pattern()), name.to_string())
+    }
 }
 
 #[cfg(test)]
@@ -9,6 +9,8 @@
 /// - Enables parameter distribution tracking for KL divergence
 /// - Uses LLM for semantic understanding, regex for fast extraction
 use anyhow::Result;
+use crate::log_matcher::LogTemplate;
+use crate::template_metadata::{Severity, TemplateMetadata, SOURCE_SEMANTIC};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -37,6 +39,26 @@ pub struct SemanticTemplate {
     pub pattern: Option<String>,
 }
 
+impl SemanticTemplate {
+    /// Regex template for the matcher, carrying the description as metadata.
+    /// None until a pattern has been generated.
+    pub fn to_log_template(&self) -> Option<LogTemplate> {
+        let pattern = self.pattern.clone()?;
+        let mut metadata = TemplateMetadata::new()
+            .with_description(&self.description)
+            .with_source(SOURCE_SEMANTIC);
+        metadata.severity = Severity::detect(&self.example);
+
+        Some(LogTemplate {
+            template_id: self.template_id,
+            pattern,
+            variables: self.parameters.clone(),
+            example: self.example.clone(),
+            metadata,
+        })
+    }
+}
+
 /// A matched log with extracted parameters
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SemanticMatch {
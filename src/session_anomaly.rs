@@ -0,0 +1,506 @@
+/// Session grouping and sequence anomaly detection
+///
+/// Groups matched logs into sessions by an identifier taken from the template
+/// variables (instance id, block id, request id) or, failing that, from the raw
+/// line with a regex. Each session becomes the sequence of its template ids.
+/// An n-gram model learns which template follows which context in normal
+/// sessions; a session is abnormal when a template is not among the most
+/// likely next templates of its context, in the style of DeepLog's top-k check.
+/// `evaluate` scores predictions against labels such as the LogHub OpenStack
+/// `anomaly_labels.txt`.
+use crate::log_matcher::ExtractedMatch;
+use anyhow::Result;
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Marks the start of a session in n-gram contexts
+const SESSION_START: u64 = u64::MAX;
+/// Marks the end of a session, so truncated sessions can be caught
+const SESSION_END: u64 = u64::MAX - 1;
+
+/// How a log line's session identifier is found
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Template variables holding the identifier, tried in order
+    pub id_variables: Vec<String>,
+    /// Regex applied to the raw line when no variable holds the identifier;
+    /// the first capture group (or the whole match) is the identifier
+    pub id_pattern: Option<String>,
+    /// Events kept per session; later events are dropped
+    pub max_events: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            id_variables: Vec::new(),
+            id_pattern: None,
+            max_events: 10_000,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// OpenStack VM instances (`[instance: <uuid>]`)
+    pub fn openstack_instance() -> Self {
+        Self::new()
+            .with_id_variables(&["instance_id", "instance", "instance_uuid"])
+            .with_id_pattern(r"\[instance: ([0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})\]")
+    }
+
+    /// HDFS blocks (`blk_-123...`)
+    pub fn hdfs_block() -> Self {
+        Self::new()
+            .with_id_variables(&["block_id", "block", "blk"])
+            .with_id_pattern(r"(blk_-?\d+)")
+    }
+
+    /// Request ids (`req-<uuid>`, as logged by OpenStack services)
+    pub fn request_id() -> Self {
+        Self::new()
+            .with_id_variables(&["request_id", "req_id", "trace_id"])
+            .with_id_pattern(r"(req-[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})")
+    }
+
+    pub fn with_id_variables(mut self, names: &[&str]) -> Self {
+        self.id_variables = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_id_pattern(mut self, pattern: &str) -> Self {
+        self.id_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn with_max_events(mut self, max_events: usize) -> Self {
+        self.max_events = max_events;
+        self
+    }
+}
+
+/// The template ids of one session's logs, in arrival order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub template_ids: Vec<u64>,
+}
+
+/// Groups matched logs into sessions
+pub struct SessionBuilder {
+    config: SessionConfig,
+    id_regex: Option<Regex>,
+    /// Session id -> index into `sessions`
+    index: FxHashMap<String, usize>,
+    sessions: Vec<Session>,
+}
+
+impl SessionBuilder {
+    pub fn new(config: SessionConfig) -> Result<Self> {
+        let id_regex = config.id_pattern.as_deref().map(Regex::new).transpose()?;
+        Ok(Self {
+            config,
+            id_regex,
+            index: FxHashMap::default(),
+            sessions: Vec::new(),
+        })
+    }
+
+    /// Session identifier of a log line: the first configured variable with a
+    /// non-empty value, else the id pattern applied to the line
+    pub fn session_id(&self, variables: &HashMap<String, String>, log_line: &str) -> Option<String> {
+        let from_variables = self
+            .config
+            .id_variables
+            .iter()
+            .filter_map(|name| variables.get(name))
+            .find(|value| !value.is_empty());
+        if let Some(value) = from_variables {
+            return Some(value.clone());
+        }
+
+        let caps = self.id_regex.as_ref()?.captures(log_line)?;
+        caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str().to_string())
+    }
+
+    /// Add a matched log to its session. Returns false when no identifier was found.
+    pub fn add(&mut self, template_id: u64, variables: &HashMap<String, String>, log_line: &str) -> bool {
+        let Some(id) = self.session_id(variables, log_line) else {
+            return false;
+        };
+
+        let index = match self.index.get(&id) {
+            Some(&index) => index,
+            None => {
+                self.index.insert(id.clone(), self.sessions.len());
+                self.sessions.push(Session {
+                    id,
+                    template_ids: Vec::new(),
+                });
+                self.sessions.len() - 1
+            }
+        };
+        let session = &mut self.sessions[index];
+        if session.template_ids.len() < self.config.max_events {
+            session.template_ids.push(template_id);
+        }
+        true
+    }
+
+    /// `add` for a `LogMatcher::match_and_extract` result
+    pub fn add_match(&mut self, matched: &ExtractedMatch, log_line: &str) -> bool {
+        self.add(matched.template_id, &matched.variables, log_line)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Sessions in order of their first log
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn into_sessions(self) -> Vec<Session> {
+        self.sessions
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SequenceConfig {
+    /// N-gram order: the next template is predicted from the previous `n - 1`
+    pub n: usize,
+    /// A template is expected when it is among the `top_k` most frequent
+    /// successors of its context
+    pub top_k: usize,
+    /// Unexpected transitions tolerated before a session is abnormal
+    pub max_misses: usize,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        Self {
+            n: 3,
+            top_k: 5,
+            max_misses: 0,
+        }
+    }
+}
+
+impl SequenceConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_n(mut self, n: usize) -> Self {
+        self.n = n;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_max_misses(mut self, max_misses: usize) -> Self {
+        self.max_misses = max_misses;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.n < 2 {
+            anyhow::bail!("n-gram order must be at least 2");
+        }
+        if self.top_k == 0 {
+            anyhow::bail!("top_k must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// How well a session fits the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceScore {
+    /// Transitions checked, including the session end
+    pub transitions: usize,
+    /// Transitions whose template was not among the top-k successors
+    pub misses: usize,
+    /// Position of the first miss (`template_ids.len()` for the session end)
+    pub first_miss: Option<usize>,
+    /// Mean log-probability per transition (add-one smoothing), for ranking
+    pub mean_log_prob: f64,
+    pub anomalous: bool,
+}
+
+/// N-gram transition model over template-id sequences, with back-off to
+/// shorter contexts for contexts not seen in training
+pub struct NgramModel {
+    config: SequenceConfig,
+    /// Context (1 to n-1 previous ids) -> successor -> count
+    transitions: FxHashMap<Vec<u64>, FxHashMap<u64, u64>>,
+    vocabulary: HashSet<u64>,
+    sessions: usize,
+}
+
+impl NgramModel {
+    pub fn new(config: SequenceConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            transitions: FxHashMap::default(),
+            vocabulary: HashSet::new(),
+            sessions: 0,
+        })
+    }
+
+    pub fn config(&self) -> &SequenceConfig {
+        &self.config
+    }
+
+    /// Sessions trained on
+    pub fn sessions(&self) -> usize {
+        self.sessions
+    }
+
+    /// Start and end markers around a session
+    fn padded(template_ids: &[u64]) -> Vec<u64> {
+        let mut padded = Vec::with_capacity(template_ids.len() + 2);
+        padded.push(SESSION_START);
+        padded.extend_from_slice(template_ids);
+        padded.push(SESSION_END);
+        padded
+    }
+
+    /// Count the transitions of normal sessions
+    pub fn train<'a, I>(&mut self, sequences: I)
+    where
+        I: IntoIterator<Item = &'a [u64]>,
+    {
+        for template_ids in sequences {
+            self.vocabulary.extend(template_ids.iter().copied());
+            let padded = Self::padded(template_ids);
+            for i in 1..padded.len() {
+                for len in 1..self.config.n.min(i + 1) {
+                    let context = padded[i - len..i].to_vec();
+                    *self.transitions.entry(context).or_default().entry(padded[i]).or_insert(0) += 1;
+                }
+            }
+            self.sessions += 1;
+        }
+    }
+
+    /// Successor counts of the longest suffix of `history` seen in training
+    fn successors(&self, history: &[u64]) -> Option<&FxHashMap<u64, u64>> {
+        let longest = (self.config.n - 1).min(history.len());
+        (1..=longest)
+            .rev()
+            .find_map(|len| self.transitions.get(&history[history.len() - len..]))
+    }
+
+    /// Score a session's template ids against the trained transitions
+    pub fn score(&self, template_ids: &[u64]) -> SequenceScore {
+        let padded = Self::padded(template_ids);
+        // Successors plus one slot for templates never seen in training
+        let outcomes = (self.vocabulary.len() + 2) as f64;
+        let mut misses = 0;
+        let mut first_miss = None;
+        let mut log_prob = 0.0;
+
+        for i in 1..padded.len() {
+            let next = padded[i];
+            let (expected, count, total) = match self.successors(&padded[..i]) {
+                Some(successors) => {
+                    let mut ranked: Vec<(u64, u64)> = successors.iter().map(|(&id, &count)| (id, count)).collect();
+                    ranked.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                    let expected = ranked.iter().take(self.config.top_k).any(|&(id, _)| id == next);
+                    let total: u64 = ranked.iter().map(|&(_, count)| count).sum();
+                    (expected, successors.get(&next).copied().unwrap_or(0), total)
+                }
+                None => (false, 0, 0),
+            };
+
+            log_prob += ((count as f64 + 1.0) / (total as f64 + outcomes)).ln();
+            if !expected {
+                misses += 1;
+                first_miss.get_or_insert(i - 1);
+            }
+        }
+
+        let transitions = padded.len() - 1;
+        SequenceScore {
+            transitions,
+            misses,
+            first_miss,
+            mean_log_prob: log_prob / transitions as f64,
+            anomalous: misses > self.config.max_misses,
+        }
+    }
+}
+
+/// Anomalous session ids from a label file: one id per line. Blank lines and
+/// lines with spaces (such as the LogHub header sentence) are skipped.
+pub fn load_anomaly_labels<P: AsRef<Path>>(path: P) -> Result<HashSet<String>> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(parse_anomaly_labels(&text))
+}
+
+pub fn parse_anomaly_labels(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains(char::is_whitespace))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Detection quality against labels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    pub sessions: usize,
+    /// Labeled anomalous sessions among the scored ones
+    pub labeled_anomalies: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub true_negatives: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// Labeled sessions that were not scored (no logs grouped under them)
+    pub missing_labels: Vec<String>,
+}
+
+/// Compare (session id, predicted anomalous) pairs with the anomalous ids
+pub fn evaluate<'a, I>(predictions: I, labels: &HashSet<String>) -> EvaluationReport
+where
+    I: IntoIterator<Item = (&'a str, bool)>,
+{
+    let (mut tp, mut fp, mut fn_, mut tn) = (0, 0, 0, 0);
+    let mut scored = HashSet::new();
+
+    for (session_id, predicted) in predictions {
+        scored.insert(session_id);
+        match (predicted, labels.contains(session_id)) {
+            (true, true) => tp += 1,
+            (true, false) => fp += 1,
+            (false, true) => fn_ += 1,
+            (false, false) => tn += 1,
+        }
+    }
+
+    let ratio = |num: usize, den: usize| if den == 0 { 0.0 } else { num as f64 / den as f64 };
+    let precision = ratio(tp, tp + fp);
+    let recall = ratio(tp, tp + fn_);
+    let f1 = if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 };
+    let mut missing_labels: Vec<String> = labels.iter().filter(|id| !scored.contains(id.as_str())).cloned().collect();
+    missing_labels.sort();
+
+    EvaluationReport {
+        sessions: scored.len(),
+        labeled_anomalies: tp + fn_,
+        true_positives: tp,
+        false_positives: fp,
+        false_negatives: fn_,
+        true_negatives: tn,
+        precision,
+        recall,
+        f1,
+        missing_labels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// VM lifecycle: create (1), spawn (2), start (3), n x heartbeat (4), stop (5), delete (6)
+    fn lifecycle(heartbeats: usize) -> Vec<u64> {
+        let mut ids = vec![1, 2, 3];
+        ids.extend(std::iter::repeat_n(4, heartbeats));
+        ids.extend([5, 6]);
+        ids
+    }
+
+    #[test]
+    fn test_session_builder() {
+        let mut builder = SessionBuilder::new(SessionConfig::openstack_instance()).unwrap();
+        let uuid = "544fd51c-4edc-4780-baae-ba1d80a0acfc";
+        let line = format!("nova.compute.manager [req-1 - - - - -] [instance: {}] VM Started", uuid);
+
+        // Variable first, regex on the line second
+        let variables = HashMap::from([("instance_id".to_string(), "abc".to_string())]);
+        assert!(builder.add(1, &variables, &line));
+        assert!(builder.add(2, &HashMap::new(), &line));
+        assert!(builder.add(3, &HashMap::new(), &line));
+        assert!(!builder.add(4, &HashMap::new(), "no instance here"));
+
+        let sessions = builder.into_sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!((sessions[0].id.as_str(), sessions[0].template_ids.as_slice()), ("abc", &[1][..]));
+        assert_eq!((sessions[1].id.as_str(), sessions[1].template_ids.as_slice()), (uuid, &[2, 3][..]));
+
+        let mut builder = SessionBuilder::new(SessionConfig::hdfs_block().with_max_events(2)).unwrap();
+        for id in [1, 2, 3] {
+            builder.add(id, &HashMap::new(), "Receiving block blk_-1608999687919862906 src: /10.250.19.102");
+        }
+        assert_eq!(builder.sessions()[0].template_ids, [1, 2]);
+        assert_eq!(builder.sessions()[0].id, "blk_-1608999687919862906");
+    }
+
+    #[test]
+    fn test_ngram_model() {
+        let mut model = NgramModel::new(SequenceConfig::new().with_n(3).with_top_k(2)).unwrap();
+        let normal: Vec<Vec<u64>> = (0..5).map(lifecycle).collect();
+        model.train(normal.iter().map(Vec::as_slice));
+        assert_eq!(model.sessions(), 5);
+
+        // Unseen length, same transitions
+        let score = model.score(&lifecycle(12));
+        assert!(!score.anomalous, "{:?}", score);
+        assert_eq!(score.transitions, 12 + 6);
+
+        // Spawn failed: stop right after spawn, delete missing
+        let score = model.score(&[1, 2, 5]);
+        assert!(score.anomalous);
+        assert_eq!(score.first_miss, Some(2));
+        assert_eq!(score.misses, 2);
+
+        // Unknown template
+        let score = model.score(&[1, 2, 3, 99, 5, 6]);
+        assert!(score.anomalous);
+        assert!(score.mean_log_prob < model.score(&lifecycle(1)).mean_log_prob);
+
+        // A tolerant model accepts a single miss
+        let mut tolerant = NgramModel::new(SequenceConfig::new().with_top_k(1).with_max_misses(1)).unwrap();
+        tolerant.train(normal.iter().map(Vec::as_slice));
+        assert!(!tolerant.score(&[1, 2, 3, 4, 6]).anomalous);
+        assert!(NgramModel::new(SequenceConfig::new().with_n(1)).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let labels = parse_anomaly_labels("Sessions with anomalies:\r\n\r\na\r\nb\r\nc\r\n");
+        assert_eq!(labels, HashSet::from(["a".to_string(), "b".to_string(), "c".to_string()]));
+
+        let report = evaluate([("a", true), ("b", false), ("x", true), ("y", false), ("z", false)], &labels);
+        assert_eq!(
+            (report.true_positives, report.false_positives, report.false_negatives, report.true_negatives),
+            (1, 1, 1, 2)
+        );
+        assert_eq!((report.sessions, report.labeled_anomalies), (5, 2));
+        assert!((report.precision - 0.5).abs() < 1e-9 && (report.recall - 0.5).abs() < 1e-9);
+        assert!((report.f1 - 0.5).abs() < 1e-9);
+        assert_eq!(report.missing_labels, ["c"]);
+    }
+}
@@ -1,6 +1,7 @@
 /// Smart template generator that detects log format and generates appropriate patterns
 use crate::log_format_detector::{LogFormat, LogFormatDetector};
 use crate::log_matcher::LogTemplate;
+use crate::template_metadata::{Severity, TemplateMetadata, SOURCE_SMART};
 use regex::Regex;
 
 pub struct SmartTemplateGenerator;
@@ -10,7 +11,7 @@ impl SmartTemplateGenerator {
     pub fn generate_template(log_line: &str, template_id: u64) -> LogTemplate {
         let format = LogFormatDetector::detect(log_line);
 
-        match format {
+        let mut template = match format {
             LogFormat::Syslog { has_pid } => {
                 Self::generate_syslog_template(log_line, template_id, has_pid)
             }
@@ -19,7 +20,11 @@ impl SmartTemplateGenerator {
                 Self::generate_delimited_template(log_line, template_id, delimiter)
             }
             LogFormat::Unstructured => Self::generate_generic_template(log_line, template_id),
-        }
+        };
+
+        template.metadata = TemplateMetadata::new().with_source(SOURCE_SMART);
+        template.metadata.severity = Severity::detect(log_line);
+        template
     }
 
     /// Generate template for syslog format
@@ -54,6 +59,7 @@ impl SmartTemplateGenerator {
                 pattern,
                 variables,
                 example: log_line.to_string(),
+                ..Default::default()
             }
         } else {
             Self::generate_generic_template(log_line, template_id)
@@ -163,6 +169,7 @@ impl SmartTemplateGenerator {
             pattern,
             variables,
             example: log_line.to_string(),
+            ..Default::default()
         }
     }
 }
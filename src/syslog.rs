@@ -0,0 +1,495 @@
+/// Syslog message parsing and TCP framing
+///
+/// Parses RFC 5424 and RFC 3164 (BSD) messages into their header fields and
+/// splits TCP streams into messages by octet counting or newline framing
+/// (RFC 6587), detected per frame.
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Largest message accepted over TCP or UDP
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Priority of messages without a PRI part: user.notice (RFC 3164 4.3.3)
+const DEFAULT_PRI: u8 = 13;
+
+const SEVERITIES: [&str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+    "security", "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5", "local6",
+    "local7",
+];
+
+/// Keyword of a syslog severity (0-7)
+pub fn severity_name(severity: u8) -> &'static str {
+    SEVERITIES.get(severity as usize).copied().unwrap_or("unknown")
+}
+
+/// Keyword of a syslog facility (0-23)
+pub fn facility_name(facility: u8) -> &'static str {
+    FACILITIES.get(facility as usize).copied().unwrap_or("unknown")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogProtocol {
+    Rfc3164,
+    Rfc5424,
+}
+
+/// One SD-ELEMENT: `[id name="value" ...]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredData {
+    pub id: String,
+    pub params: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyslogMessage {
+    pub protocol: SyslogProtocol,
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub hostname: Option<String>,
+    /// APP-NAME, or the TAG of a BSD message
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    pub structured_data: Vec<StructuredData>,
+    pub message: String,
+}
+
+impl SyslogMessage {
+    /// Header fields stored with the log record. Structured data params are
+    /// keyed `sd.<id>.<name>`; elements without params as `sd.<id>`.
+    pub fn headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::from([
+            ("facility".to_string(), facility_name(self.facility).to_string()),
+            ("severity".to_string(), severity_name(self.severity).to_string()),
+        ]);
+        let optional = [
+            ("hostname", &self.hostname),
+            ("app", &self.app_name),
+            ("pid", &self.proc_id),
+            ("msgid", &self.msg_id),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                headers.insert(key.to_string(), value.clone());
+            }
+        }
+        for element in &self.structured_data {
+            if element.params.is_empty() {
+                headers.insert(format!("sd.{}", element.id), String::new());
+            }
+            for (name, value) in &element.params {
+                headers.insert(format!("sd.{}.{}", element.id, name), value.clone());
+            }
+        }
+        headers
+    }
+}
+
+/// Parse a syslog message. `received` supplies the year of BSD timestamps,
+/// which carry none; those are read as UTC.
+pub fn parse(line: &str, received: DateTime<Utc>) -> Result<SyslogMessage> {
+    let line = line.trim_end_matches(['\r', '\n', '\0']);
+    let (pri, rest) = parse_pri(line)?;
+    let (facility, severity) = (pri >> 3, pri & 7);
+
+    if let Some(rest) = rest.strip_prefix("1 ") {
+        return parse_rfc5424(rest, facility, severity);
+    }
+    Ok(parse_rfc3164(rest, facility, severity, received))
+}
+
+/// `<PRI>` prefix; messages without one get user.notice
+fn parse_pri(line: &str) -> Result<(u8, &str)> {
+    let Some(rest) = line.strip_prefix('<') else {
+        return Ok((DEFAULT_PRI, line));
+    };
+    let end = rest.find('>').filter(|&end| (1..=3).contains(&end)).context("malformed PRI")?;
+    let pri: u8 = rest[..end].parse().ok().filter(|&pri| pri <= 191).context("PRI out of range")?;
+    Ok((pri, &rest[end + 1..]))
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`, after the version
+fn parse_rfc5424(rest: &str, facility: u8, severity: u8) -> Result<SyslogMessage> {
+    let mut fields = rest.splitn(6, ' ');
+    let mut next = |name: &str| fields.next().filter(|f| !f.is_empty()).with_context(|| format!("missing {}", name));
+    let nil = |field: &str| (field != "-").then(|| field.to_string());
+
+    let timestamp = match next("TIMESTAMP")? {
+        "-" => None,
+        ts => Some(DateTime::parse_from_rfc3339(ts).with_context(|| format!("invalid TIMESTAMP '{}'", ts))?.with_timezone(&Utc)),
+    };
+    let hostname = nil(next("HOSTNAME")?);
+    let app_name = nil(next("APP-NAME")?);
+    let proc_id = nil(next("PROCID")?);
+    let msg_id = nil(next("MSGID")?);
+    let (structured_data, message) = parse_structured_data(next("STRUCTURED-DATA")?)?;
+
+    Ok(SyslogMessage {
+        protocol: SyslogProtocol::Rfc5424,
+        facility,
+        severity,
+        timestamp,
+        hostname,
+        app_name,
+        proc_id,
+        msg_id,
+        structured_data,
+        message: message.trim_start_matches('\u{feff}').to_string(),
+    })
+}
+
+/// STRUCTURED-DATA and the MSG after it
+fn parse_structured_data(input: &str) -> Result<(Vec<StructuredData>, &str)> {
+    if let Some(message) = input.strip_prefix('-') {
+        return Ok((Vec::new(), message.strip_prefix(' ').unwrap_or(message)));
+    }
+
+    let mut elements = Vec::new();
+    let mut rest = input;
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element.find([' ', ']']).context("unterminated SD-ELEMENT")?;
+        let mut sd = StructuredData {
+            id: element[..id_end].to_string(),
+            params: Vec::new(),
+        };
+        rest = &element[id_end..];
+
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(after) = rest.strip_prefix(']') {
+                rest = after;
+                break;
+            }
+            let eq = rest.find("=\"").context("malformed SD-PARAM")?;
+            let name = rest[..eq].to_string();
+            let (value, after) = parse_param_value(&rest[eq + 2..])?;
+            sd.params.push((name, value));
+            rest = after;
+        }
+        elements.push(sd);
+    }
+
+    if elements.is_empty() {
+        bail!("malformed STRUCTURED-DATA");
+    }
+    Ok((elements, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// PARAM-VALUE up to the closing quote, with `\"`, `\\` and `\]` unescaped
+fn parse_param_value(input: &str) -> Result<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &input[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | ']'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    bail!("unterminated PARAM-VALUE")
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`. Senders often drop the hostname
+/// or the whole header; whatever doesn't parse stays in the message.
+fn parse_rfc3164(rest: &str, facility: u8, severity: u8, received: DateTime<Utc>) -> SyslogMessage {
+    let mut message = SyslogMessage {
+        protocol: SyslogProtocol::Rfc3164,
+        facility,
+        severity,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        proc_id: None,
+        msg_id: None,
+        structured_data: Vec::new(),
+        message: rest.to_string(),
+    };
+
+    let Some(timestamp) = rest.get(..15).and_then(|ts| parse_bsd_timestamp(ts, received)) else {
+        return message;
+    };
+    message.timestamp = Some(timestamp);
+    let mut rest = rest[15..].trim_start_matches(' ');
+
+    // A token ending in ':' or holding '[' is the tag; anything before it is the hostname
+    let first = rest.split(' ').next().unwrap_or_default();
+    if !first.is_empty() && !first.ends_with(':') && !first.contains('[') && rest.len() > first.len() {
+        message.hostname = Some(first.to_string());
+        rest = &rest[first.len() + 1..];
+    }
+
+    let tag_end = rest.find([':', '[', ' ']).unwrap_or(rest.len());
+    let (tag, after) = rest.split_at(tag_end);
+    let (proc_id, after) = match after.strip_prefix('[').and_then(|a| a.split_once(']')) {
+        Some((pid, after)) => (Some(pid.to_string()), after),
+        None => (None, after),
+    };
+    match after.strip_prefix(':') {
+        Some(msg) if !tag.is_empty() => {
+            message.app_name = Some(tag.to_string());
+            message.proc_id = proc_id;
+            message.message = msg.strip_prefix(' ').unwrap_or(msg).to_string();
+        }
+        _ => message.message = rest.to_string(),
+    }
+    message
+}
+
+/// `Mmm dd hh:mm:ss` in the year it was received, or the year before when
+/// that would put it more than a day in the future (messages around New Year)
+fn parse_bsd_timestamp(ts: &str, received: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let at = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, ts), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|dt| dt.and_utc())
+    };
+    let timestamp = at(received.year())?;
+    if timestamp > received + Duration::days(1) {
+        return at(received.year() - 1);
+    }
+    Some(timestamp)
+}
+
+/// Splits a TCP byte stream into syslog messages. A frame starting with a
+/// digit is octet-counted (`LEN SP MSG`); anything else runs to the next LF.
+#[derive(Debug, Default)]
+pub struct SyslogFramer {
+    buffer: Vec<u8>,
+}
+
+impl SyslogFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received bytes; returns the messages they completed. An error
+    /// means the stream can't be framed and the connection should be closed.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(data);
+        let mut messages = Vec::new();
+        let mut start = 0;
+
+        while start < self.buffer.len() {
+            let pending = &self.buffer[start..];
+            if pending[0] == b'\n' || pending[0] == b'\r' {
+                start += 1;
+                continue;
+            }
+
+            if pending[0].is_ascii_digit() {
+                let Some(space) = pending.iter().position(|&b| b == b' ') else {
+                    if pending.len() > 7 {
+                        bail!("invalid octet count");
+                    }
+                    break;
+                };
+                let len: usize = std::str::from_utf8(&pending[..space])
+                    .ok()
+                    .and_then(|len| len.parse().ok())
+                    .context("invalid octet count")?;
+                if len > MAX_MESSAGE_BYTES {
+                    bail!("message of {} bytes exceeds the {} byte limit", len, MAX_MESSAGE_BYTES);
+                }
+                let end = space + 1 + len;
+                if pending.len() < end {
+                    break;
+                }
+                messages.push(String::from_utf8_lossy(&pending[space + 1..end]).into_owned());
+                start += end;
+            } else {
+                let Some(newline) = pending.iter().position(|&b| b == b'\n') else {
+                    if pending.len() > MAX_MESSAGE_BYTES {
+                        bail!("message exceeds the {} byte limit", MAX_MESSAGE_BYTES);
+                    }
+                    break;
+                };
+                messages.push(String::from_utf8_lossy(&pending[..newline]).trim_end_matches('\r').to_string());
+                start += newline + 1;
+            }
+        }
+
+        self.buffer.drain(..start);
+        Ok(messages)
+    }
+
+    /// Trailing message of a closed connection that sent no final LF
+    pub fn finish(self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&self.buffer);
+        let rest = rest.trim();
+        (!rest.is_empty() && !rest.as_bytes()[0].is_ascii_digit()).then(|| rest.to_string())
+    }
+}
+
+/// How syslog messages map onto the log fields. Syslog carries no tenant, so
+/// all messages of a receiver share one org.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogMapping {
+    pub org_id: String,
+    /// Stream of every message; unset uses the app name
+    pub log_stream_id: Option<String>,
+    pub region: String,
+    /// Service and stream of messages without an app name
+    pub default_app: String,
+}
+
+impl Default for SyslogMapping {
+    fn default() -> Self {
+        Self {
+            org_id: "default".to_string(),
+            log_stream_id: None,
+            region: String::new(),
+            default_app: "syslog".to_string(),
+        }
+    }
+}
+
+impl SyslogMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn service(&self, message: &SyslogMessage) -> String {
+        message.app_name.clone().unwrap_or_else(|| self.default_app.clone())
+    }
+
+    pub fn log_stream_id(&self, message: &SyslogMessage) -> String {
+        self.log_stream_id.clone().unwrap_or_else(|| self.service(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-03-10T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_rfc5424() {
+        let line = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 4711 ID47 [exampleSDID@32473 iut="3" eventSource="Appli\"cation"][origin] An application event"#;
+        let message = parse(line, received()).unwrap();
+
+        assert_eq!(message.protocol, SyslogProtocol::Rfc5424);
+        assert_eq!((facility_name(message.facility), severity_name(message.severity)), ("local4", "notice"));
+        assert_eq!(message.timestamp.unwrap().to_rfc3339(), "2003-10-11T22:14:15.003+00:00");
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.proc_id.as_deref(), Some("4711"));
+        assert_eq!(message.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(message.structured_data.len(), 2);
+        assert_eq!(message.structured_data[0].params[1], ("eventSource".to_string(), "Appli\"cation".to_string()));
+        assert_eq!(message.message, "An application event");
+
+        let headers = message.headers();
+        assert_eq!(headers["sd.exampleSDID@32473.iut"], "3");
+        assert_eq!(headers["sd.origin"], "");
+        assert_eq!(headers["pid"], "4711");
+    }
+
+    #[test]
+    fn test_rfc5424_nil_values() {
+        let message = parse("<34>1 - - - - - -\n", received()).unwrap();
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.hostname, None);
+        assert!(message.structured_data.is_empty());
+        assert_eq!(message.message, "");
+
+        let message = parse("<34>1 - host su - - - \u{feff}'su root' failed", received()).unwrap();
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.message, "'su root' failed");
+
+        assert!(parse("<34>1 yesterday host su - - - msg", received()).is_err());
+        assert!(parse("<34>1 - host su - - [unterminated msg", received()).is_err());
+    }
+
+    #[test]
+    fn test_rfc3164() {
+        let message = parse("<34>Mar  9 22:14:15 mymachine su[230]: 'su root' failed", received()).unwrap();
+        assert_eq!(message.protocol, SyslogProtocol::Rfc3164);
+        assert_eq!((facility_name(message.facility), severity_name(message.severity)), ("auth", "crit"));
+        assert_eq!(message.timestamp.unwrap().to_rfc3339(), "2024-03-09T22:14:15+00:00");
+        assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.proc_id.as_deref(), Some("230"));
+        assert_eq!(message.message, "'su root' failed");
+
+        // No hostname
+        let message = parse("<13>Mar 10 11:00:00 cron: job started", received()).unwrap();
+        assert_eq!((message.hostname, message.app_name.as_deref()), (None, Some("cron")));
+        assert_eq!(message.message, "job started");
+
+        // December messages received in early January belong to last year
+        let january = DateTime::parse_from_rfc3339("2025-01-01T00:10:00Z").unwrap().with_timezone(&Utc);
+        let message = parse("<13>Dec 31 23:59:59 host app: bye", january).unwrap();
+        assert_eq!(message.timestamp.unwrap().year(), 2024);
+    }
+
+    #[test]
+    fn test_rfc3164_without_header() {
+        let message = parse("plain text without priority", received()).unwrap();
+        assert_eq!((message.facility, message.severity), (1, 5));
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.message, "plain text without priority");
+
+        let message = parse("<14>Mar 10 11:00:00 host no tag here", received()).unwrap();
+        assert_eq!(message.hostname.as_deref(), Some("host"));
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "no tag here");
+
+        assert!(parse("<192>overflow", received()).is_err());
+        assert!(parse("<x>bad", received()).is_err());
+    }
+
+    #[test]
+    fn test_framer_octet_counting() {
+        let mut framer = SyslogFramer::new();
+        let frames = framer.push(b"15 <13>hello w").unwrap();
+        assert!(frames.is_empty());
+        let frames = framer.push(b"orld5 <13>x9 <13>a\nb\nc").unwrap();
+        assert_eq!(frames, vec!["<13>hello world", "<13>x", "<13>a\nb\nc"]);
+        assert_eq!(framer.finish(), None);
+
+        let mut framer = SyslogFramer::new();
+        assert!(framer.push(b"99999999 <13>x").is_err());
+        let mut framer = SyslogFramer::new();
+        assert!(framer.push(b"12x <13>x").is_err());
+    }
+
+    #[test]
+    fn test_framer_newline() {
+        let mut framer = SyslogFramer::new();
+        let frames = framer.push(b"<13>one\r\n\n<13>tw").unwrap();
+        assert_eq!(frames, vec!["<13>one"]);
+        // Framing is detected per frame
+        let frames = framer.push(b"o\n5 <13>3\n<13>four").unwrap();
+        assert_eq!(frames, vec!["<13>two", "<13>3"]);
+        assert_eq!(framer.finish().as_deref(), Some("<13>four"));
+    }
+
+    #[test]
+    fn test_mapping() {
+        let mapping = SyslogMapping::new();
+        let message = parse("<13>Mar 10 11:00:00 host sshd[1]: ok", received()).unwrap();
+        assert_eq!((mapping.service(&message), mapping.log_stream_id(&message)), ("sshd".to_string(), "sshd".to_string()));
+
+        let mapping: SyslogMapping = serde_json::from_str(r#"{"org_id": "acme", "log_stream_id": "syslog"}"#).unwrap();
+        let message = parse("no header", received()).unwrap();
+        assert_eq!((mapping.org_id.as_str(), mapping.default_app.as_str()), ("acme", "syslog"));
+        assert_eq!((mapping.service(&message), mapping.log_stream_id(&message)), ("syslog".to_string(), "syslog".to_string()));
+    }
+}
@@ -0,0 +1,120 @@
+/// Template frequency changes against a baseline window
+///
+/// Compares per-template log counts in a window with the counts of a baseline
+/// window: the previous window of equal length, or the same window a day or a
+/// week earlier. Counts are compared as rates, so a baseline of a different
+/// length still gives a meaningful relative change. Ranking and the new and
+/// disappeared templates come from the ClickHouse queries.
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which window a query window is compared with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BaselineWindow {
+    /// The window of equal length that ends where the query window starts
+    #[default]
+    Previous,
+    /// The query window shifted back by this many seconds
+    Offset { secs: i64 },
+}
+
+impl BaselineWindow {
+    pub fn same_time_yesterday() -> Self {
+        BaselineWindow::Offset { secs: 24 * 3600 }
+    }
+
+    pub fn same_time_last_week() -> Self {
+        BaselineWindow::Offset { secs: 7 * 24 * 3600 }
+    }
+
+    /// Baseline range for the window `[start, end]`; the baseline end is exclusive
+    pub fn range(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        match *self {
+            BaselineWindow::Previous => (start - (end - start), start),
+            BaselineWindow::Offset { secs } => {
+                let offset = Duration::seconds(secs);
+                (start - offset, end - offset)
+            }
+        }
+    }
+}
+
+impl FromStr for BaselineWindow {
+    type Err = anyhow::Error;
+
+    /// `previous`, `day` (same time yesterday), `week` (same time last week) or an offset in seconds
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "previous" => Ok(BaselineWindow::Previous),
+            "day" | "yesterday" => Ok(BaselineWindow::same_time_yesterday()),
+            "week" | "last_week" => Ok(BaselineWindow::same_time_last_week()),
+            other => match other.parse::<i64>() {
+                Ok(secs) if secs > 0 => Ok(BaselineWindow::Offset { secs }),
+                _ => anyhow::bail!("invalid baseline '{}' (expected previous, day, week or seconds)", s),
+            },
+        }
+    }
+}
+
+impl fmt::Display for BaselineWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaselineWindow::Previous => f.write_str("previous"),
+            BaselineWindow::Offset { secs } => write!(f, "{}s earlier", secs),
+        }
+    }
+}
+
+/// Change of a template's rate against the baseline:
+/// (rate - baseline rate) / baseline rate, so 0.5 is 50% more and -1.0 gone.
+/// 0 for new templates, which have no baseline rate. The durations turn counts
+/// into rates; pass equal durations to compare counts.
+pub fn relative_change(count: u64, baseline_count: u64, duration: Duration, baseline_duration: Duration) -> f64 {
+    if baseline_count == 0 {
+        return 0.0;
+    }
+    // Baseline count scaled to the window length
+    let scale = match (duration.num_milliseconds(), baseline_duration.num_milliseconds()) {
+        (window, baseline) if window > 0 && baseline > 0 => window as f64 / baseline as f64,
+        _ => 1.0,
+    };
+    let expected = baseline_count as f64 * scale;
+    (count as f64 - expected) / expected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_change() {
+        let hour = Duration::hours(1);
+        assert!((relative_change(300, 100, hour, hour) - 2.0).abs() < 1e-9);
+        assert!((relative_change(50, 100, hour, hour) + 0.5).abs() < 1e-9);
+        // New and disappeared templates
+        assert_eq!(relative_change(10, 0, hour, hour), 0.0);
+        assert_eq!(relative_change(0, 40, hour, hour), -1.0);
+
+        // A day-long baseline is compared as a rate: 2400/day is 100/hour
+        assert!((relative_change(300, 2400, hour, Duration::hours(24)) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_baseline_window() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let end = start + Duration::hours(1);
+
+        assert_eq!(BaselineWindow::Previous.range(start, end), (start - Duration::hours(1), start));
+        assert_eq!(
+            BaselineWindow::same_time_last_week().range(start, end),
+            (start - Duration::days(7), end - Duration::days(7))
+        );
+
+        assert_eq!("week".parse::<BaselineWindow>().unwrap(), BaselineWindow::same_time_last_week());
+        assert_eq!("3600".parse::<BaselineWindow>().unwrap(), BaselineWindow::Offset { secs: 3600 });
+        assert!("-5".parse::<BaselineWindow>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+/// Template metadata
+///
+/// Severity, a human description and free-form tags, plus provenance: which
+/// generator or LLM provider created the template, with which prompt version,
+/// and when it was created and last changed. Carried on every `LogTemplate`
+/// through snapshots, JSON caches and the ClickHouse `templates` table.
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Built-in default templates
+pub const SOURCE_BUILTIN: &str = "builtin";
+/// Created or replaced through the template API
+pub const SOURCE_MANUAL: &str = "manual";
+/// Loaded from a cache file by sync-templates
+pub const SOURCE_IMPORT: &str = "import";
+/// `SmartTemplateGenerator` (format detection rules)
+pub const SOURCE_SMART: &str = "smart";
+/// `SemanticTemplate` converted to a regex template
+pub const SOURCE_SEMANTIC: &str = "semantic";
+
+/// Source of a template generated by an LLM provider (the provider config name)
+pub fn llm_source(provider: &str) -> String {
+    format!("llm:{}", provider)
+}
+
+/// Level words at a word boundary: upper case anywhere, any case when
+/// bracketed or after `level=`
+static LEVEL_WORD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"\b(TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|ERR|SEVERE|FATAL|CRIT|CRITICAL|ALERT|EMERG|PANIC)\b|(?i:\[|level[=:]\s*)(trace|debug|info|notice|warn|warning|error|err|severe|fatal|crit|critical|alert|emerg|panic)\b",
+    )
+    .unwrap()
+});
+
+/// Severity of the events a template describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Trace => "trace",
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+            Severity::Fatal => "fatal",
+        }
+    }
+
+    /// Severity from the first level word in a log line (`ERROR`, `[warn]`, `level=info`)
+    pub fn detect(log_line: &str) -> Option<Self> {
+        let caps = LEVEL_WORD.captures(log_line)?;
+        let word = caps.get(1).or_else(|| caps.get(2))?.as_str();
+        word.parse().ok()
+    }
+}
+
+impl FromStr for Severity {
+    type Err = anyhow::Error;
+
+    /// Accepts the usual level spellings, including syslog names
+    fn from_str(level: &str) -> anyhow::Result<Self> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "trace" => Ok(Severity::Trace),
+            "debug" => Ok(Severity::Debug),
+            "info" | "information" | "notice" => Ok(Severity::Info),
+            "warn" | "warning" => Ok(Severity::Warn),
+            "error" | "err" | "severe" => Ok(Severity::Error),
+            "fatal" | "crit" | "critical" | "alert" | "emerg" | "emergency" | "panic" => Ok(Severity::Fatal),
+            other => anyhow::bail!("unknown severity '{}'", other),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemplateMetadata {
+    pub severity: Option<Severity>,
+    /// One-line human description of the event
+    pub description: Option<String>,
+    /// Free-form tags (component, team, runbook, ...)
+    pub tags: Vec<String>,
+    /// What created the template: `llm:<provider>`, `smart`, `semantic`, `manual`, `import`, `builtin`
+    pub source: Option<String>,
+    /// Model name, for LLM-generated templates
+    pub model: Option<String>,
+    /// Prompt version the template was generated with (`llm_service::PROMPT_VERSION`)
+    pub prompt_version: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl TemplateMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = Some(severity);
+        self
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn with_prompt_version(mut self, version: &str) -> Self {
+        self.prompt_version = Some(version.to_string());
+        self
+    }
+
+    /// Set updated_at to `now`, and created_at too if it is not set yet
+    pub fn touch(&mut self, now: DateTime<Utc>) {
+        self.created_at.get_or_insert(now);
+        self.updated_at = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severity() {
+        assert_eq!(Severity::detect("2024-01-15 10:00:00 ERROR db: timeout"), Some(Severity::Error));
+        assert_eq!(Severity::detect("[Sun Dec 04 04:47:44 2005] [notice] workerEnv.init() ok"), Some(Severity::Info));
+        assert_eq!(Severity::detect("ts=1 level=warn msg=\"slow\""), Some(Severity::Warn));
+        // Lower-case level words in running text are not levels
+        assert_eq!(Severity::detect("could not fetch user info"), None);
+
+        assert_eq!("CRITICAL".parse::<Severity>().unwrap(), Severity::Fatal);
+        assert!("loud".parse::<Severity>().is_err());
+        assert!(Severity::Error > Severity::Warn);
+        assert_eq!(serde_json::to_string(&Severity::Warn).unwrap(), "\"warn\"");
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut metadata = TemplateMetadata::new()
+            .with_severity(Severity::Error)
+            .with_tag("db")
+            .with_tag("db")
+            .with_source(&llm_source("openai"));
+        assert_eq!(metadata.tags, ["db"]);
+        assert_eq!(metadata.source.as_deref(), Some("llm:openai"));
+
+        let created = Utc::now();
+        metadata.touch(created);
+        let later = created + chrono::Duration::seconds(5);
+        metadata.touch(later);
+        assert_eq!((metadata.created_at, metadata.updated_at), (Some(created), Some(later)));
+
+        // Missing fields deserialize to defaults
+        let parsed: TemplateMetadata = serde_json::from_str(r#"{"severity": "info"}"#).unwrap();
+        assert_eq!(parsed, TemplateMetadata::new().with_severity(Severity::Info));
+    }
+}
@@ -0,0 +1,241 @@
+/// Template linting
+///
+/// Checks a template before it is stored or added to a matcher: the regex
+/// must compile within a size limit, match its own example and have one
+/// capture group per variable, and it must carry enough literal text for
+/// fragment indexing instead of being a handful of catch-all groups.
+use crate::log_matcher::{extract_fragments, LogTemplate};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Matches catch-all capture groups: (.*), (.+), (.*?), (.+?) and named variants
+static CATCH_ALL_GROUP: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\((?:\?P?<[A-Za-z_][A-Za-z0-9_]*>)?\.[*+]\??\)").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum LintIssue {
+    /// The pattern is not a valid regex
+    InvalidRegex { error: String },
+    /// The compiled regex exceeds the size limit
+    RegexTooLarge { limit: usize },
+    /// The regex does not match the template's own example
+    ExampleNotMatched,
+    /// Number of capture groups differs from the number of variables
+    CaptureCountMismatch { captures: usize, variables: usize },
+    /// Not enough literal fragments for the matcher to index
+    TooFewFragments { found: usize, required: usize },
+    /// Most capture groups are catch-alls like (.*)
+    CatchAllDominated { catch_all: usize, captures: usize },
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintIssue::InvalidRegex { error } => write!(f, "invalid regex: {}", error),
+            LintIssue::RegexTooLarge { limit } => {
+                write!(f, "compiled regex exceeds size limit of {} bytes", limit)
+            }
+            LintIssue::ExampleNotMatched => write!(f, "pattern does not match its example"),
+            LintIssue::CaptureCountMismatch { captures, variables } => write!(
+                f,
+                "{} capture groups but {} variables",
+                captures, variables
+            ),
+            LintIssue::TooFewFragments { found, required } => write!(
+                f,
+                "{} indexable literal fragments, need at least {}",
+                found, required
+            ),
+            LintIssue::CatchAllDominated { catch_all, captures } => write!(
+                f,
+                "{} of {} capture groups are catch-alls like (.*)",
+                catch_all, captures
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateValidator {
+    /// Compiled regex size limit in bytes
+    pub max_regex_size: usize,
+    /// Minimum number of indexable literal fragments
+    pub min_fragments: usize,
+    /// Minimum trimmed length for a fragment to count as indexable
+    pub min_fragment_length: usize,
+    /// Maximum share of capture groups that may be catch-alls
+    pub max_catch_all_ratio: f64,
+}
+
+impl Default for TemplateValidator {
+    fn default() -> Self {
+        Self {
+            max_regex_size: 1 << 20,
+            min_fragments: 1,
+            min_fragment_length: 3,
+            max_catch_all_ratio: 0.5,
+        }
+    }
+}
+
+impl TemplateValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_regex_size(mut self, bytes: usize) -> Self {
+        self.max_regex_size = bytes;
+        self
+    }
+
+    pub fn with_min_fragments(mut self, count: usize) -> Self {
+        self.min_fragments = count;
+        self
+    }
+
+    pub fn with_min_fragment_length(mut self, length: usize) -> Self {
+        self.min_fragment_length = length.max(1);
+        self
+    }
+
+    pub fn with_max_catch_all_ratio(mut self, ratio: f64) -> Self {
+        self.max_catch_all_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Run all checks and return every issue found (empty if the template is valid)
+    pub fn lint(&self, template: &LogTemplate) -> Vec<LintIssue> {
+        let mut issues = Vec::new();
+
+        match RegexBuilder::new(&template.pattern)
+            .size_limit(self.max_regex_size)
+            .build()
+        {
+            Ok(regex) => {
+                if !regex.is_match(&template.example) {
+                    issues.push(LintIssue::ExampleNotMatched);
+                }
+
+                let captures = regex.captures_len() - 1;
+                if captures != template.variables.len() {
+                    issues.push(LintIssue::CaptureCountMismatch {
+                        captures,
+                        variables: template.variables.len(),
+                    });
+                }
+
+                let catch_all = CATCH_ALL_GROUP.find_iter(&template.pattern).count();
+                if catch_all > 0 && catch_all as f64 > captures as f64 * self.max_catch_all_ratio {
+                    issues.push(LintIssue::CatchAllDominated { catch_all, captures });
+                }
+            }
+            Err(regex::Error::CompiledTooBig(limit)) => {
+                issues.push(LintIssue::RegexTooLarge { limit });
+            }
+            Err(e) => {
+                issues.push(LintIssue::InvalidRegex { error: e.to_string() });
+            }
+        }
+
+        let found = extract_fragments(&template.pattern, 1)
+            .iter()
+            .filter(|fragment| fragment.trim().len() >= self.min_fragment_length)
+            .count();
+        if found < self.min_fragments {
+            issues.push(LintIssue::TooFewFragments {
+                found,
+                required: self.min_fragments,
+            });
+        }
+
+        issues
+    }
+
+    /// Validate a template, failing with all reasons joined into one message
+    pub fn validate(&self, template: &LogTemplate) -> anyhow::Result<()> {
+        let issues = self.lint(template);
+        if issues.is_empty() {
+            return Ok(());
+        }
+
+        let reasons: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+        anyhow::bail!(
+            "template '{}' rejected: {}",
+            template.pattern,
+            reasons.join("; ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(pattern: &str, variables: &[&str], example: &str) -> LogTemplate {
+        LogTemplate {
+            template_id: 0,
+            pattern: pattern.to_string(),
+            variables: variables.iter().map(|v| v.to_string()).collect(),
+            example: example.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_template() {
+        let validator = TemplateValidator::default();
+        let t = template(
+            r"^Connection from (\d+\.\d+\.\d+\.\d+) closed after (\d+)ms$",
+            &["ip", "duration"],
+            "Connection from 10.0.0.1 closed after 35ms",
+        );
+        assert!(validator.lint(&t).is_empty());
+        assert!(validator.validate(&t).is_ok());
+
+        // One catch-all next to a specific group is fine
+        let t = template(r"user (\w+) said (.*)", &["user", "message"], "user bob said hi there");
+        assert!(validator.lint(&t).is_empty());
+    }
+
+    #[test]
+    fn test_degenerate_templates_rejected() {
+        let validator = TemplateValidator::default();
+
+        let t = template(r"^(.*)$", &["message"], "anything at all");
+        let issues = validator.lint(&t);
+        assert!(issues.contains(&LintIssue::CatchAllDominated { catch_all: 1, captures: 1 }));
+        assert!(issues.contains(&LintIssue::TooFewFragments { found: 0, required: 1 }));
+
+        let t = template(r"(.+?) error (?P<rest>.*)", &["a", "b"], "db error timeout");
+        assert!(validator
+            .lint(&t)
+            .contains(&LintIssue::CatchAllDominated { catch_all: 2, captures: 2 }));
+    }
+
+    #[test]
+    fn test_structural_checks() {
+        let validator = TemplateValidator::default();
+
+        let t = template(r"disk (\w+ full", &["disk"], "disk sda1 full");
+        assert!(matches!(validator.lint(&t)[0], LintIssue::InvalidRegex { .. }));
+
+        let t = template(r"disk (\d+) full", &["disk"], "disk sda1 full");
+        assert_eq!(validator.lint(&t), vec![LintIssue::ExampleNotMatched]);
+
+        let t = template(r"disk (\w+) full on (\w+)", &["disk"], "disk sda1 full on web1");
+        assert_eq!(
+            validator.lint(&t),
+            vec![LintIssue::CaptureCountMismatch { captures: 2, variables: 1 }]
+        );
+
+        let small = TemplateValidator::default().with_max_regex_size(64);
+        let t = template(r"disk (\w{1,500}) full", &["disk"], "disk sda1 full");
+        assert!(matches!(small.lint(&t)[0], LintIssue::RegexTooLarge { .. }));
+
+        let err = validator.validate(&template(r"disk (\d+) full", &["disk"], "disk sda1 full"));
+        assert!(err.unwrap_err().to_string().contains("does not match its example"));
+    }
+}
@@ -9,9 +9,20 @@
 /// - Level 1 (Log Type): STATIC keywords only → "auth failure"
 /// - Level 2 (Template ID): STATIC + PARAMETER → "auth failure for user=root"
 /// - For KL divergence: Track PARAMETER distributions per log type
+///
+/// The matcher groups its templates into log types with `template_log_type`.
+use crate::log_matcher::{content_id, head_line, normalize_pattern, LogTemplate};
+use crate::semantic_template_generator::tokenize;
+use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+static IPV4: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$").unwrap());
+static TIME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{2}:\d{2}:\d{2}$").unwrap());
+static DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?:\d{4}-\d{2}-\d{2}|\d{2}/\d{2}/\d{4})$").unwrap());
+static UUID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap());
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenClass {
     /// Static keywords - define log structure
@@ -95,14 +106,14 @@ fn is_static_keyword(token: &str) -> bool {
 }
 
 /// Check if token is ephemeral (always changes, no clustering value)
-fn is_ephemeral(token: &str) -> bool {
+pub(crate) fn is_ephemeral(token: &str) -> bool {
     // Pure numbers (PIDs, ports, counts)
     if token.chars().all(|c| c.is_numeric()) {
         return true;
     }
 
     // IP addresses (v4)
-    if Regex::new(r"^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$").unwrap().is_match(token) {
+    if IPV4.is_match(token) {
         return true;
     }
 
@@ -112,13 +123,12 @@ fn is_ephemeral(token: &str) -> bool {
     }
 
     // Timestamps (HH:MM:SS)
-    if Regex::new(r"^\d{2}:\d{2}:\d{2}$").unwrap().is_match(token) {
+    if TIME.is_match(token) {
         return true;
     }
 
     // Dates (YYYY-MM-DD, MM/DD/YYYY, etc.)
-    if Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap().is_match(token) ||
-       Regex::new(r"^\d{2}/\d{2}/\d{4}$").unwrap().is_match(token) {
+    if DATE.is_match(token) {
         return true;
     }
 
@@ -129,7 +139,7 @@ fn is_ephemeral(token: &str) -> bool {
     }
 
     // UUIDs
-    if Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap().is_match(token) {
+    if UUID.is_match(token) {
         return true;
     }
 
@@ -228,6 +238,70 @@ pub fn extract_template_signature(tokens: &[(&str, TokenClass)]) -> String {
         .join(" ")
 }
 
+/// Classify the tokens of a template's example using the template itself.
+/// Tokens inside a capture group are ephemeral or parameters. Literal tokens
+/// are static unless they look ephemeral or like a typed parameter (`root`,
+/// a hostname, a path), which is how value-specific variants of one event
+/// (`user root ...` vs `user admin ...`) end up with the same log type.
+pub fn classify_template_example<'a>(regex: Option<&Regex>, example: &'a str) -> Vec<(&'a str, TokenClass)> {
+    let captured = |text: &str| {
+        let caps = regex?.captures(text)?;
+        Some(caps.iter().skip(1).flatten().map(|m| m.range()).collect::<Vec<_>>())
+    };
+
+    // The text the regex matched: the example, or the first line of a multi-line record
+    let (text, groups) = match captured(example) {
+        Some(groups) => (example, groups),
+        None => head_line(example)
+            .and_then(|line| captured(line).map(|groups| (line, groups)))
+            .unwrap_or((example, Vec::new())),
+    };
+
+    let mut previous: Option<&str> = None;
+    tokenize(text)
+        .into_iter()
+        .map(|token| {
+            let start = token.as_ptr() as usize - text.as_ptr() as usize;
+            let end = start + token.len();
+            let in_group = groups.iter().any(|group| group.start < end && start < group.end);
+
+            let class = if in_group {
+                if is_ephemeral(token) {
+                    TokenClass::Ephemeral
+                } else {
+                    TokenClass::Parameter(classify_parameter(token, previous))
+                }
+            } else {
+                // No context for literals: the word after `user` is not a user name
+                match classify_token(token, None) {
+                    TokenClass::Parameter(ParameterType::Generic) => TokenClass::Static,
+                    class => class,
+                }
+            };
+            previous = Some(token);
+            (token, class)
+        })
+        .collect()
+}
+
+/// Log type signature of a template (Level 1). A template whose example has
+/// no static token is its own log type, keyed by its normalized pattern.
+pub fn template_log_type(regex: Option<&Regex>, template: &LogTemplate) -> String {
+    let tokens = classify_template_example(regex, &template.example);
+    let signature = extract_log_type_signature(&tokens);
+    if signature.is_empty() {
+        normalize_pattern(&template.pattern)
+    } else {
+        signature
+    }
+}
+
+/// Deterministic log type ID for a signature. Log types are not tenant-scoped,
+/// so the same event type has the same ID in every stream.
+pub fn log_type_id(signature: &str) -> u64 {
+    content_id("log_type", signature)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -302,4 +376,29 @@ mod tests {
         let signature = extract_template_signature(&tokens);
         assert_eq!(signature, "sshd authentication failure <User> <Location>");
     }
+
+    #[test]
+    fn test_template_log_type() {
+        let template = |pattern: &str, example: &str| LogTemplate {
+            pattern: pattern.to_string(),
+            example: example.to_string(),
+            ..Default::default()
+        };
+        let log_type = |t: &LogTemplate| template_log_type(Regex::new(&t.pattern).ok().as_ref(), t);
+
+        let root = template(r"user root logged in from (\S+) port (\d+)", "user root logged in from 10.0.0.1 port 22");
+        let admin = template(r"user admin logged in from (\S+) port (\d+)", "user admin logged in from db.example.com port 22");
+        let any = template(r"user (\w+) logged in from (\S+) port (\d+)", "user alice logged in from 10.0.0.2 port 22");
+        let logout = template(r"user (\w+) logged out", "user alice logged out");
+
+        assert_eq!(log_type(&root), "user logged in from port");
+        assert_eq!(log_type(&admin), log_type(&root));
+        assert_eq!(log_type(&any), log_type(&root));
+        assert_ne!(log_type(&logout), log_type(&root));
+        assert_eq!(log_type_id(&log_type(&admin)), log_type_id(&log_type(&root)));
+
+        // Only variables: the template is its own log type
+        let bare = template(r"^(\d+) (\S+)$", "42 ok");
+        assert_eq!(log_type(&bare), r"(\d+) (\S+)");
+    }
 }
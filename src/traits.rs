@@ -65,6 +65,9 @@ pub trait LogMatcherTrait: Send + Sync {
     fn add_template(&mut self, template: LogTemplate);
 
     /// Add multiple templates at once
+    ///
+    /// Default implementation calls `add_template` for each template;
+    /// implementations with an expensive index build should override it
     fn add_templates(&mut self, templates: Vec<LogTemplate>) {
         for template in templates {
             self.add_template(template);
@@ -85,6 +88,21 @@ pub trait LogMatcherTrait: Send + Sync {
         log_lines.iter().map(|line| self.match_log(line)).collect()
     }
 
+    /// Match a raw byte log line that may not be valid UTF-8
+    ///
+    /// Default implementation converts lossily and calls `match_log`;
+    /// implementations that can search bytes directly should override it
+    fn match_log_bytes(&self, log_line: &[u8]) -> Option<u64> {
+        self.match_log(&String::from_utf8_lossy(log_line))
+    }
+
+    /// Match multiple raw byte log lines
+    ///
+    /// Default implementation calls `match_log_bytes` for each line
+    fn match_batch_bytes(&self, log_lines: &[&[u8]]) -> Vec<Option<u64>> {
+        log_lines.iter().map(|line| self.match_log_bytes(line)).collect()
+    }
+
     /// Match multiple log lines in parallel (multi-threaded batch processing)
     ///
     /// Uses rayon for parallel processing with per-thread scratch buffers.
@@ -137,6 +155,14 @@ pub trait DatasetLoader: Send + Sync {
     /// Load raw log lines (no ground truth)
     fn load_raw_logs(&self) -> Result<Vec<String>>;
 
+    /// Load raw log lines as bytes, without UTF-8 validation
+    ///
+    /// Default implementation goes through `load_raw_logs`; file-based
+    /// loaders should override it to read bytes directly
+    fn load_raw_log_bytes(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self.load_raw_logs()?.into_iter().map(String::into_bytes).collect())
+    }
+
     /// Load structured data with ground truth labels
     fn load_ground_truth(&self) -> Result<Vec<GroundTruthEntry>>;
 
@@ -0,0 +1,76 @@
+/// Test the count anomaly webhook against a local HTTP stand-in
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use log_analyzer::count_anomaly::{AnomalyConfig, AnomalyKind, AnomalyNotification, AnomalyWebhook, CountAnomalyDetector};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+type Received = Arc<Mutex<Vec<AnomalyNotification>>>;
+
+/// Start a webhook receiver on a free local port; returns its URL
+async fn start_receiver(status: StatusCode) -> (String, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(move |State(received): State<Received>, Json(notification): Json<AnomalyNotification>| async move {
+                received.lock().unwrap().push(notification);
+                status
+            }),
+        )
+        .with_state(received.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (format!("http://{}/hook", addr), received)
+}
+
+fn window(i: i64) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap() + Duration::minutes(i);
+    (start, start + Duration::minutes(1))
+}
+
+#[tokio::test]
+async fn test_webhook_receives_anomalies() {
+    let (url, received) = start_receiver(StatusCode::OK).await;
+    let webhook = AnomalyWebhook::new(&url, std::time::Duration::from_secs(5)).unwrap();
+
+    let mut detector = CountAnomalyDetector::new(AnomalyConfig::new().with_min_history(5));
+    for i in 0..20 {
+        let (start, end) = window(i);
+        assert!(detector.observe(&HashMap::from([(1, 100)]), start, end).is_empty());
+    }
+    let (start, end) = window(20);
+    let anomalies = detector.observe(&HashMap::from([(1, 500), (2, 3)]), start, end);
+    assert_eq!(anomalies.len(), 2);
+
+    webhook.send("org-1", "stream-1", &anomalies).await.unwrap();
+    // Nothing to report: no request
+    webhook.send("org-1", "stream-1", &[]).await.unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let notification = &received[0];
+    assert_eq!((notification.org_id.as_str(), notification.log_stream_id.as_str()), ("org-1", "stream-1"));
+    assert_eq!(notification.anomalies, anomalies);
+    let kinds: Vec<AnomalyKind> = notification.anomalies.iter().map(|a| a.kind).collect();
+    assert!(kinds.contains(&AnomalyKind::Spike) && kinds.contains(&AnomalyKind::NewTemplate));
+}
+
+#[tokio::test]
+async fn test_webhook_error_status() {
+    let (url, _received) = start_receiver(StatusCode::SERVICE_UNAVAILABLE).await;
+    let webhook = AnomalyWebhook::new(&url, std::time::Duration::from_secs(5)).unwrap();
+
+    let mut detector = CountAnomalyDetector::new(AnomalyConfig::new());
+    let (start, end) = window(0);
+    detector.observe(&HashMap::from([(1, 10)]), start, end);
+    let (start, end) = window(1);
+    let anomalies = detector.observe(&HashMap::from([(2, 10)]), start, end);
+
+    let err = webhook.send("org-1", "stream-1", &anomalies).await.unwrap_err();
+    assert!(err.to_string().contains("503"), "{}", err);
+}
@@ -37,6 +37,11 @@
 ///    cargo test --release --test benchmarks full -- --nocapture --ignored
 ///    ```
 ///
+/// 7. **Snapshot load** - Cold start from a binary snapshot, with and without its saved tables
+///    ```bash
+///    cargo test --release --test benchmarks snapshot_load -- --nocapture --ignored
+///    ```
+///
 /// ## Performance Tips:
 /// - ALWAYS use `--release` flag for accurate measurements
 /// - Debug mode is 20-50x slower than release mode
@@ -151,16 +156,21 @@ fn load_cached_matcher(dataset_name: &str) -> anyhow::Result<LogMatcher> {
     let cached: CachedTemplates = serde_json::from_str(&json_content)?;
 
     let config = MatcherConfig::batch_processing();
-    let mut matcher = LogMatcher::with_config(config);
-
-    for template in cached.templates {
-        matcher.add_template(LogTemplate {
-            template_id: template.template_id,
-            pattern: template.pattern,
-            variables: template.variables,
-            example: template.example,
-        });
-    }
+    let matcher = LogMatcher::with_config(config);
+
+    matcher.add_templates(
+        cached
+            .templates
+            .into_iter()
+            .map(|template| LogTemplate {
+                template_id: template.template_id,
+                pattern: template.pattern,
+                variables: template.variables,
+                example: template.example,
+                ..Default::default()
+            })
+            .collect(),
+    ).unwrap();
 
     Ok(matcher)
 }
@@ -537,9 +547,7 @@ async fn mixed() -> anyhow::Result<()> {
     for (dataset_name, matcher, _) in &all_data {
         let templates = matcher.get_all_templates();
         println!("  Adding {} templates from {}", templates.len(), dataset_name);
-        for template in templates {
-            combined_matcher.add_template(template);
-        }
+        combined_matcher.add_templates(templates).unwrap();
         template_count += matcher.get_all_templates().len();
     }
 
@@ -607,6 +615,77 @@ async fn mixed() -> anyhow::Result<()> {
     Ok(())
 }
 
+// ============================================================================
+// Benchmark: Snapshot load (saved tables vs rebuild)
+// ============================================================================
+
+/// The Aho-Corasick automaton has no serialized form and is built on every
+/// load either way; the saved tables skip fragment extraction, weighting and
+/// log type classification. Regexes are compiled in both cases.
+#[test]
+#[ignore]
+fn snapshot_load() -> anyhow::Result<()> {
+    println!("\n{:=<100}", "");
+    println!("💾 SNAPSHOT LOAD BENCHMARK (saved tables vs rebuild)");
+    println!("{:=<100}\n", "");
+
+    const RUNS: u32 = 3;
+
+    for template_count in [1_000u64, 5_000] {
+        let matcher = LogMatcher::empty(MatcherConfig::default());
+        let templates = (0..template_count)
+            .map(|i| LogTemplate {
+                template_id: i + 1,
+                pattern: format!(
+                    r"service{} request (\d+) for tenant{} completed in (\d+)ms on node (\S+) shard{}",
+                    i % 500,
+                    i / 500,
+                    i % 37
+                ),
+                variables: vec!["request".to_string(), "ms".to_string(), "node".to_string()],
+                example: format!("service{} request 7 for tenant{} completed in 12ms on node a1 shard{}", i % 500, i / 500, i % 37),
+                ..Default::default()
+            })
+            .collect();
+        matcher.add_templates(templates)?;
+
+        let with_tables = "snapshot_load_tables.bin";
+        let without_tables = "snapshot_load_rebuild.bin";
+        matcher.save_to_file(with_tables)?;
+
+        // Another format version makes the loader ignore the tables and rebuild
+        let mut bytes = fs::read(with_tables)?;
+        let version = u32::from_le_bytes(bytes[8..12].try_into()?);
+        bytes[8..12].copy_from_slice(&(version + 1).to_le_bytes());
+        fs::write(without_tables, &bytes)?;
+
+        let time_load = |path: &str| -> anyhow::Result<f64> {
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                let loaded = LogMatcher::load_from_file(path)?;
+                assert_eq!(loaded.template_count() as u64, template_count);
+            }
+            Ok(start.elapsed().as_secs_f64() * 1000.0 / RUNS as f64)
+        };
+        let tables_ms = time_load(with_tables)?;
+        let rebuild_ms = time_load(without_tables)?;
+
+        println!(
+            "{:>6} templates: {:>9.1} ms with tables, {:>9.1} ms rebuilt ({:.2}x)",
+            template_count,
+            tables_ms,
+            rebuild_ms,
+            rebuild_ms / tables_ms
+        );
+
+        fs::remove_file(with_tables).ok();
+        fs::remove_file(without_tables).ok();
+    }
+
+    println!("\n{:=<100}", "");
+    Ok(())
+}
+
 // ============================================================================
 // Core Benchmark Functions
 // ============================================================================
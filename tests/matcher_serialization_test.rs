@@ -5,7 +5,10 @@
 /// - Caching generated templates from Ollama
 /// - Distributing pre-built matchers
 /// - Persisting learned patterns
-use log_analyzer::log_matcher::{LogMatcher, LogTemplate};
+use log_analyzer::log_matcher::{content_template_id, LogMatcher, LogTemplate, SNAPSHOT_FORMAT_VERSION};
+use log_analyzer::matcher_config::MatcherConfig;
+use log_analyzer::template_metadata::{Severity, TemplateMetadata};
+use serde::Serialize;
 
 #[test]
 fn test_save_and_load_binary() {
@@ -17,14 +20,16 @@ fn test_save_and_load_binary() {
         pattern: r"(\d{4}-\d{2}-\d{2}) INFO (.+?) logged in".to_string(),
         variables: vec!["timestamp".to_string(), "username".to_string()],
         example: "2025-01-15 INFO alice logged in".to_string(),
-    });
+        ..Default::default()
+    }).unwrap();
 
     matcher.add_template(LogTemplate {
         template_id: 101,
         pattern: r"ERROR: Connection to (.+?):(\d+) failed".to_string(),
         variables: vec!["host".to_string(), "port".to_string()],
         example: "ERROR: Connection to db.example.com:5432 failed".to_string(),
-    });
+        ..Default::default()
+    }).unwrap();
 
     // Test matching before save
     let test_log = "2025-01-15 INFO bob logged in";
@@ -63,7 +68,8 @@ fn test_save_and_load_json() {
         pattern: r"Request (.+?) completed in (\d+)ms".to_string(),
         variables: vec!["request_id".to_string(), "duration".to_string()],
         example: "Request req_abc123 completed in 145ms".to_string(),
-    });
+        ..Default::default()
+    }).unwrap();
 
     // Save to JSON file (human-readable)
     let path = "test_matcher.json";
@@ -103,9 +109,10 @@ fn test_preserves_all_template_data() {
         pattern: r"cpu_usage: (\d+\.\d+)% - (.*)".to_string(),
         variables: vec!["percentage".to_string(), "message".to_string()],
         example: "cpu_usage: 45.2% - Server load normal".to_string(),
+        ..Default::default()
     };
 
-    matcher.add_template(original_template.clone());
+    matcher.add_template(original_template.clone()).unwrap();
 
     // Save and load
     let path = "test_template_preservation.bin";
@@ -141,7 +148,8 @@ fn test_aho_corasick_rebuilt_correctly() {
             pattern: format!(r"Pattern{} (.+?) value: (\d+)", i),
             variables: vec!["field".to_string(), "value".to_string()],
             example: format!("Pattern{} test value: 123", i),
-        });
+            ..Default::default()
+        }).unwrap();
     }
 
     // Test logs for each template
@@ -191,7 +199,8 @@ fn test_performance_binary_vs_json() {
             pattern: format!(r"Event{} (\d+) (.+)", i),
             variables: vec!["id".to_string(), "data".to_string()],
             example: format!("Event{} 123 test", i),
-        });
+            ..Default::default()
+        }).unwrap();
     }
 
     // Binary save/load
@@ -240,3 +249,226 @@ fn test_performance_binary_vs_json() {
 
     println!("✅ Performance comparison test passed");
 }
+
+fn snapshot_test_matcher() -> LogMatcher {
+    let config = MatcherConfig::default().with_fragment_threshold(0.5);
+    let matcher = LogMatcher::empty(config);
+
+    matcher.add_templates(vec![
+        LogTemplate {
+            template_id: 10,
+            pattern: r"user (\w+) logged in from (\S+)".to_string(),
+            variables: vec!["user".to_string(), "ip".to_string()],
+            example: "user alice logged in from 10.0.0.1".to_string(),
+            ..Default::default()
+        },
+        LogTemplate {
+            template_id: 11,
+            pattern: r"user (\w+) logged out after (\d+) minutes".to_string(),
+            variables: vec!["user".to_string(), "minutes".to_string()],
+            example: "user alice logged out after 30 minutes".to_string(),
+            ..Default::default()
+        },
+        LogTemplate {
+            template_id: 12,
+            pattern: r"disk (\w+) usage at (\d+) percent".to_string(),
+            variables: vec!["disk".to_string(), "pct".to_string()],
+            example: "disk sda1 usage at 91 percent".to_string(),
+            ..Default::default()
+        },
+    ]).unwrap();
+    matcher.set_template_disabled(12, true);
+    matcher
+}
+
+const SNAPSHOT_TEST_LOGS: &[&str] = &[
+    "user bob logged in from 192.168.1.5",
+    "user carol logged out after 12 minutes",
+    "disk sdb2 usage at 40 percent",
+    "nothing to see here",
+];
+
+#[test]
+fn test_snapshot_round_trip_preserves_tables() {
+    let matcher = snapshot_test_matcher();
+
+    let path = "test_snapshot_round_trip.bin";
+    matcher.save_to_file(path).unwrap();
+
+    // Header: magic + current format version
+    let bytes = std::fs::read(path).unwrap();
+    assert_eq!(&bytes[0..8], b"LAMSNAP\0");
+    assert_eq!(u32::from_le_bytes(bytes[8..12].try_into().unwrap()), SNAPSHOT_FORMAT_VERSION);
+
+    let loaded = LogMatcher::load_from_file(path).unwrap();
+
+    assert_eq!(
+        loaded.match_batch(SNAPSHOT_TEST_LOGS),
+        matcher.match_batch(SNAPSHOT_TEST_LOGS)
+    );
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[0]), Some(10));
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[2]), None);
+
+    // Fragment tables, weights and log types come back unchanged
+    for log in SNAPSHOT_TEST_LOGS {
+        assert_eq!(loaded.explain_match(log), matcher.explain_match(log));
+    }
+    assert_eq!(loaded.log_types(), matcher.log_types());
+
+    // Disabled state and config survive the round-trip
+    assert_eq!(loaded.disabled_templates(), vec![12]);
+    assert_eq!(loaded.config().fragment_match_threshold, 0.5);
+    assert_eq!(loaded.template_count(), 3);
+
+    // The restored matcher is fully live
+    loaded.set_template_disabled(12, false);
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[2]), Some(12));
+
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_snapshot_version_mismatch_rebuilds() {
+    let matcher = snapshot_test_matcher();
+
+    let path = "test_snapshot_version_mismatch.bin";
+    matcher.save_to_file(path).unwrap();
+
+    let mut bytes = std::fs::read(path).unwrap();
+    bytes[8..12].copy_from_slice(&(SNAPSHOT_FORMAT_VERSION + 1).to_le_bytes());
+    std::fs::write(path, &bytes).unwrap();
+
+    let loaded = LogMatcher::load_from_file(path).unwrap();
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[0]), Some(10));
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[1]), Some(11));
+    assert_eq!(loaded.disabled_templates(), vec![12]);
+    // The config lives in the templates section, so a rebuild keeps it
+    assert_eq!(loaded.config().fragment_match_threshold, 0.5);
+
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_snapshot_corrupt_tables_rebuilds() {
+    let matcher = snapshot_test_matcher();
+
+    let path = "test_snapshot_corrupt_tables.bin";
+    matcher.save_to_file(path).unwrap();
+
+    // Chop off the end of the tables section
+    let bytes = std::fs::read(path).unwrap();
+    std::fs::write(path, &bytes[..bytes.len() - 16]).unwrap();
+
+    let loaded = LogMatcher::load_from_file(path).unwrap();
+    assert_eq!(loaded.match_log(SNAPSHOT_TEST_LOGS[0]), Some(10));
+    assert_eq!(loaded.template_count(), 3);
+    assert_eq!(loaded.config().fragment_match_threshold, 0.5);
+
+    std::fs::remove_file(path).ok();
+}
+
+/// `LogTemplate` as written before template metadata existed
+#[derive(Serialize)]
+struct LegacyTemplate {
+    template_id: u64,
+    pattern: String,
+    variables: Vec<String>,
+    example: String,
+}
+
+fn legacy_template() -> LegacyTemplate {
+    LegacyTemplate {
+        template_id: 7,
+        pattern: r"Request (.+?) completed in (\d+)ms".to_string(),
+        variables: vec!["request_id".to_string(), "duration".to_string()],
+        example: "Request req_abc123 completed in 145ms".to_string(),
+    }
+}
+
+#[test]
+fn test_json_round_trip_preserves_config() {
+    let matcher = snapshot_test_matcher();
+
+    let path = "test_snapshot_config.json";
+    matcher.save_to_json(path).unwrap();
+    let loaded = LogMatcher::load_from_json(path).unwrap();
+    assert_eq!(loaded.config().fragment_match_threshold, 0.5);
+    assert_eq!(loaded.disabled_templates(), vec![12]);
+    assert_eq!(loaded.match_batch(SNAPSHOT_TEST_LOGS), matcher.match_batch(SNAPSHOT_TEST_LOGS));
+    std::fs::remove_file(path).ok();
+
+    // JSON written before the config was saved loads with the default one
+    let path = "test_snapshot_no_config.json";
+    std::fs::write(path, r#"{"templates": [], "next_template_id": 1}"#).unwrap();
+    let loaded = LogMatcher::load_from_json(path).unwrap();
+    assert_eq!(loaded.config().fragment_match_threshold, MatcherConfig::default().fragment_match_threshold);
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_snapshot_round_trip_preserves_metadata() {
+    let matcher = LogMatcher::empty(MatcherConfig::default());
+    let metadata = TemplateMetadata::new()
+        .with_severity(Severity::Error)
+        .with_description("Request finished slowly")
+        .with_tag("api")
+        .with_source("llm:openai")
+        .with_prompt_version("template-v2");
+    matcher.add_template(LogTemplate {
+        template_id: 7,
+        pattern: r"Request (.+?) completed in (\d+)ms".to_string(),
+        variables: vec!["request_id".to_string(), "duration".to_string()],
+        example: "Request req_abc123 completed in 145ms".to_string(),
+        metadata: metadata.clone(),
+    }).unwrap();
+
+    let path = "test_snapshot_metadata.bin";
+    matcher.save_to_file(path).unwrap();
+    let loaded = LogMatcher::load_from_file(path).unwrap();
+    assert_eq!(loaded.get_all_templates()[0].metadata, metadata);
+    // Log types come back from the saved tables
+    assert_eq!(loaded.log_types(), matcher.log_types());
+    std::fs::remove_file(path).ok();
+
+    let path = "test_snapshot_metadata.json";
+    matcher.save_to_json(path).unwrap();
+    let loaded = LogMatcher::load_from_json(path).unwrap();
+    assert_eq!(loaded.get_all_templates()[0].metadata, metadata);
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+fn test_load_template_only_file() {
+    // Layout written by earlier versions: templates and next id, no header
+    #[derive(Serialize)]
+    struct MatcherState {
+        templates: Vec<LegacyTemplate>,
+        next_template_id: u64,
+    }
+
+    let state = MatcherState {
+        templates: vec![legacy_template()],
+        next_template_id: 8,
+    };
+
+    let path = "test_snapshot_legacy.bin";
+    std::fs::write(path, bincode::serialize(&state).unwrap()).unwrap();
+
+    let loaded = LogMatcher::load_from_file(path).unwrap();
+    assert_eq!(loaded.match_log("Request req_xyz completed in 9ms"), Some(7));
+
+    // The saved next_template_id is ignored; new templates get content-addressed IDs
+    loaded.add_template(LogTemplate {
+        template_id: 0,
+        pattern: r"cache (\w+) evicted".to_string(),
+        variables: vec!["key".to_string()],
+        example: "cache users evicted".to_string(),
+        ..Default::default()
+    }).unwrap();
+    assert_eq!(
+        loaded.match_log("cache sessions evicted"),
+        Some(content_template_id("", r"cache (\w+) evicted"))
+    );
+
+    std::fs::remove_file(path).ok();
+}
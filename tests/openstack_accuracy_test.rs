@@ -123,7 +123,7 @@ async fn test_openstack_grouping_accuracy() {
                     let tid = new_template.template_id;
                     {
                         let mut m = matcher.write().await;
-                        m.add_template(new_template);
+                        m.add_template(new_template).unwrap();
                     }
                     generated_templates += 1;
                     Some(tid)
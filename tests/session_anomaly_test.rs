@@ -0,0 +1,111 @@
+/// Sequence anomaly detection on the LogHub OpenStack dataset
+///
+/// Sessions are VM instances. The model is trained on openstack_normal1.log and
+/// scored on openstack_normal2.log plus openstack_abnormal.log against
+/// anomaly_labels.txt. Only the labels ship with the repo; place the three log
+/// files next to them and run the ignored evaluation with `--ignored`.
+/// tests/fixtures/openstack holds a few labeled instances in the same format,
+/// which always run.
+use log_analyzer::log_format_detector::HeaderFormat;
+use log_analyzer::log_matcher::LogMatcher;
+use log_analyzer::loghub_loader::read_raw_lines;
+use log_analyzer::matcher_config::MatcherConfig;
+use log_analyzer::session_anomaly::{
+    evaluate, load_anomaly_labels, EvaluationReport, NgramModel, SequenceConfig, Session, SessionBuilder, SessionConfig,
+};
+use log_analyzer::smart_template_generator::SmartTemplateGenerator;
+use std::path::Path;
+
+const DATA_DIR: &str = "data/Loghub OpenStack Datasets";
+const FIXTURE_DIR: &str = "tests/fixtures/openstack";
+
+/// Train on `train`, score the sessions of `tests` and evaluate them against `labels`
+fn evaluate_files(train: &str, tests: &[&str], labels: &str) -> (EvaluationReport, NgramModel, LogMatcher, usize) {
+    let labels = load_anomaly_labels(labels).unwrap();
+    // Fragment-only matches would merge messages sharing the instance prefix
+    let matcher = LogMatcher::with_config(MatcherConfig::new().with_regex_verification(true));
+    let mut next_template_id = 1_000;
+
+    let training = build_sessions(train, &matcher, &mut next_template_id);
+    let mut model = NgramModel::new(SequenceConfig::new()).unwrap();
+    model.train(training.iter().map(|s| s.template_ids.as_slice()));
+
+    let test_sessions: Vec<Session> = tests
+        .iter()
+        .flat_map(|path| build_sessions(path, &matcher, &mut next_template_id))
+        .collect();
+    let scores: Vec<(&str, bool)> = test_sessions
+        .iter()
+        .map(|s| (s.id.as_str(), model.score(&s.template_ids).anomalous))
+        .collect();
+    (evaluate(scores, &labels), model, matcher, training.len())
+}
+
+/// Match every line's content, generating a template for lines no template
+/// matches yet, and group the lines into instance sessions
+fn build_sessions(path: &str, matcher: &LogMatcher, next_template_id: &mut u64) -> Vec<Session> {
+    let format = HeaderFormat::loghub("OpenStack").unwrap();
+    let mut builder = SessionBuilder::new(SessionConfig::openstack_instance()).unwrap();
+
+    for raw in read_raw_lines(path).unwrap() {
+        let line = String::from_utf8_lossy(&raw);
+        let content = format.content(&line);
+
+        match matcher.match_and_extract(content) {
+            Some(matched) => {
+                builder.add_match(&matched, &line);
+            }
+            None => {
+                let template_id = *next_template_id;
+                *next_template_id += 1;
+                matcher.add_template(SmartTemplateGenerator::generate_template(content, template_id)).unwrap();
+                let variables = matcher.extract_variables(template_id, content).unwrap_or_default();
+                builder.add(template_id, &variables, &line);
+            }
+        }
+    }
+
+    builder.into_sessions()
+}
+
+#[test]
+fn test_openstack_labels() {
+    let labels = load_anomaly_labels(Path::new(DATA_DIR).join("anomaly_labels.txt")).unwrap();
+    assert_eq!(labels.len(), 4);
+    assert!(labels.contains("544fd51c-4edc-4780-baae-ba1d80a0acfc"));
+    assert!(labels.iter().all(|id| id.len() == 36 && id.matches('-').count() == 4));
+}
+
+#[test]
+fn test_fixture_session_anomalies() {
+    let path = |name: &str| format!("{}/{}", FIXTURE_DIR, name);
+    let (report, _, _, training) =
+        evaluate_files(&path("openstack_train.log"), &[&path("openstack_test.log")], &path("anomaly_labels.txt"));
+
+    assert_eq!(training, 8);
+    assert_eq!((report.sessions, report.labeled_anomalies), (6, 2));
+    assert!(report.missing_labels.is_empty(), "labeled instances without logs: {:?}", report.missing_labels);
+    assert!(report.precision >= 0.99, "precision {:.3} below floor: {:?}", report.precision, report);
+    assert!(report.recall >= 0.99, "recall {:.3} below floor: {:?}", report.recall, report);
+}
+
+#[test]
+#[ignore] // Requires openstack_normal1.log, openstack_normal2.log and openstack_abnormal.log in DATA_DIR
+fn test_openstack_session_anomalies() {
+    let path = |name: &str| format!("{}/{}", DATA_DIR, name);
+    let (train, normal, abnormal) = (path("openstack_normal1.log"), path("openstack_normal2.log"), path("openstack_abnormal.log"));
+    for file in [&train, &normal, &abnormal] {
+        assert!(Path::new(file).exists(), "{} not found; download the LogHub OpenStack logs into {}/", file, DATA_DIR);
+    }
+
+    let (report, model, matcher, training) = evaluate_files(&train, &[&normal, &abnormal], &path("anomaly_labels.txt"));
+
+    println!("\n📊 OpenStack session anomalies (n = {}, top-k = {})", model.config().n, model.config().top_k);
+    println!("   Templates:         {}", matcher.template_count());
+    println!("   Training sessions: {}", training);
+    println!("   Test sessions:     {} ({} labeled anomalous)", report.sessions, report.labeled_anomalies);
+    println!("   TP {}  FP {}  FN {}  TN {}", report.true_positives, report.false_positives, report.false_negatives, report.true_negatives);
+    println!("   Precision {:.3}  Recall {:.3}  F1 {:.3}", report.precision, report.recall, report.f1);
+
+    assert!(report.missing_labels.is_empty(), "labeled instances without logs: {:?}", report.missing_labels);
+}
@@ -76,7 +76,8 @@ fn test_config_affects_matching() {
         pattern: r"err: (\d+)".to_string(), // "err: " is only 4 chars
         variables: vec!["code".to_string()],
         example: "err: 404".to_string(),
-    });
+        ..Default::default()
+    }).unwrap();
 
     // This should NOT match because "err: " is too short
     let result = matcher.match_log("err: 404");
@@ -90,7 +91,8 @@ fn test_config_affects_matching() {
         pattern: r"err: (\d+)".to_string(),
         variables: vec!["code".to_string()],
         example: "err: 404".to_string(),
-    });
+        ..Default::default()
+    }).unwrap();
 
     let result_default = matcher_default.match_log("err: 404");
     println!("With min_fragment_length=2, 'err: 404' matches: {:?}", result_default);